@@ -4,7 +4,7 @@ extern crate rustcurrent;
 extern crate crossbeam;
 
 use criterion::{Bencher, Criterion};
-use rustcurrent::structures::Queue;
+use rustcurrent::structures::{Queue, FlatCombiningQueue, BoundedQueue, WaitFreeQueue};
 use std::collections::VecDeque;
 use crossbeam::sync::MsQueue;
 
@@ -48,6 +48,102 @@ fn bench_equal(num_threads: usize) {
     let queue = Arc::new(Queue::new());
     let mut wait_vec: Vec<JoinHandle<()>> = Vec::new();
 
+    for _ in 0..num_threads / 2 {
+        let queue_clone = queue.clone();
+        wait_vec.push(thread::spawn(move || {
+            for i in 0..10000 / num_threads {
+                queue_clone.enqueue(i).unwrap();
+            }
+        }));
+    }
+
+    for _ in 0..num_threads / 2 {
+        let queue_clone = queue.clone();
+        wait_vec.push(thread::spawn(move || {
+            for i in 0..10000 / num_threads {
+                loop {
+                    match queue_clone.dequeue() {
+                        Some(_) => {break;},
+                        None => {}
+                    }
+                }
+            }
+        }))
+    }
+
+    for handle in wait_vec {
+        handle.join().unwrap();
+    }
+}
+
+fn bench_equal_fc(num_threads: usize) {
+    let queue = Arc::new(FlatCombiningQueue::new());
+    let mut wait_vec: Vec<JoinHandle<()>> = Vec::new();
+
+    for _ in 0..num_threads / 2 {
+        let queue_clone = queue.clone();
+        wait_vec.push(thread::spawn(move || {
+            for i in 0..10000 / num_threads {
+                queue_clone.enqueue(i);
+            }
+        }));
+    }
+
+    for _ in 0..num_threads / 2 {
+        let queue_clone = queue.clone();
+        wait_vec.push(thread::spawn(move || {
+            for i in 0..10000 / num_threads {
+                loop {
+                    match queue_clone.dequeue() {
+                        Some(_) => {break;},
+                        None => {}
+                    }
+                }
+            }
+        }))
+    }
+
+    for handle in wait_vec {
+        handle.join().unwrap();
+    }
+}
+
+fn bench_equal_bounded(num_threads: usize) {
+    let queue = Arc::new(BoundedQueue::new(1024));
+    let mut wait_vec: Vec<JoinHandle<()>> = Vec::new();
+
+    for _ in 0..num_threads / 2 {
+        let queue_clone = queue.clone();
+        wait_vec.push(thread::spawn(move || {
+            for i in 0..10000 / num_threads {
+                while queue_clone.try_enqueue(i).is_err() {}
+            }
+        }));
+    }
+
+    for _ in 0..num_threads / 2 {
+        let queue_clone = queue.clone();
+        wait_vec.push(thread::spawn(move || {
+            for i in 0..10000 / num_threads {
+                loop {
+                    match queue_clone.dequeue() {
+                        Some(_) => {break;},
+                        None => {}
+                    }
+                }
+            }
+        }))
+    }
+
+    for handle in wait_vec {
+        handle.join().unwrap();
+    }
+}
+
+fn bench_equal_wait_free(num_threads: usize) {
+    let queue = Arc::new(WaitFreeQueue::new(num_threads));
+    let mut wait_vec: Vec<JoinHandle<()>> = Vec::new();
+
     for _ in 0..num_threads / 2 {
         let queue_clone = queue.clone();
         wait_vec.push(thread::spawn(move || {
@@ -160,6 +256,105 @@ fn bench_mp_sc(num_threads: usize) {
         }
     }));
 
+    for _ in 0..num_threads - 1 {
+        q = queue.clone();
+        wait_vec.push(thread::spawn(move || {
+            for i in 0..amount {
+                q.enqueue(i).unwrap();
+            }
+        }))
+    }
+
+    for handle in wait_vec {
+        handle.join().unwrap();
+    }
+}
+
+fn bench_mp_sc_fc(num_threads: usize) {
+    let queue = Arc::new(FlatCombiningQueue::new());
+    let mut wait_vec = Vec::new();
+
+    let amount = 10000 / num_threads;
+    let consumer_num = amount * (num_threads - 1);
+
+    let mut q = queue.clone();
+    wait_vec.push(thread::spawn(move || {
+        for _ in 0..consumer_num {
+            loop {
+                match q.dequeue() {
+                    Some(val) => break,
+                    None => {}
+                }
+            }
+        }
+    }));
+
+    for _ in 0..num_threads - 1 {
+        q = queue.clone();
+        wait_vec.push(thread::spawn(move || {
+            for i in 0..amount {
+                q.enqueue(i);
+            }
+        }))
+    }
+
+    for handle in wait_vec {
+        handle.join().unwrap();
+    }
+}
+
+fn bench_mp_sc_bounded(num_threads: usize) {
+    let queue = Arc::new(BoundedQueue::new(1024));
+    let mut wait_vec = Vec::new();
+
+    let amount = 10000 / num_threads;
+    let consumer_num = amount * (num_threads - 1);
+
+    let mut q = queue.clone();
+    wait_vec.push(thread::spawn(move || {
+        for _ in 0..consumer_num {
+            loop {
+                match q.dequeue() {
+                    Some(val) => break,
+                    None => {}
+                }
+            }
+        }
+    }));
+
+    for _ in 0..num_threads - 1 {
+        q = queue.clone();
+        wait_vec.push(thread::spawn(move || {
+            for i in 0..amount {
+                while q.try_enqueue(i).is_err() {}
+            }
+        }))
+    }
+
+    for handle in wait_vec {
+        handle.join().unwrap();
+    }
+}
+
+fn bench_mp_sc_wait_free(num_threads: usize) {
+    let queue = Arc::new(WaitFreeQueue::new(num_threads));
+    let mut wait_vec = Vec::new();
+
+    let amount = 10000 / num_threads;
+    let consumer_num = amount * (num_threads - 1);
+
+    let mut q = queue.clone();
+    wait_vec.push(thread::spawn(move || {
+        for _ in 0..consumer_num {
+            loop {
+                match q.dequeue() {
+                    Some(val) => break,
+                    None => {}
+                }
+            }
+        }
+    }));
+
     for _ in 0..num_threads - 1 {
         q = queue.clone();
         wait_vec.push(thread::spawn(move || {
@@ -250,7 +445,7 @@ fn bench_sp_mc(num_threads: usize) {
     let mut q = queue.clone();
     wait_vec.push(thread::spawn(move || {
         for i in 0..producer_num {
-            q.enqueue(i);
+            q.enqueue(i).unwrap();
         }
     }));
 
@@ -342,5 +537,31 @@ fn crossbeam_bench_sp_mc(c: &mut Criterion) {
     c.bench_function_over_inputs("crossbeam_queue_sp_mc", |b: &mut Bencher, num_threads: &usize| b.iter(|| bench_sp_mc_crossbeam(*num_threads)), (2..42).filter(|num| num % 2 == 0).collect::<Vec<usize>>());
 }
 
-criterion_group!(benches, bench_queue_equal_lock, bench_queue_equal, bench_queue_mp_sc_lock, bench_queue_mp_sc, bench_queue_sp_mc_lock, bench_queue_sp_mc);
+fn bench_queue_equal_fc(c: &mut Criterion) {
+    c.bench_function_over_inputs("fc_queue_equal", |b: &mut Bencher, num_threads: &usize| b.iter(|| bench_equal_fc(*num_threads)), (2..42).filter(|num| num % 2 == 0).collect::<Vec<usize>>());
+}
+
+fn bench_queue_mp_sc_fc(c: &mut Criterion) {
+    c.bench_function_over_inputs("fc_queue_mp_sc", |b: &mut Bencher, num_threads: &usize| b.iter(|| bench_mp_sc_fc(*num_threads)), (2..42).filter(|num| num % 2 == 0).collect::<Vec<usize>>());
+}
+
+fn bench_queue_equal_bounded(c: &mut Criterion) {
+    c.bench_function_over_inputs("bounded_queue_equal", |b: &mut Bencher, num_threads: &usize| b.iter(|| bench_equal_bounded(*num_threads)), (2..42).filter(|num| num % 2 == 0).collect::<Vec<usize>>());
+}
+
+fn bench_queue_mp_sc_bounded(c: &mut Criterion) {
+    c.bench_function_over_inputs("bounded_queue_mp_sc", |b: &mut Bencher, num_threads: &usize| b.iter(|| bench_mp_sc_bounded(*num_threads)), (2..42).filter(|num| num % 2 == 0).collect::<Vec<usize>>());
+}
+
+fn bench_queue_equal_wait_free(c: &mut Criterion) {
+    c.bench_function_over_inputs("wait_free_queue_equal", |b: &mut Bencher, num_threads: &usize| b.iter(|| bench_equal_wait_free(*num_threads)), (2..42).filter(|num| num % 2 == 0).collect::<Vec<usize>>());
+}
+
+fn bench_queue_mp_sc_wait_free(c: &mut Criterion) {
+    c.bench_function_over_inputs("wait_free_queue_mp_sc", |b: &mut Bencher, num_threads: &usize| b.iter(|| bench_mp_sc_wait_free(*num_threads)), (2..42).filter(|num| num % 2 == 0).collect::<Vec<usize>>());
+}
+
+criterion_group!(benches, bench_queue_equal_lock, bench_queue_equal, bench_queue_mp_sc_lock, bench_queue_mp_sc, bench_queue_sp_mc_lock, bench_queue_sp_mc,
+                          bench_queue_equal_fc, bench_queue_mp_sc_fc, bench_queue_equal_bounded, bench_queue_mp_sc_bounded,
+                          bench_queue_equal_wait_free, bench_queue_mp_sc_wait_free, crossbeam_bench_equal, crossbeam_bench_mp_sc);
 criterion_main!(benches);
\ No newline at end of file
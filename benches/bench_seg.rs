@@ -50,7 +50,7 @@ fn bench_equal(num_threads: usize) {
         let queue_clone = queue.clone();
         wait_vec.push(thread::spawn(move || {
             for i in 0..10000 / num_threads {
-                queue_clone.enqueue(i);
+                queue_clone.enqueue(i).unwrap();
             }
         }));
     }
@@ -130,7 +130,7 @@ fn bench_mp_sc(num_threads: usize) {
         q = queue.clone();
         wait_vec.push(thread::spawn(move || {
             for i in 0..amount {
-                q.enqueue(i);
+                q.enqueue(i).unwrap();
             }
         }))
     }
@@ -183,7 +183,7 @@ fn bench_sp_mc(num_threads: usize) {
     let mut q = queue.clone();
     wait_vec.push(thread::spawn(move || {
         for i in 0..producer_num {
-            q.enqueue(i);
+            q.enqueue(i).unwrap();
         }
     }));
 
@@ -4,7 +4,7 @@ extern crate rustcurrent;
 extern crate crossbeam;
 
 use criterion::{Bencher, Criterion};
-use rustcurrent::structures::Stack;
+use rustcurrent::structures::{Stack, VersionedStack, FlatCombiningStack};
 use crossbeam::sync::TreiberStack;
 use std::thread;
 use std::sync::{Arc, Mutex};
@@ -68,6 +68,64 @@ fn bench_crossbeam_stack(num_threads: usize, elim: bool) {
     }
 }
 
+fn bench_versioned_stack(num_threads: usize) {
+    let stack = Arc::new(VersionedStack::new());
+    let mut wait_vec: Vec<JoinHandle<()>> = Vec::new();
+
+    for _ in 0..num_threads {
+        let mut s = stack.clone();
+        wait_vec.push(thread::spawn(move || {
+            for n in 0..10000 / num_threads {
+                s.push(n);
+            }
+        }));
+        s = stack.clone();
+        wait_vec.push(thread::spawn(move || {
+            for n in 0..10000 / num_threads {
+                loop {
+                    match s.pop() {
+                        Some(v) => {break;}
+                        None => {}
+                    }
+                }
+            }
+        }));
+    }
+
+    for handle in wait_vec {
+        handle.join().unwrap();
+    }
+}
+
+fn bench_fc_stack(num_threads: usize) {
+    let stack = Arc::new(FlatCombiningStack::new());
+    let mut wait_vec: Vec<JoinHandle<()>> = Vec::new();
+
+    for _ in 0..num_threads {
+        let mut s = stack.clone();
+        wait_vec.push(thread::spawn(move || {
+            for n in 0..10000 / num_threads {
+                s.push(n);
+            }
+        }));
+        s = stack.clone();
+        wait_vec.push(thread::spawn(move || {
+            for n in 0..10000 / num_threads {
+                loop {
+                    match s.pop() {
+                        Some(v) => {break;}
+                        None => {}
+                    }
+                }
+            }
+        }));
+    }
+
+    for handle in wait_vec {
+        handle.join().unwrap();
+    }
+}
+
 fn bench_locked_stack(num_threads: usize) {
     let stack: Arc<Mutex<Vec<usize>>> = Arc::new(Mutex::new(Vec::new()));
     let mut wait_vec: Vec<JoinHandle<()>> = Vec::new();
@@ -163,6 +221,72 @@ fn bench_mp_sc_crossbeam(num_threads: usize, elim: bool) {
     }
 }
 
+fn bench_mp_sc_versioned(num_threads: usize) {
+    let stack = Arc::new(VersionedStack::new());
+    let mut wait_vec = Vec::new();
+
+    let amount = 10000 / num_threads;
+    let consumer_num = amount * (num_threads - 1);
+
+    let mut s = stack.clone();
+    wait_vec.push(thread::spawn(move || {
+        for i in 0..consumer_num {
+            loop {
+                match s.pop() {
+                    Some(v) => break,
+                    None => {}
+                }
+            }
+        }
+    }));
+
+    for _ in 0..(num_threads - 1) {
+        s = stack.clone();
+        wait_vec.push(thread::spawn(move || {
+            for i in 0..amount {
+                s.push(i);
+            }
+        }))
+    }
+
+    for handle in wait_vec {
+        handle.join().unwrap();
+    }
+}
+
+fn bench_mp_sc_fc(num_threads: usize) {
+    let stack = Arc::new(FlatCombiningStack::new());
+    let mut wait_vec = Vec::new();
+
+    let amount = 10000 / num_threads;
+    let consumer_num = amount * (num_threads - 1);
+
+    let mut s = stack.clone();
+    wait_vec.push(thread::spawn(move || {
+        for i in 0..consumer_num {
+            loop {
+                match s.pop() {
+                    Some(v) => break,
+                    None => {}
+                }
+            }
+        }
+    }));
+
+    for _ in 0..(num_threads - 1) {
+        s = stack.clone();
+        wait_vec.push(thread::spawn(move || {
+            for i in 0..amount {
+                s.push(i);
+            }
+        }))
+    }
+
+    for handle in wait_vec {
+        handle.join().unwrap();
+    }
+}
+
 fn bench_mp_sc_lock(num_threads: usize) {
     let stack = Arc::new(Mutex::new(Vec::new()));
     let mut wait_vec = Vec::new();
@@ -343,6 +467,23 @@ fn bench_crossbeam_sp_mc(c: &mut Criterion) {
     c.bench_function_over_inputs("cross_stack_sp_mc", |b: &mut Bencher, num_threads: &usize| b.iter(|| bench_sp_mc_crossbeam(*num_threads, true)), (2..42).filter(|num| num % 2 == 0).collect::<Vec<usize>>());
 }
 
+fn bench_versioned_equal(c: &mut Criterion) {
+    c.bench_function_over_inputs("stack_equal_versioned", |b: &mut Bencher, num_threads: &usize| b.iter(|| bench_versioned_stack(*num_threads)), (2..42).filter(|num| num % 2 == 0).collect::<Vec<usize>>());
+}
+
+fn bench_versioned_mp_sc(c: &mut Criterion) {
+    c.bench_function_over_inputs("stack_mp_sc_versioned", |b: &mut Bencher, num_threads: &usize| b.iter(|| bench_mp_sc_versioned(*num_threads)), (2..42).filter(|num| num % 2 == 0).collect::<Vec<usize>>());
+}
+
+fn bench_fc_equal(c: &mut Criterion) {
+    c.bench_function_over_inputs("stack_equal_fc", |b: &mut Bencher, num_threads: &usize| b.iter(|| bench_fc_stack(*num_threads)), (2..42).filter(|num| num % 2 == 0).collect::<Vec<usize>>());
+}
+
+fn bench_fc_mp_sc(c: &mut Criterion) {
+    c.bench_function_over_inputs("stack_mp_sc_fc", |b: &mut Bencher, num_threads: &usize| b.iter(|| bench_mp_sc_fc(*num_threads)), (2..42).filter(|num| num % 2 == 0).collect::<Vec<usize>>());
+}
+
 criterion_group!(benches, bench_lock_equal, bench_elim_equal, bench_no_elim_equal, bench_lock_mp_sc, bench_elim_mp_sc, bench_no_elim_mp_sc,
-                          bench_lock_sp_mc, bench_elim_sp_mc, bench_no_elim_sp_mc);
+                          bench_lock_sp_mc, bench_elim_sp_mc, bench_no_elim_sp_mc, bench_versioned_equal, bench_versioned_mp_sc,
+                          bench_fc_equal, bench_fc_mp_sc);
 criterion_main!(benches);
\ No newline at end of file
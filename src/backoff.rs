@@ -0,0 +1,138 @@
+//! Backoff strategies for retrying operations under contention.
+//!
+//! When a lock-free operation fails because another thread changed the structure first, retrying
+//! immediately tends to fail again, as every thread hammers the same location. Waiting a little between
+//! attempts spreads the threads out. How long to wait, and how, depends on the workload and on how many
+//! threads share each core, so the structures in this crate which retry can be constructed with any
+//! `Backoff` strategy.
+//! # Example
+//! ```
+//! let queue: Queue<u8> = Queue::new_with_backoff(YieldBackoff);
+//! let stack: Stack<u8> = Stack::new_with_backoff(false, ExponentialSpin::new(10));
+//! ```
+
+use std::hint;
+use std::thread;
+use std::time::Duration;
+use std::cmp;
+use std::fmt::Debug;
+
+/// A strategy for waiting between attempts at a contended operation.
+///
+/// The strategy is shared by every thread using the structure, so it is told how many times the current
+/// operation has already failed rather than keeping state of its own.
+pub trait Backoff: Send + Sync + Debug {
+    /// Wait after the operation has failed `failures` times in a row. `failures` starts at 1.
+    fn backoff(&self, failures: u32);
+}
+
+/// Spin for a number of iterations which doubles with every failure, up to `2^max_exponent`.
+///
+/// This keeps the thread on its core, which suits structures where every thread has a core to itself.
+#[derive(Debug, Clone, Copy)]
+pub struct ExponentialSpin {
+    max_exponent: u32
+}
+
+impl ExponentialSpin {
+    /// Create a new strategy spinning for at most `2^max_exponent` iterations.
+    /// # Examples
+    /// ```
+    /// let backoff = ExponentialSpin::new(10); // Spin for at most 1024 iterations
+    /// ```
+    pub fn new(max_exponent: u32) -> Self {
+        ExponentialSpin {
+            max_exponent: cmp::min(max_exponent, 31)
+        }
+    }
+}
+
+impl Default for ExponentialSpin {
+    fn default() -> Self {
+        ExponentialSpin::new(10)
+    }
+}
+
+impl Backoff for ExponentialSpin {
+    fn backoff(&self, failures: u32) {
+        let exponent = cmp::min(failures.saturating_sub(1), self.max_exponent);
+        for _ in 0..(1u32 << exponent) {
+            hint::spin_loop();
+        }
+    }
+}
+
+/// Yield the thread to the scheduler after every failure.
+///
+/// This suits machines running more threads than cores, where the thread in the way may be waiting for this one's core.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct YieldBackoff;
+
+impl Backoff for YieldBackoff {
+    fn backoff(&self, _failures: u32) {
+        thread::yield_now();
+    }
+}
+
+/// Sleep for a duration which doubles with every failure, from `base` up to `max`.
+///
+/// Sleeping costs a system call, so this only suits structures which are contended for long stretches.
+#[derive(Debug, Clone, Copy)]
+pub struct SleepBackoff {
+    base: Duration,
+    max: Duration
+}
+
+impl SleepBackoff {
+    /// Create a new strategy sleeping for between `base` and `max`.
+    /// # Examples
+    /// ```
+    /// let backoff = SleepBackoff::new(Duration::new(0, 10), Duration::new(0, 20480));
+    /// ```
+    pub fn new(base: Duration, max: Duration) -> Self {
+        SleepBackoff {
+            base,
+            max
+        }
+    }
+}
+
+impl Backoff for SleepBackoff {
+    fn backoff(&self, failures: u32) {
+        let exponent = cmp::min(failures.saturating_sub(1), 31);
+        let duration = self.base.checked_mul(1 << exponent).unwrap_or(self.max);
+        thread::sleep(cmp::min(duration, self.max));
+    }
+}
+
+/// Retry immediately.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoBackoff;
+
+impl Backoff for NoBackoff {
+    #[inline]
+    fn backoff(&self, _failures: u32) {}
+}
+
+mod tests {
+    #![allow(unused_imports)]
+    use super::{Backoff, ExponentialSpin, SleepBackoff, YieldBackoff, NoBackoff};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_strategies() {
+        let strategies: Vec<Box<dyn Backoff>> = vec![Box::new(ExponentialSpin::new(4)), Box::new(YieldBackoff),
+                                                     Box::new(SleepBackoff::new(Duration::new(0, 10), Duration::new(0, 1000))),
+                                                     Box::new(NoBackoff)];
+        for strategy in strategies {
+            for failures in 1..100 {
+                strategy.backoff(failures);
+            }
+        }
+
+        // The sleep is capped at the maximum, even after many failures
+        let start = Instant::now();
+        SleepBackoff::new(Duration::from_millis(1), Duration::from_millis(2)).backoff(u32::max_value());
+        assert!(start.elapsed() < Duration::from_millis(500));
+    }
+}
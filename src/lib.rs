@@ -9,10 +9,14 @@ extern crate time;
 extern crate rand;
 extern crate thread_local;
 extern crate rayon;
+#[cfg(feature = "futures")]
+extern crate futures;
 
 pub mod structures;
 pub mod memory;
 pub mod testing;
+pub mod stats;
+pub mod backoff;
 
 mod tests {
    
@@ -0,0 +1,256 @@
+use std::sync::Arc;
+use std::sync::atomic::AtomicPtr;
+use std::sync::atomic::Ordering::{Acquire, AcqRel, Relaxed};
+use std::fmt::Debug;
+use std::fmt;
+use std::ptr;
+use super::{HPBRManager, HPHandle};
+
+/// A lock-free atomic cell holding an `Arc`.
+///
+/// Readers can obtain the current value either as a cloned `Arc`, or as an `ArcGuard` which
+/// borrows the value without touching the reference count. Writers can replace the value with
+/// `store`, `swap` or `compare_and_swap`.
+///
+/// Each `Arc` is stored in its own heap allocation, and replaced allocations are retired through an
+/// HPBRManager. A reader protects the allocation with a hazard pointer before cloning the `Arc` out
+/// of it, so the old reference is only released once no thread can still be reading it. This makes
+/// the hazard pointer machinery usable for reference-counted data, such as a shared configuration
+/// that is read by many threads and swapped occasionally.
+/// # Usage
+/// ```
+/// let config: Arc<AtomicArc<String>> = Arc::new(AtomicArc::new(Arc::new("v1".to_owned())));
+/// let config_clone = config.clone();
+/// thread::spawn(move || {
+///     config_clone.store(Arc::new("v2".to_owned()));
+/// });
+/// println!("{}", config.load());
+/// ```
+pub struct AtomicArc<T: Send + Sync> {
+    ptr: AtomicPtr<Arc<T>>,
+    manager: HPBRManager<Arc<T>>
+}
+
+impl<T: Send + Sync> AtomicArc<T> {
+    /// Create a new AtomicArc holding the given value.
+    /// # Examples
+    /// ```
+    /// let cell: AtomicArc<u8> = AtomicArc::new(Arc::new(8));
+    /// ```
+    pub fn new(value: Arc<T>) -> Self {
+        AtomicArc {
+            ptr: AtomicPtr::new(Box::into_raw(Box::new(value))),
+            manager: HPBRManager::new(100, 1)
+        }
+    }
+
+    /// Load a clone of the `Arc` currently held in the cell.
+    /// # Examples
+    /// ```
+    /// let cell: AtomicArc<u8> = AtomicArc::new(Arc::new(8));
+    /// assert_eq!(*cell.load(), 8);
+    /// ```
+    pub fn load(&self) -> Arc<T> {
+        let current = self.protect_current();
+        let value = unsafe { (*current).clone() };
+        self.manager.unprotect(0);
+        value
+    }
+
+    /// Load a reference to the value currently held in the cell, protected by an ArcGuard.
+    /// The reference count is not changed, and the value is guaranteed to live for as long
+    /// as the guard.
+    /// # Examples
+    /// ```
+    /// let cell: AtomicArc<u8> = AtomicArc::new(Arc::new(8));
+    /// let guard = cell.load_guard();
+    /// assert_eq!(guard.data(), &8);
+    /// ```
+    pub fn load_guard(&self) -> ArcGuard<'_, T> {
+        let current = self.protect_current();
+        let handle = self.manager.protect_dynamic(current);
+        self.manager.unprotect(0);
+        ArcGuard::new(unsafe { &*current }, handle)
+    }
+
+    /// Store a new value in the cell. The old value is released once no thread is reading it.
+    /// # Examples
+    /// ```
+    /// let cell: AtomicArc<u8> = AtomicArc::new(Arc::new(8));
+    /// cell.store(Arc::new(9));
+    /// assert_eq!(*cell.load(), 9);
+    /// ```
+    pub fn store(&self, value: Arc<T>) {
+        let new_ptr = Box::into_raw(Box::new(value));
+        let old_ptr = self.ptr.swap(new_ptr, AcqRel);
+        self.manager.retire(old_ptr, 0);
+    }
+
+    /// Store a new value in the cell, returning the old one.
+    /// # Examples
+    /// ```
+    /// let cell: AtomicArc<u8> = AtomicArc::new(Arc::new(8));
+    /// assert_eq!(*cell.swap(Arc::new(9)), 8);
+    /// ```
+    pub fn swap(&self, value: Arc<T>) -> Arc<T> {
+        let new_ptr = Box::into_raw(Box::new(value));
+        let old_ptr = self.ptr.swap(new_ptr, AcqRel);
+        // Nobody else can retire the old allocation now that it has been swapped out
+        let old = unsafe { (*old_ptr).clone() };
+        self.manager.retire(old_ptr, 0);
+        old
+    }
+
+    /// Replace the value in the cell with `new` if the cell still holds `current`, where
+    /// `Arc`s are compared by pointer rather than by value.
+    /// # Errors
+    /// If the cell does not hold `current`, `new` is returned inside an Err.
+    /// # Examples
+    /// ```
+    /// let cell: AtomicArc<u8> = AtomicArc::new(Arc::new(8));
+    /// let current = cell.load();
+    /// assert_eq!(cell.compare_and_swap(&current, Arc::new(9)).map(|old| *old), Ok(8));
+    /// assert!(cell.compare_and_swap(&current, Arc::new(10)).is_err());
+    /// ```
+    pub fn compare_and_swap(&self, current: &Arc<T>, mut new: Arc<T>) -> Result<Arc<T>, Arc<T>> {
+        loop {
+            let old_ptr = self.protect_current();
+            if !Arc::ptr_eq(unsafe { &*old_ptr }, current) {
+                self.manager.unprotect(0);
+                return Err(new)
+            }
+
+            let new_ptr = Box::into_raw(Box::new(new));
+            match self.ptr.compare_exchange(old_ptr, new_ptr, AcqRel, Relaxed) {
+                Ok(_) => {
+                    let old = unsafe { (*old_ptr).clone() };
+                    self.manager.retire(old_ptr, 0);
+                    return Ok(old)
+                },
+                Err(_) => {
+                    // The allocation changed, but it may still hold the same Arc, so try again
+                    new = unsafe { *Box::from_raw(new_ptr) };
+                }
+            }
+        }
+    }
+
+    /// Protect the current allocation in the first hazard pointer, making sure it has
+    /// not been replaced before it was protected.
+    fn protect_current(&self) -> *mut Arc<T> {
+        let mut current = self.ptr.load(Acquire);
+        self.manager.protect(current, 0);
+        while !ptr::eq(current, self.ptr.load(Acquire)) {
+            current = self.ptr.load(Acquire);
+            self.manager.protect(current, 0);
+        }
+        current
+    }
+}
+
+impl<T: Send + Sync + Debug> Debug for AtomicArc<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "AtomicArc({:?})", self.load())
+    }
+}
+
+impl<T: Send + Sync> Drop for AtomicArc<T> {
+    fn drop(&mut self) {
+        let current = self.ptr.load(Relaxed);
+        unsafe { drop(Box::from_raw(current)) };
+    }
+}
+
+/// A borrow of the value held in an AtomicArc, protected by a HPHandle. When the guard goes out
+/// of scope, the value is unprotected and may be released.
+pub struct ArcGuard<'a, T: Send + Sync + 'a> {
+    data: &'a Arc<T>,
+    handle: HPHandle<'a, Arc<T>>
+}
+
+impl<'a, T: Send + Sync + 'a> ArcGuard<'a, T> {
+    fn new(data: &'a Arc<T>, handle: HPHandle<'a, Arc<T>>) -> Self {
+        ArcGuard {
+            data,
+            handle
+        }
+    }
+
+    /// Access the data inside the guard.
+    /// # Example
+    /// ```
+    /// let guard = cell.load_guard();
+    /// println!("{}", guard.data());
+    /// ```
+    pub fn data(&self) -> &T {
+        self.data
+    }
+
+    /// Consume the guard to obtain a clone of the protected `Arc`.
+    /// # Example
+    /// ```
+    /// let guard = cell.load_guard();
+    /// let arc = guard.cloned(); // The allocation is unprotected
+    /// ```
+    pub fn cloned(self) -> Arc<T> {
+        self.data.clone()
+    }
+}
+
+impl<'a, T: Debug + Send + Sync + 'a> Debug for ArcGuard<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ArcGuard({:?})", self.data)
+    }
+}
+
+mod tests {
+    #![allow(unused_imports)]
+    use super::AtomicArc;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_single_threaded() {
+        let first = Arc::new(1);
+        let cell: AtomicArc<u32> = AtomicArc::new(first.clone());
+        assert_eq!(*cell.load(), 1);
+        {
+            let guard = cell.load_guard();
+            assert_eq!(guard.data(), &1);
+            assert_eq!(Arc::strong_count(&first), 2);
+        }
+
+        assert_eq!(*cell.swap(Arc::new(2)), 1);
+        assert!(cell.compare_and_swap(&first, Arc::new(3)).is_err());
+        let current = cell.load();
+        assert_eq!(cell.compare_and_swap(&current, Arc::new(3)).map(|old| *old), Ok(2));
+        cell.store(Arc::new(4));
+        assert_eq!(*cell.load_guard().cloned(), 4);
+    }
+
+    #[test]
+    fn test_all_released() {
+        let values: Vec<Arc<usize>> = (0..1000).map(|i| Arc::new(i)).collect();
+        {
+            let cell: Arc<AtomicArc<usize>> = Arc::new(AtomicArc::new(values[0].clone()));
+            let mut waitvec: Vec<thread::JoinHandle<()>> = Vec::new();
+            for thread_no in 0..10 {
+                let cell_copy = cell.clone();
+                let values_copy = values.clone();
+                waitvec.push(thread::spawn(move || {
+                    for i in 0..100 {
+                        cell_copy.store(values_copy[thread_no * 100 + i].clone());
+                        let guard = cell_copy.load_guard();
+                        assert!(*guard.data() < 1000);
+                    }
+                }));
+            }
+            for handle in waitvec {
+                handle.join().unwrap();
+            }
+        }
+        for value in &values {
+            assert_eq!(Arc::strong_count(value), 1);
+        }
+    }
+}
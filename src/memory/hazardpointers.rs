@@ -1,12 +1,21 @@
 #![allow(unused_assignments)]
 use std::sync::atomic::{AtomicPtr, Ordering, AtomicBool};
+use std::hint;
 use std::fmt::Debug;
+use std::thread;
 use thread_local::CachedThreadLocal;
 use std::collections::{VecDeque, HashSet};
 use std::cell::UnsafeCell;
 use std::fmt;
 use std::ptr;
 use std::mem;
+use stats::Counters;
+#[cfg(feature = "stats")]
+use stats::Stats;
+
+/// The most collections a thread makes while waiting for its free list to fall under the hard limit,
+/// before it gives up and carries on over the limit.
+const MAX_BACKPRESSURE_SCANS: usize = 1_000_000;
 
 /// A Hazard Pointer based memory manager for use in lock-free data structures.
 ///
@@ -43,13 +52,48 @@ use std::mem;
 /// optimisations provided by the `thread_local` crate ensure that a thread's access to its own
 /// hazard pointers is of the order of nanoseconds, so there should be no performance hit. 
 ///
+/// If a thread holds on to a hazard pointer for a long time, the records it protects can never be
+/// freed, and the free lists of the other threads keep growing. A hard limit on the size of a free
+/// list can be set with `new_with_backpressure`, along with a `BackpressurePolicy` deciding what a
+/// thread does once its free list is still over the limit after a collection.
+///
 /// Records are freed by reclaiming `Box` ownership, so the manager should be used with raw pointers
 /// created through the `Box::into_raw()` function.
 pub struct HPBRManager<T: Send> {
     thread_info: CachedThreadLocal<UnsafeCell<ThreadLocalInfo<T>>>,
     head: AtomicPtr<HazardPointer<T>>,
     max_retired: usize,
-    num_hp_per_thread: usize
+    num_hp_per_thread: usize,
+    hard_limit: usize,
+    policy: BackpressurePolicy,
+    stats: Counters
+}
+
+/// The action taken by a thread whose free list is still at or above the hard limit of an
+/// HPBRManager after garbage collection.
+pub enum BackpressurePolicy {
+    /// Keep scanning the hazard pointers until enough records have been freed to bring the free
+    /// list back under the limit. The retiring thread busy-waits on the threads holding the
+    /// records, so it is no longer lock-free while over the limit. It stops waiting and carries on
+    /// if its own hazard pointers protect enough records to keep it over the limit, as waiting could
+    /// then never succeed, or after a bounded number of scans, in case the threads holding the records
+    /// are themselves waiting on it.
+    HelpScan,
+    /// Like `HelpScan`, but yield the thread to the scheduler between scans.
+    Yield,
+    /// Report the size of the free list to the given callback and carry on. Nothing is
+    /// blocked, so memory use is only made visible, not bounded.
+    Report(Box<dyn Fn(usize) + Send + Sync>)
+}
+
+impl Debug for BackpressurePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BackpressurePolicy::HelpScan => write!(f, "HelpScan"),
+            BackpressurePolicy::Yield => write!(f, "Yield"),
+            BackpressurePolicy::Report(_) => write!(f, "Report")
+        }
+    }
 }
 
 impl<'a, T: Send + Debug + 'a> Debug for HPBRManager<T> {
@@ -66,7 +110,8 @@ impl<'a, T: Send + Debug + 'a> Debug for HPBRManager<T> {
             }
         };
 
-        write!(f, "HPBRManager(\n\tthread_info: {:?}, \n\thead: {:?}, \n\tmax_retired: {:?}", &thread_info_string, self.head, self.max_retired)
+        write!(f, "HPBRManager(\n\tthread_info: {:?}, \n\thead: {:?}, \n\tmax_retired: {:?}, \n\thard_limit: {:?}, \n\tpolicy: {:?}", 
+               &thread_info_string, self.head, self.max_retired, self.hard_limit, self.policy)
     }
 }
 
@@ -82,7 +127,35 @@ impl<'a, T: Send> HPBRManager<T> {
             thread_info: CachedThreadLocal::new(),
             head: AtomicPtr::default(),
             max_retired,
-            num_hp_per_thread
+            num_hp_per_thread,
+            hard_limit: usize::max_value(),
+            policy: BackpressurePolicy::HelpScan,
+            stats: Counters::new()
+        }
+    }
+
+    /// Create a new HPBRManager with a hard limit on the size of each thread's free list, and the
+    /// policy to apply when a collection fails to bring the free list back under that limit.
+    /// Panics if the hard limit is smaller than `max_retired`, as every collection would leave the
+    /// free list over it.
+    /// # Examples
+    /// ```
+    /// let manager: HPBRManager<u8> = HPBRManager::new_with_backpressure(100, 1, 10000, 
+    ///     BackpressurePolicy::Report(Box::new(|size| println!("free list has grown to {}", size))));
+    /// ```
+    pub fn new_with_backpressure(max_retired: usize, num_hp_per_thread: usize, hard_limit: usize, 
+                                 policy: BackpressurePolicy) -> Self {
+        if hard_limit < max_retired {
+            panic!("hard_limit must be at least max_retired!")
+        }
+        HPBRManager {
+            thread_info: CachedThreadLocal::new(),
+            head: AtomicPtr::default(),
+            max_retired,
+            num_hp_per_thread,
+            hard_limit,
+            policy,
+            stats: Counters::new()
         }
     }
 
@@ -128,13 +201,71 @@ impl<'a, T: Send> HPBRManager<T> {
             thread_info_mut.get_mut_hazard_pointer(hazard_num).unprotect();
             thread_info_mut.retired_list.push_back(record);
             thread_info_mut.retired_number += 1;
+            self.stats.retire();
 
             if thread_info_mut.retired_number > self.max_retired {
                 self.scan();
+                if thread_info_mut.retired_number >= self.hard_limit {
+                    self.apply_backpressure();
+                }
+            }
+        }
+    }
+
+    /// Apply the backpressure policy for a thread whose free list is over the hard limit.
+    fn apply_backpressure(&self) {
+        let wait: fn() = match self.policy {
+            BackpressurePolicy::HelpScan => hint::spin_loop,
+            BackpressurePolicy::Yield => thread::yield_now,
+            BackpressurePolicy::Report(ref callback) => {
+                callback(self.retired_len());
+                return
             }
+        };
+        for _ in 0..MAX_BACKPRESSURE_SCANS {
+            if self.retired_len() < self.hard_limit || self.blocked_by_own_hazards() {
+                return
+            }
+            wait();
+            self.scan();
+        }
+    }
+
+    /// Check whether this thread's own hazard pointers protect enough of its free list to keep it at
+    /// or above the hard limit, in which case waiting on the other threads can never bring it under.
+    fn blocked_by_own_hazards(&self) -> bool {
+        unsafe {
+            let thread_info = self.get_mut_thread_info();
+            let own_hazards: HashSet<*mut T> = thread_info.local_hazards.iter()
+                .map(|&hp| (*hp).protected.load(Ordering::Relaxed))
+                .filter(|ptr| !ptr.is_null())
+                .collect();
+            let blocked = thread_info.retired_list.iter().filter(|ptr| own_hazards.contains(ptr)).count();
+            blocked >= self.hard_limit
         }
     }
 
+    /// Get the number of records currently waiting in this thread's free list.
+    /// # Examples
+    /// ```
+    /// let manager: HBPRManager<u8> = HPBRManager::new(100, 1);
+    /// assert_eq!(manager.retired_len(), 0);
+    /// ```
+    pub fn retired_len(&self) -> usize {
+        unsafe { self.get_mut_thread_info().retired_number }
+    }
+
+    /// Get a snapshot of the number of records retired to and freed by this manager.
+    /// # Examples
+    /// ```
+    /// let manager: HBPRManager<u8> = HPBRManager::new(100, 1);
+    /// assert_eq!(manager.stats().retired, 0);
+    /// ```
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> Stats {
+        self.stats.snapshot()
+    }
+
     /// Protect the given record with in the given hazard pointer. The caller should always check after protection
     /// that the proteced record has not changed before operating on it, to make sure the protected record has not
     /// already been removed and possibly freed.
@@ -191,12 +322,13 @@ impl<'a, T: Send> HPBRManager<T> {
             let thread_info_mut = self.get_mut_thread_info();
             for i in thread_info_mut.starting_hazards_num..thread_info_mut.local_hazards.len() {
                 let hp = thread_info_mut.get_mut_hazard_pointer(i);
-                if !hp.protected.load(Ordering::Relaxed).is_null() {
+                if hp.protected.load(Ordering::Relaxed).is_null() {
                     hp.protect(record);
                     return HPHandle::new(i, self)
                 }
             }
             let new_hp = self.allocate_hp();
+            (*new_hp).protect(record);
             let new_hp_index = thread_info_mut.add_dynamic_hazard_pointer(new_hp);
             HPHandle::new(new_hp_index, self)
         }
@@ -281,6 +413,7 @@ impl<'a, T: Send> HPBRManager<T> {
                     Self::free(ptr);
                 }
             }
+            self.stats.free(thread_info.retired_number - new_retired_list.len());
             thread_info.retired_number = new_retired_list.len();
             thread_info.retired_list = Box::new(new_retired_list);
         }
@@ -438,7 +571,11 @@ impl<T: Send> Drop for ThreadLocalInfo<T> {
 
 mod tests {
     #![allow(unused_imports)]
-    use super::HPBRManager;
+    use super::{HPBRManager, BackpressurePolicy};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
 
     #[derive(Debug)]
     struct Foo {
@@ -467,4 +604,85 @@ mod tests {
         println!("{:?}", manager);
                 
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_backpressure_report() {
+        let reported = Arc::new(AtomicUsize::new(0));
+        let reported_clone = reported.clone();
+        let manager: HPBRManager<Foo> = HPBRManager::new_with_backpressure(0, 4, 2, 
+            BackpressurePolicy::Report(Box::new(move |size| reported_clone.store(size, Ordering::SeqCst))));
+        let pointers: Vec<*mut Foo> = (0..3).map(|i| Box::into_raw(Box::new(Foo {data: i}))).collect();
+        for (i, ptr) in pointers.iter().enumerate() {
+            manager.protect(*ptr, i + 1);
+        }
+
+        // The records stay protected by hazard pointers 1 to 3, so they cannot be freed
+        manager.retire(pointers[0], 0);
+        assert_eq!(reported.load(Ordering::SeqCst), 0);
+        manager.retire(pointers[1], 0);
+        assert_eq!(reported.load(Ordering::SeqCst), 2);
+        manager.retire(pointers[2], 0);
+        assert_eq!(reported.load(Ordering::SeqCst), 3);
+        assert_eq!(manager.retired_len(), 3);
+
+        for i in 1..4 {
+            manager.unprotect(i);
+        }
+        manager.retire(Box::into_raw(Box::new(Foo {data: 3})), 0);
+        assert_eq!(manager.retired_len(), 0);
+    }
+
+    /// Retire a record protected by another thread, which only unprotects it after a while, and check
+    /// that the retiring thread waits for it to be freed.
+    fn check_backpressure_waits(policy: BackpressurePolicy) {
+        let manager: Arc<HPBRManager<Foo>> = Arc::new(HPBRManager::new_with_backpressure(0, 1, 1, policy));
+        let ptr = Box::into_raw(Box::new(Foo {data: 1})) as usize;
+        let protected = Arc::new(AtomicBool::new(false));
+        let released = Arc::new(AtomicBool::new(false));
+        let (manager_clone, protected_clone, released_clone) = (manager.clone(), protected.clone(), released.clone());
+        let holder = thread::spawn(move || {
+            manager_clone.protect(ptr as *mut Foo, 0);
+            protected_clone.store(true, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(10));
+            released_clone.store(true, Ordering::SeqCst);
+            manager_clone.unprotect(0);
+        });
+        while !protected.load(Ordering::SeqCst) {
+            thread::yield_now();
+        }
+
+        manager.retire(ptr as *mut Foo, 0);
+        assert!(released.load(Ordering::SeqCst));
+        assert_eq!(manager.retired_len(), 0);
+        holder.join().unwrap();
+    }
+
+    #[test]
+    fn test_backpressure_help_scan() {
+        check_backpressure_waits(BackpressurePolicy::HelpScan);
+    }
+
+    #[test]
+    fn test_backpressure_yield() {
+        check_backpressure_waits(BackpressurePolicy::Yield);
+    }
+
+    #[test]
+    fn test_backpressure_own_hazards() {
+        // Records protected by the retiring thread itself can never be freed while it waits
+        for policy in vec![BackpressurePolicy::HelpScan, BackpressurePolicy::Yield] {
+            let manager: HPBRManager<Foo> = HPBRManager::new_with_backpressure(0, 2, 1, policy);
+            let ptr = Box::into_raw(Box::new(Foo {data: 1}));
+            manager.protect(ptr, 1);
+            manager.retire(ptr, 0);
+            assert_eq!(manager.retired_len(), 1);
+            manager.unprotect(1);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_hard_limit_below_max_retired() {
+        let _manager: HPBRManager<Foo> = HPBRManager::new_with_backpressure(10, 1, 5, BackpressurePolicy::HelpScan);
+    }
+}
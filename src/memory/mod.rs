@@ -7,4 +7,8 @@
 
 pub use self::hazardpointers::HPBRManager;
 pub use self::hazardpointers::HPHandle;
-mod hazardpointers;
\ No newline at end of file
+pub use self::hazardpointers::BackpressurePolicy;
+pub use self::atomic_arc::{AtomicArc, ArcGuard};
+
+mod hazardpointers;
+mod atomic_arc;
\ No newline at end of file
@@ -0,0 +1,183 @@
+//! Contention and reclamation statistics for the structures in this crate.
+//!
+//! When the `stats` feature is enabled, every structure keeps a set of counters recording
+//! how often its operations had to retry, back off or escalate, and how many records its
+//! HPBRManager retired and freed. The counters can be read at any time as a `Stats`
+//! snapshot through the structure's `stats` method, which helps explain drops in throughput.
+//!
+//! When the feature is disabled, the counters are zero-sized and every increment compiles
+//! away to nothing, so there is no cost in space or time.
+//! # Example
+//! ```
+//! let stack: Stack<u8> = Stack::new(true);
+//! stack.push(8);
+//! println!("{:?}", stack.stats()); // Only available with the `stats` feature
+//! ```
+
+#[cfg(feature = "stats")]
+use std::sync::atomic::AtomicUsize;
+#[cfg(feature = "stats")]
+use std::sync::atomic::Ordering::Relaxed;
+use std::ops::Add;
+
+/// A snapshot of the counters of a structure. Counters which do not apply to a structure are 0.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+    /// The number of CAS operations on the structure that failed and had to be retried.
+    pub cas_failures: usize,
+    /// The number of times a thread backed off after contention.
+    pub backoffs: usize,
+    /// The number of operations completed through an elimination layer.
+    pub eliminations: usize,
+    /// The number of times an operation exceeded its failure limit and forced the structure to expand.
+    pub escalations: usize,
+    /// The number of records retired to the memory manager.
+    pub retired: usize,
+    /// The number of retired records which have been freed.
+    pub freed: usize
+}
+
+impl Add for Stats {
+    type Output = Stats;
+
+    fn add(self, other: Stats) -> Stats {
+        Stats {
+            cas_failures: self.cas_failures + other.cas_failures,
+            backoffs: self.backoffs + other.backoffs,
+            eliminations: self.eliminations + other.eliminations,
+            escalations: self.escalations + other.escalations,
+            retired: self.retired + other.retired,
+            freed: self.freed + other.freed
+        }
+    }
+}
+
+/// The live counters kept inside a structure. These are zero-sized unless the `stats` feature is enabled.
+#[derive(Debug, Default)]
+pub(crate) struct Counters {
+    #[cfg(feature = "stats")]
+    cas_failures: AtomicUsize,
+    #[cfg(feature = "stats")]
+    backoffs: AtomicUsize,
+    #[cfg(feature = "stats")]
+    eliminations: AtomicUsize,
+    #[cfg(feature = "stats")]
+    escalations: AtomicUsize,
+    #[cfg(feature = "stats")]
+    retired: AtomicUsize,
+    #[cfg(feature = "stats")]
+    freed: AtomicUsize
+}
+
+impl Counters {
+    pub fn new() -> Self {
+        Counters::default()
+    }
+
+    #[inline]
+    pub fn cas_failure(&self) {
+        #[cfg(feature = "stats")]
+        self.cas_failures.fetch_add(1, Relaxed);
+    }
+
+    #[inline]
+    pub fn backoff(&self) {
+        #[cfg(feature = "stats")]
+        self.backoffs.fetch_add(1, Relaxed);
+    }
+
+    #[inline]
+    pub fn elimination(&self) {
+        #[cfg(feature = "stats")]
+        self.eliminations.fetch_add(1, Relaxed);
+    }
+
+    #[inline]
+    pub fn escalation(&self) {
+        #[cfg(feature = "stats")]
+        self.escalations.fetch_add(1, Relaxed);
+    }
+
+    #[inline]
+    pub fn retire(&self) {
+        #[cfg(feature = "stats")]
+        self.retired.fetch_add(1, Relaxed);
+    }
+
+    #[inline]
+    #[allow(unused_variables)]
+    pub fn free(&self, amount: usize) {
+        #[cfg(feature = "stats")]
+        self.freed.fetch_add(amount, Relaxed);
+    }
+
+    /// Read the current value of every counter. Each counter is read separately, so the
+    /// snapshot is not atomic with respect to concurrent operations.
+    #[cfg(feature = "stats")]
+    pub fn snapshot(&self) -> Stats {
+        Stats {
+            cas_failures: self.cas_failures.load(Relaxed),
+            backoffs: self.backoffs.load(Relaxed),
+            eliminations: self.eliminations.load(Relaxed),
+            escalations: self.escalations.load(Relaxed),
+            retired: self.retired.load(Relaxed),
+            freed: self.freed.load(Relaxed)
+        }
+    }
+}
+
+#[cfg(feature = "stats")]
+mod tests {
+    #![allow(unused_imports)]
+    use super::{Counters, Stats};
+    use structures::{Stack, Queue};
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_counters() {
+        let counters = Counters::new();
+        counters.cas_failure();
+        counters.cas_failure();
+        counters.escalation();
+        counters.free(3);
+        let stats = counters.snapshot();
+        assert_eq!(stats, Stats { cas_failures: 2, escalations: 1, freed: 3, ..Stats::default() });
+    }
+
+    #[test]
+    fn test_stack_stats() {
+        let stack: Arc<Stack<usize>> = Arc::new(Stack::new(true));
+        let mut waitvec: Vec<thread::JoinHandle<()>> = Vec::new();
+        for _ in 0..8 {
+            let stack_copy = stack.clone();
+            waitvec.push(thread::spawn(move || {
+                for i in 0..1000 {
+                    stack_copy.push(i);
+                    stack_copy.pop();
+                }
+            }));
+        }
+        for handle in waitvec {
+            handle.join().unwrap();
+        }
+        let stats = stack.stats();
+        assert!(stats.retired + stats.eliminations > 0);
+        assert!(stats.freed <= stats.retired);
+    }
+
+    #[test]
+    fn test_queue_stats() {
+        let queue: Queue<usize> = Queue::new();
+        for i in 0..1000 {
+            queue.enqueue(i).unwrap();
+        }
+        for _ in 0..1000 {
+            queue.dequeue();
+        }
+        let stats = queue.stats();
+        assert_eq!(stats.retired, 1000);
+        assert_eq!(stats.cas_failures, 0);
+        assert_eq!(stats.backoffs, 0);
+    }
+}
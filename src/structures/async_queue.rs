@@ -0,0 +1,368 @@
+use futures::sink::Sink;
+use futures::stream::Stream;
+use futures::task::{Context, Poll, Waker};
+use std::sync::atomic::{self, AtomicUsize};
+use std::sync::atomic::Ordering::SeqCst;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::fmt;
+use super::{Queue, ConcurrentQueue};
+
+/// A queue for async code, whose receivers wait for elements as a `Stream` instead of blocking a thread.
+///
+/// This wraps a Queue, SegQueue or FlatCombiningQueue. A receiver that finds the queue empty registers the
+/// waker of its task, checks the queue one last time, and returns `Pending`. A sender wakes the longest
+/// waiting task after an enqueue, but only takes the lock to do so if it sees a registered waker, so while
+/// every receiver is busy, sending and receiving are as lock-free as the wrapped queue. Every waiting task
+/// is woken once the last sender is gone, and a receiver dropped after being woken passes the wake on.
+///
+/// The queue is created as a pair of handles, both of which can be cloned. The `Sender` also implements
+/// `Sink`, and the stream of each `Receiver` ends once every sender is gone and the queue is drained.
+/// Sending fails once every receiver is gone.
+/// # Usage
+/// ```
+/// let (sender, receiver) = AsyncQueue::handles();
+/// thread::spawn(move || {
+///     for i in 0..8 {
+///         sender.send(i).unwrap();
+///     }
+/// });
+/// let received: Vec<u8> = block_on(receiver.collect());
+/// ```
+pub struct AsyncQueue<T: Send, Q: ConcurrentQueue<T> = Queue<T>> {
+    queue: Q,
+    wakers: Mutex<Vec<(usize, Waker)>>,
+    waiting: AtomicUsize,
+    senders: AtomicUsize,
+    receivers: AtomicUsize,
+    next_id: AtomicUsize,
+    marker: PhantomData<T>
+}
+
+/// The sending end of an AsyncQueue. It can be cloned to add more senders.
+pub struct Sender<T: Send, Q: ConcurrentQueue<T> = Queue<T>> {
+    queue: Arc<AsyncQueue<T, Q>>
+}
+
+/// The receiving end of an AsyncQueue, which is a stream of its elements. It can be cloned to add more receivers.
+pub struct Receiver<T: Send, Q: ConcurrentQueue<T> = Queue<T>> {
+    queue: Arc<AsyncQueue<T, Q>>,
+    id: usize
+}
+
+/// The error returned by the sink of a Sender once every receiver is gone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SendError;
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "every receiver of the queue has been dropped")
+    }
+}
+
+impl<T: Send> AsyncQueue<T, Queue<T>> {
+    /// Create a new, empty queue backed by a Queue, and return its sender and receiver handles.
+    /// # Examples
+    /// ```
+    /// let (sender, receiver): (Sender<u8>, Receiver<u8>) = AsyncQueue::handles();
+    /// ```
+    pub fn handles() -> (Sender<T>, Receiver<T>) {
+        AsyncQueue::from_queue(Queue::new())
+    }
+}
+
+impl<T: Send, Q: ConcurrentQueue<T>> AsyncQueue<T, Q> {
+    /// Create a new queue around the given queue, keeping any elements already in it, and return its
+    /// sender and receiver handles.
+    /// # Examples
+    /// ```
+    /// let (sender, receiver): (Sender<u8, SegQueue<u8>>, Receiver<u8, SegQueue<u8>>) = AsyncQueue::from_queue(SegQueue::new(8));
+    /// ```
+    pub fn from_queue(queue: Q) -> (Sender<T, Q>, Receiver<T, Q>) {
+        let queue = Arc::new(AsyncQueue {
+            queue,
+            wakers: Mutex::new(Vec::new()),
+            waiting: AtomicUsize::new(0),
+            senders: AtomicUsize::new(1),
+            receivers: AtomicUsize::new(1),
+            next_id: AtomicUsize::new(1),
+            marker: PhantomData
+        });
+        (Sender { queue: queue.clone() }, Receiver { queue, id: 0 })
+    }
+
+    /// Wake the longest waiting receiver, if there are any, to take an element that was just enqueued.
+    fn wake_one(&self) {
+        // Pairs with the registration of a waker: either it sees our change, or we see its waker
+        atomic::fence(SeqCst);
+        if self.waiting.load(SeqCst) == 0 {
+            return
+        }
+        let waker = {
+            let mut wakers = self.lock();
+            let waker = if wakers.is_empty() { None } else { Some(wakers.remove(0).1) };
+            self.waiting.store(wakers.len(), SeqCst);
+            waker
+        };
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+
+    /// Wake every registered receiver, if there are any.
+    fn wake_all(&self) {
+        // Pairs with the registration of a waker: either it sees our change, or we see its waker
+        atomic::fence(SeqCst);
+        if self.waiting.load(SeqCst) == 0 {
+            return
+        }
+        let wakers: Vec<(usize, Waker)> = {
+            let mut wakers = self.lock();
+            self.waiting.store(0, SeqCst);
+            wakers.drain(..).collect()
+        };
+        for (_, waker) in wakers {
+            waker.wake();
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Vec<(usize, Waker)>> {
+        self.wakers.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl<T: Send, Q: ConcurrentQueue<T>> Sender<T, Q> {
+    /// Add an element to the queue, waking any receiver waiting for one. If every receiver is gone, the
+    /// element is handed back inside Err.
+    /// # Examples
+    /// ```
+    /// let (sender, receiver) = AsyncQueue::handles();
+    /// assert_eq!(sender.send(1), Ok(()));
+    /// drop(receiver);
+    /// assert_eq!(sender.send(2), Err(2));
+    /// ```
+    pub fn send(&self, val: T) -> Result<(), T> {
+        if self.queue.receivers.load(SeqCst) == 0 {
+            return Err(val)
+        }
+        self.queue.queue.enqueue(val)?;
+        self.queue.wake_one();
+        Ok(())
+    }
+}
+
+impl<T: Send, Q: ConcurrentQueue<T>> Clone for Sender<T, Q> {
+    fn clone(&self) -> Self {
+        self.queue.senders.fetch_add(1, SeqCst);
+        Sender { queue: self.queue.clone() }
+    }
+}
+
+impl<T: Send, Q: ConcurrentQueue<T>> Drop for Sender<T, Q> {
+    fn drop(&mut self) {
+        // The streams end once the last sender is gone, so the receivers must check again
+        if self.queue.senders.fetch_sub(1, SeqCst) == 1 {
+            self.queue.wake_all();
+        }
+    }
+}
+
+impl<T: Send, Q: ConcurrentQueue<T>> Sink<T> for Sender<T, Q> {
+    type Error = SendError;
+
+    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<(), SendError>> {
+        if self.queue.receivers.load(SeqCst) == 0 {
+            Poll::Ready(Err(SendError))
+        } else {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: T) -> Result<(), SendError> {
+        self.send(item).map_err(|_| SendError)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<(), SendError>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<Result<(), SendError>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<T: Send, Q: ConcurrentQueue<T>> Receiver<T, Q> {
+    /// Take an element from the queue, or return None if the queue is empty, without waiting.
+    /// # Examples
+    /// ```
+    /// let (sender, receiver) = AsyncQueue::handles();
+    /// sender.send(1).unwrap();
+    /// assert_eq!(receiver.try_recv(), Some(1));
+    /// assert_eq!(receiver.try_recv(), None);
+    /// ```
+    pub fn try_recv(&self) -> Option<T> {
+        self.queue.queue.dequeue()
+    }
+}
+
+impl<T: Send, Q: ConcurrentQueue<T>> Stream for Receiver<T, Q> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<T>> {
+        let queue = &*self.queue;
+        if let Some(val) = queue.queue.dequeue() {
+            return Poll::Ready(Some(val))
+        }
+        {
+            let mut wakers = queue.lock();
+            match wakers.iter_mut().find(|entry| entry.0 == self.id) {
+                Some(entry) => {
+                    if !entry.1.will_wake(cx.waker()) {
+                        entry.1 = cx.waker().clone();
+                    }
+                },
+                None => wakers.push((self.id, cx.waker().clone()))
+            }
+            queue.waiting.store(wakers.len(), SeqCst);
+        }
+        // Pairs with the fence in wake_all: either the sender sees our waker, or we see its change
+        atomic::fence(SeqCst);
+        let senders = queue.senders.load(SeqCst);
+        match queue.queue.dequeue() {
+            Some(val) => Poll::Ready(Some(val)),
+            None if senders == 0 => Poll::Ready(None),
+            None => Poll::Pending
+        }
+    }
+}
+
+impl<T: Send, Q: ConcurrentQueue<T>> Clone for Receiver<T, Q> {
+    fn clone(&self) -> Self {
+        self.queue.receivers.fetch_add(1, SeqCst);
+        Receiver {
+            queue: self.queue.clone(),
+            id: self.queue.next_id.fetch_add(1, SeqCst)
+        }
+    }
+}
+
+impl<T: Send, Q: ConcurrentQueue<T>> Drop for Receiver<T, Q> {
+    fn drop(&mut self) {
+        self.queue.receivers.fetch_sub(1, SeqCst);
+        let registered = {
+            let mut wakers = self.queue.lock();
+            let len = wakers.len();
+            wakers.retain(|entry| entry.0 != self.id);
+            self.queue.waiting.store(wakers.len(), SeqCst);
+            wakers.len() < len
+        };
+        // We may have been woken for an element we will never take, so another receiver must take it
+        if !registered {
+            self.queue.wake_one();
+        }
+    }
+}
+
+mod tests {
+    #![allow(unused_imports)]
+    use super::{AsyncQueue, Sender, Receiver, SendError};
+    use super::super::SegQueue;
+    use futures::executor::block_on;
+    use futures::{future, stream, SinkExt, StreamExt};
+    use futures::task::{self, ArcWake, Context, Poll};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    struct WakeCounter(AtomicUsize);
+
+    impl ArcWake for WakeCounter {
+        fn wake_by_ref(arc_self: &Arc<Self>) {
+            arc_self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_single_threaded() {
+        let (sender, mut receiver) = AsyncQueue::handles();
+        assert_eq!(receiver.try_recv(), None);
+        for i in 0..10 {
+            assert_eq!(sender.send(i), Ok(()));
+        }
+        for i in 0..10 {
+            assert_eq!(block_on(receiver.next()), Some(i));
+        }
+        drop(sender);
+        assert_eq!(block_on(receiver.next()), None);
+    }
+
+    #[test]
+    fn test_multithreaded() {
+        let (sender, receiver): (Sender<usize, SegQueue<usize>>, Receiver<usize, SegQueue<usize>>)
+            = AsyncQueue::from_queue(SegQueue::new(4));
+        let mut waitvec: Vec<thread::JoinHandle<usize>> = Vec::new();
+        for _ in 0..4 {
+            let receiver_copy = receiver.clone();
+            waitvec.push(thread::spawn(move || {
+                block_on(receiver_copy.fold(0, |sum, val| future::ready(sum + val)))
+            }));
+        }
+        drop(receiver);
+        for id in 0..4 {
+            let sender_copy = sender.clone();
+            thread::spawn(move || {
+                for i in 0..5000 {
+                    sender_copy.send(id * 5000 + i).unwrap();
+                }
+            });
+        }
+        // The streams end once every sender has finished and been dropped
+        drop(sender);
+        let sum: usize = waitvec.into_iter().map(|handle| handle.join().unwrap()).sum();
+        assert_eq!(sum, (0..20000).sum::<usize>());
+    }
+
+    #[test]
+    fn test_wakes_one_receiver() {
+        let (sender, mut first): (Sender<u8>, Receiver<u8>) = AsyncQueue::handles();
+        let mut second = first.clone();
+        let counters: Vec<Arc<WakeCounter>> = (0..2).map(|_| Arc::new(WakeCounter(AtomicUsize::new(0)))).collect();
+        let wakers: Vec<_> = counters.iter().map(|counter| task::waker(counter.clone())).collect();
+        assert_eq!(first.poll_next_unpin(&mut Context::from_waker(&wakers[0])), Poll::Pending);
+        assert_eq!(second.poll_next_unpin(&mut Context::from_waker(&wakers[1])), Poll::Pending);
+
+        // Only the longest waiting receiver is woken for an element
+        sender.send(1).unwrap();
+        assert_eq!(counters[0].0.load(Ordering::SeqCst), 1);
+        assert_eq!(counters[1].0.load(Ordering::SeqCst), 0);
+
+        // Dropping it without taking the element passes the wake on
+        drop(first);
+        assert_eq!(counters[1].0.load(Ordering::SeqCst), 1);
+        assert_eq!(second.poll_next_unpin(&mut Context::from_waker(&wakers[1])), Poll::Ready(Some(1)));
+
+        // Every waiting receiver is woken once the last sender is gone
+        let mut third = second.clone();
+        assert_eq!(second.poll_next_unpin(&mut Context::from_waker(&wakers[0])), Poll::Pending);
+        assert_eq!(third.poll_next_unpin(&mut Context::from_waker(&wakers[1])), Poll::Pending);
+        drop(sender);
+        assert_eq!(counters[0].0.load(Ordering::SeqCst), 2);
+        assert_eq!(counters[1].0.load(Ordering::SeqCst), 2);
+        assert_eq!(block_on(second.next()), None);
+        assert_eq!(block_on(third.next()), None);
+    }
+
+    #[test]
+    fn test_sink() {
+        let (mut sender, receiver) = AsyncQueue::handles();
+        let consumer = thread::spawn(move || block_on(receiver.collect::<Vec<usize>>()));
+        block_on(sender.send_all(&mut stream::iter((0..100).map(Ok)))).unwrap();
+        drop(sender);
+        assert_eq!(consumer.join().unwrap(), (0..100).collect::<Vec<usize>>());
+
+        let (mut sender, receiver) = AsyncQueue::handles();
+        drop(receiver);
+        assert_eq!(block_on(SinkExt::send(&mut sender, 1)), Err(SendError));
+    }
+}
@@ -0,0 +1,305 @@
+use std::sync::atomic::{self, AtomicBool, AtomicUsize};
+use std::sync::atomic::Ordering::{SeqCst, Relaxed};
+use std::sync::{Mutex, MutexGuard, Condvar};
+use std::time::{Duration, Instant};
+use std::marker::PhantomData;
+use super::{Queue, SegQueue, FlatCombiningQueue};
+
+/// A queue which can be wrapped by a BlockingQueue.
+pub trait ConcurrentQueue<T: Send>: Send + Sync {
+    /// Add an element to the queue, or hand it back inside Err if the queue has been closed.
+    fn enqueue(&self, val: T) -> Result<(), T>;
+    /// Take an element from the queue, or return None if it is empty.
+    fn dequeue(&self) -> Option<T>;
+}
+
+impl<T: Send> ConcurrentQueue<T> for Queue<T> {
+    fn enqueue(&self, val: T) -> Result<(), T> { Queue::enqueue(self, val) }
+    fn dequeue(&self) -> Option<T> { Queue::dequeue(self) }
+}
+
+impl<T: Send> ConcurrentQueue<T> for SegQueue<T> {
+    fn enqueue(&self, val: T) -> Result<(), T> { SegQueue::enqueue(self, val) }
+    fn dequeue(&self) -> Option<T> { SegQueue::dequeue(self) }
+}
+
+impl<T: Send> ConcurrentQueue<T> for FlatCombiningQueue<T> {
+    fn enqueue(&self, val: T) -> Result<(), T> { FlatCombiningQueue::enqueue(self, val); Ok(()) }
+    fn dequeue(&self) -> Option<T> { FlatCombiningQueue::dequeue(self) }
+}
+
+/// A queue whose consumers can sleep until an element arrives, instead of polling `dequeue`.
+///
+/// This wraps a Queue, SegQueue or FlatCombiningQueue and adds an eventcount: a sleeping consumer registers
+/// itself as a waiter, checks the queue one last time, and then waits for the event count to change. A producer
+/// only takes the lock to change the count and wake a consumer if it sees a registered waiter, so while every
+/// consumer is busy, enqueue and dequeue are as lock-free as those of the wrapped queue.
+///
+/// Once the queue is closed, consumers drain the remaining elements and are then told the queue is finished,
+/// and further enqueues are refused. An enqueue racing with `close` may still succeed after every blocked consumer
+/// has given up, in which case its element can still be taken with `dequeue`.
+///
+/// The queue can be used in a multithreaded context by wrapping it in an Arc.
+/// # Usage
+/// ```
+/// let queue: Arc<BlockingQueue<u8>> = Arc::new(BlockingQueue::new());
+/// let queue_clone = queue.clone();
+/// let consumer = thread::spawn(move || {
+///     while let Some(val) = queue_clone.dequeue_blocking() {
+///         println!("{}", val);
+///     }
+/// });
+/// queue.enqueue(8);
+/// queue.close();
+/// consumer.join().unwrap();
+/// ```
+pub struct BlockingQueue<T: Send, Q: ConcurrentQueue<T> = Queue<T>> {
+    queue: Q,
+    waiters: AtomicUsize,
+    epoch: AtomicUsize,
+    closed: AtomicBool,
+    lock: Mutex<()>,
+    condvar: Condvar,
+    marker: PhantomData<T>
+}
+
+impl<T: Send> BlockingQueue<T, Queue<T>> {
+    /// Create a new, empty blocking queue backed by a Queue.
+    /// # Examples
+    /// ```
+    /// let queue: BlockingQueue<u8> = BlockingQueue::new();
+    /// ```
+    pub fn new() -> Self {
+        BlockingQueue::from_queue(Queue::new())
+    }
+}
+
+impl<T: Send, Q: ConcurrentQueue<T>> BlockingQueue<T, Q> {
+    /// Create a new blocking queue around the given queue, keeping any elements already in it.
+    /// # Examples
+    /// ```
+    /// let queue: BlockingQueue<u8, SegQueue<u8>> = BlockingQueue::from_queue(SegQueue::new(8));
+    /// ```
+    pub fn from_queue(queue: Q) -> Self {
+        BlockingQueue {
+            queue,
+            waiters: AtomicUsize::new(0),
+            epoch: AtomicUsize::new(0),
+            closed: AtomicBool::new(false),
+            lock: Mutex::new(()),
+            condvar: Condvar::new(),
+            marker: PhantomData
+        }
+    }
+
+    /// Add an element to the queue, waking a consumer if one is asleep. If the queue has been closed,
+    /// the element is handed back inside Err.
+    /// # Examples
+    /// ```
+    /// let queue: BlockingQueue<u8> = BlockingQueue::new();
+    /// assert_eq!(queue.enqueue(1), Ok(()));
+    /// queue.close();
+    /// assert_eq!(queue.enqueue(2), Err(2));
+    /// ```
+    pub fn enqueue(&self, val: T) -> Result<(), T> {
+        if self.closed.load(SeqCst) {
+            return Err(val)
+        }
+        self.queue.enqueue(val)?;
+        // Pairs with the registration of a waiter: either it sees our element, or we see it
+        atomic::fence(SeqCst);
+        if self.waiters.load(SeqCst) != 0 {
+            let _guard = self.lock();
+            self.epoch.fetch_add(1, Relaxed);
+            self.condvar.notify_one();
+        }
+        Ok(())
+    }
+
+    /// Take an element from the queue, or return None if the queue is empty, without blocking.
+    /// # Examples
+    /// ```
+    /// let queue: BlockingQueue<u8> = BlockingQueue::new();
+    /// assert_eq!(queue.dequeue(), None);
+    /// ```
+    pub fn dequeue(&self) -> Option<T> {
+        self.queue.dequeue()
+    }
+
+    /// Take an element from the queue, sleeping until one arrives if the queue is empty. Returns None
+    /// only once the queue has been closed and drained.
+    /// # Examples
+    /// ```
+    /// let queue: BlockingQueue<u8> = BlockingQueue::new();
+    /// queue.enqueue(1);
+    /// assert_eq!(queue.dequeue_blocking(), Some(1));
+    /// ```
+    pub fn dequeue_blocking(&self) -> Option<T> {
+        self.dequeue_until(None)
+    }
+
+    /// Take an element from the queue, sleeping for at most `timeout` until one arrives if the queue is
+    /// empty. Returns None if the time runs out, or if the queue has been closed and drained.
+    /// # Examples
+    /// ```
+    /// let queue: BlockingQueue<u8> = BlockingQueue::new();
+    /// assert_eq!(queue.dequeue_timeout(Duration::from_millis(10)), None);
+    /// ```
+    pub fn dequeue_timeout(&self, timeout: Duration) -> Option<T> {
+        self.dequeue_until(Some(Instant::now() + timeout))
+    }
+
+    fn dequeue_until(&self, deadline: Option<Instant>) -> Option<T> {
+        loop {
+            // Read closed first, so that a None after it means the queue was drained after closing
+            let closed = self.closed.load(SeqCst);
+            if let Some(val) = self.queue.dequeue() {
+                return Some(val)
+            }
+            if closed {
+                return None
+            }
+
+            let mut guard = self.lock();
+            self.waiters.fetch_add(1, SeqCst);
+            let epoch = self.epoch.load(Relaxed);
+            let closed = self.closed.load(SeqCst);
+            let result = self.queue.dequeue();
+            if result.is_some() || closed {
+                self.waiters.fetch_sub(1, SeqCst);
+                return result
+            }
+
+            // Producers change the epoch under the lock, so we cannot miss a wake up between the check and the wait
+            while self.epoch.load(Relaxed) == epoch {
+                guard = match deadline {
+                    None => self.condvar.wait(guard).unwrap_or_else(|poisoned| poisoned.into_inner()),
+                    Some(deadline) => {
+                        let now = Instant::now();
+                        if now >= deadline {
+                            self.waiters.fetch_sub(1, SeqCst);
+                            return None
+                        }
+                        self.condvar.wait_timeout(guard, deadline - now)
+                            .unwrap_or_else(|poisoned| poisoned.into_inner()).0
+                    }
+                };
+            }
+            self.waiters.fetch_sub(1, SeqCst);
+        }
+    }
+
+    /// Close the queue. Further enqueues are refused, and every sleeping consumer is woken to drain the
+    /// remaining elements.
+    /// # Examples
+    /// ```
+    /// let queue: BlockingQueue<u8> = BlockingQueue::new();
+    /// queue.enqueue(1);
+    /// queue.close();
+    /// assert_eq!(queue.dequeue_blocking(), Some(1));
+    /// assert_eq!(queue.dequeue_blocking(), None);
+    /// ```
+    pub fn close(&self) {
+        self.closed.store(true, SeqCst);
+        let _guard = self.lock();
+        self.epoch.fetch_add(1, Relaxed);
+        self.condvar.notify_all();
+    }
+
+    /// Whether the queue has been closed.
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(SeqCst)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, ()> {
+        self.lock.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl<T: Send> Default for BlockingQueue<T, Queue<T>> {
+    fn default() -> Self {
+        BlockingQueue::new()
+    }
+}
+
+mod tests {
+    #![allow(unused_imports)]
+    use super::BlockingQueue;
+    use super::super::SegQueue;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_timeout() {
+        let queue: BlockingQueue<u8> = BlockingQueue::new();
+        let start = Instant::now();
+        assert_eq!(queue.dequeue_timeout(Duration::from_millis(50)), None);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        queue.enqueue(1).unwrap();
+        assert_eq!(queue.dequeue_timeout(Duration::from_millis(50)), Some(1));
+    }
+
+    #[test]
+    fn test_wakes_sleeping_consumer() {
+        let queue: Arc<BlockingQueue<u8>> = Arc::new(BlockingQueue::new());
+        let queue_copy = queue.clone();
+        let consumer = thread::spawn(move || queue_copy.dequeue_blocking());
+        thread::sleep(Duration::from_millis(50));
+        queue.enqueue(8).unwrap();
+        assert_eq!(consumer.join().unwrap(), Some(8));
+    }
+
+    #[test]
+    fn test_close() {
+        let queue: Arc<BlockingQueue<u8>> = Arc::new(BlockingQueue::new());
+        let mut waitvec: Vec<thread::JoinHandle<Option<u8>>> = Vec::new();
+        for _ in 0..4 {
+            let queue_copy = queue.clone();
+            waitvec.push(thread::spawn(move || queue_copy.dequeue_blocking()));
+        }
+        thread::sleep(Duration::from_millis(50));
+        queue.close();
+        for handle in waitvec {
+            assert_eq!(handle.join().unwrap(), None);
+        }
+        assert!(queue.is_closed());
+        assert_eq!(queue.enqueue(1), Err(1));
+    }
+
+    #[test]
+    fn test_producers_consumers() {
+        let queue: Arc<BlockingQueue<usize, SegQueue<usize>>> = Arc::new(BlockingQueue::from_queue(SegQueue::new(4)));
+        let mut consumers: Vec<thread::JoinHandle<usize>> = Vec::new();
+        for _ in 0..8 {
+            let queue_copy = queue.clone();
+            consumers.push(thread::spawn(move || {
+                let mut sum = 0;
+                while let Some(val) = queue_copy.dequeue_blocking() {
+                    sum += val;
+                }
+                sum
+            }));
+        }
+        let mut producers: Vec<thread::JoinHandle<()>> = Vec::new();
+        for _ in 0..8 {
+            let queue_copy = queue.clone();
+            producers.push(thread::spawn(move || {
+                for i in 0..10000 {
+                    queue_copy.enqueue(i).unwrap();
+                    if i % 1000 == 0 {
+                        // Let the consumers run dry and fall asleep
+                        thread::sleep(Duration::from_millis(1));
+                    }
+                }
+            }));
+        }
+        for handle in producers {
+            handle.join().unwrap();
+        }
+        queue.close();
+        let sum: usize = consumers.into_iter().map(|handle| handle.join().unwrap()).sum();
+        assert_eq!(sum, 8 * (0..10000).sum::<usize>());
+    }
+}
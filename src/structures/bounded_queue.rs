@@ -0,0 +1,331 @@
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::{Acquire, Release, Relaxed};
+use std::cell::UnsafeCell;
+use std::mem::MaybeUninit;
+use stats::Counters;
+#[cfg(feature = "stats")]
+use stats::Stats;
+
+/// A lock-free bounded queue, backed by a ring buffer.
+///
+/// This is an implementation of [Dmitry Vyukov's bounded MPMC queue](http://www.1024cores.net/home/lock-free-algorithms/queues/bounded-mpmc-queue).
+/// Each slot of the buffer carries a sequence number, which tells a thread whether the slot is ready to be
+/// written by the enqueue at that position or read by the dequeue at that position. Enqueuers and dequeuers
+/// then only contend on their own end of the queue, to claim a position, and nothing is allocated after
+/// the queue is created.
+///
+/// The capacity is fixed, and an enqueue fails if the queue is full, handing the element back.
+///
+/// The queue can be used in a multithreaded context by wrapping it in an Arc.
+/// # Usage
+/// ```
+/// let queue: Arc<BoundedQueue<u8>> = Arc::new(BoundedQueue::new(64));
+/// for _ in 0..8 {
+///     let queue_clone = queue.clone();
+///     thread::spawn(move || {
+///         if let Err(val) = queue_clone.try_enqueue(8) {
+///             println!("Queue full, dropping {}", val);
+///         }
+///         queue_clone.dequeue();
+///     });
+/// }
+/// ```
+pub struct BoundedQueue<T: Send> {
+    buffer: Box<[Slot<T>]>,
+    mask: usize,
+    enqueue_pos: Position,
+    dequeue_pos: Position,
+    stats: Counters
+}
+
+unsafe impl<T: Send> Sync for BoundedQueue<T> {}
+unsafe impl<T: Send> Send for BoundedQueue<T> {}
+
+struct Slot<T> {
+    sequence: AtomicUsize,
+    data: UnsafeCell<MaybeUninit<T>>
+}
+
+/// A position counter on its own cache line, so that enqueuers and dequeuers do not slow each other down.
+#[repr(align(64))]
+struct Position(AtomicUsize);
+
+impl<T: Send> BoundedQueue<T> {
+    /// Create a new queue with the given capacity, which must be a power of 2 and at least 2. With a
+    /// single slot, the sequence numbers of a full slot and a free one would be the same.
+    /// # Examples
+    /// ```
+    /// let queue: BoundedQueue<u8> = BoundedQueue::new(64);
+    /// ```
+    pub fn new(capacity: usize) -> Self {
+        if capacity < 2 || (capacity & (capacity - 1)) != 0 {
+            panic!("capacity must be a power of 2 of at least 2!")
+        }
+        let buffer: Vec<Slot<T>> = (0..capacity).map(|i| Slot {
+            sequence: AtomicUsize::new(i),
+            data: UnsafeCell::new(MaybeUninit::uninit())
+        }).collect();
+        BoundedQueue {
+            buffer: buffer.into_boxed_slice(),
+            mask: capacity - 1,
+            enqueue_pos: Position(AtomicUsize::new(0)),
+            dequeue_pos: Position(AtomicUsize::new(0)),
+            stats: Counters::new()
+        }
+    }
+
+    /// Add an element to the back of the queue, or hand it back inside Err if the queue is full.
+    /// # Examples
+    /// ```
+    /// let queue: BoundedQueue<u8> = BoundedQueue::new(2);
+    /// assert_eq!(queue.try_enqueue(1), Ok(()));
+    /// assert_eq!(queue.try_enqueue(2), Ok(()));
+    /// assert_eq!(queue.try_enqueue(3), Err(3));
+    /// ```
+    pub fn try_enqueue(&self, val: T) -> Result<(), T> {
+        let mut pos = self.enqueue_pos.0.load(Relaxed);
+        loop {
+            let slot = &self.buffer[pos & self.mask];
+            let sequence = slot.sequence.load(Acquire);
+            let diff = sequence.wrapping_sub(pos) as isize;
+            if diff == 0 {
+                // The slot is free for this position: claim the position
+                match self.enqueue_pos.0.compare_exchange_weak(pos, pos.wrapping_add(1), Relaxed, Relaxed) {
+                    Ok(_) => {
+                        unsafe { (*slot.data.get()).as_mut_ptr().write(val) };
+                        slot.sequence.store(pos.wrapping_add(1), Release);
+                        return Ok(())
+                    },
+                    Err(current) => {
+                        self.stats.cas_failure();
+                        pos = current;
+                    }
+                }
+            } else if diff < 0 {
+                // The slot still holds the element from a lap ago
+                return Err(val)
+            } else {
+                // Another enqueue claimed this position first
+                pos = self.enqueue_pos.0.load(Relaxed);
+            }
+        }
+    }
+
+    /// Take an element from the front of the queue, or return None if the queue is empty.
+    /// # Examples
+    /// ```
+    /// let queue: BoundedQueue<u8> = BoundedQueue::new(64);
+    /// queue.try_enqueue(1);
+    /// assert_eq!(queue.dequeue(), Some(1));
+    /// assert_eq!(queue.dequeue(), None);
+    /// ```
+    pub fn dequeue(&self) -> Option<T> {
+        let mut pos = self.dequeue_pos.0.load(Relaxed);
+        loop {
+            let slot = &self.buffer[pos & self.mask];
+            let sequence = slot.sequence.load(Acquire);
+            let diff = sequence.wrapping_sub(pos.wrapping_add(1)) as isize;
+            if diff == 0 {
+                // The slot has been filled for this position: claim the position
+                match self.dequeue_pos.0.compare_exchange_weak(pos, pos.wrapping_add(1), Relaxed, Relaxed) {
+                    Ok(_) => {
+                        let val = unsafe { (*slot.data.get()).as_ptr().read() };
+                        // Free the slot for the enqueue one lap ahead
+                        slot.sequence.store(pos.wrapping_add(self.mask + 1), Release);
+                        return Some(val)
+                    },
+                    Err(current) => {
+                        self.stats.cas_failure();
+                        pos = current;
+                    }
+                }
+            } else if diff < 0 {
+                // The enqueue for this position has not happened yet
+                return None
+            } else {
+                // Another dequeue claimed this position first
+                pos = self.dequeue_pos.0.load(Relaxed);
+            }
+        }
+    }
+
+    /// The maximum number of elements the queue can hold.
+    pub fn capacity(&self) -> usize {
+        self.mask + 1
+    }
+
+    /// Get a snapshot of the contention counters of the queue.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> Stats {
+        self.stats.snapshot()
+    }
+}
+
+impl<T: Send> Drop for BoundedQueue<T> {
+    fn drop(&mut self) {
+        while self.dequeue().is_some() {}
+    }
+}
+
+mod tests {
+    #![allow(unused_imports)]
+    extern crate im;
+    use self::im::Vector;
+
+    use rand::{thread_rng, Rng};
+
+    use super::BoundedQueue;
+    use super::super::super::testing::{DropCounter, Dropper};
+    use super::super::super::testing::linearizability_tester::{LinearizabilityTester, LinearizabilityResult, ThreadLog};
+
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    #[test]
+    fn test_single_threaded() {
+        let queue: BoundedQueue<u8> = BoundedQueue::new(4);
+        assert_eq!(queue.capacity(), 4);
+        assert_eq!(queue.dequeue(), None);
+        // Go round the buffer a few times
+        for lap in 0..3 {
+            for i in 0..4 {
+                assert_eq!(queue.try_enqueue(lap * 4 + i), Ok(()));
+            }
+            assert_eq!(queue.try_enqueue(100), Err(100));
+            for i in 0..4 {
+                assert_eq!(queue.dequeue(), Some(lap * 4 + i));
+            }
+            assert_eq!(queue.dequeue(), None);
+        }
+    }
+
+    #[test]
+    fn test_smallest_capacity() {
+        let queue: BoundedQueue<u8> = BoundedQueue::new(2);
+        for lap in 0..3 {
+            assert_eq!(queue.try_enqueue(lap * 2), Ok(()));
+            assert_eq!(queue.try_enqueue(lap * 2 + 1), Ok(()));
+            assert_eq!(queue.try_enqueue(100), Err(100));
+            assert_eq!(queue.dequeue(), Some(lap * 2));
+            assert_eq!(queue.dequeue(), Some(lap * 2 + 1));
+            assert_eq!(queue.dequeue(), None);
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_capacity_one_rejected() {
+        let _queue: BoundedQueue<u8> = BoundedQueue::new(1);
+    }
+
+    #[test]
+    fn test_multithreaded() {
+        let queue: Arc<BoundedQueue<usize>> = Arc::new(BoundedQueue::new(64));
+        let mut waitvec: Vec<thread::JoinHandle<usize>> = Vec::new();
+        for _ in 0..8 {
+            let queue_copy = queue.clone();
+            waitvec.push(thread::spawn(move || {
+                for i in 0..10000 {
+                    while queue_copy.try_enqueue(i).is_err() {}
+                }
+                0
+            }));
+            let queue_copy = queue.clone();
+            waitvec.push(thread::spawn(move || {
+                let mut sum = 0;
+                for _ in 0..10000 {
+                    loop {
+                        if let Some(val) = queue_copy.dequeue() {
+                            sum += val;
+                            break;
+                        }
+                    }
+                }
+                sum
+            }));
+        }
+        let sum: usize = waitvec.into_iter().map(|handle| handle.join().unwrap()).sum();
+        assert_eq!(sum, 8 * (0..10000).sum::<usize>());
+        assert_eq!(queue.dequeue(), None);
+    }
+
+    #[test]
+    fn test_remaining_dropped() {
+        let drops = DropCounter::new();
+        let queue: BoundedQueue<Dropper> = BoundedQueue::new(8);
+        for _ in 0..5 {
+            queue.try_enqueue(drops.dropper()).unwrap();
+        }
+        drop(queue.dequeue());
+        drop(queue);
+        assert_eq!(drops.drops(), 5);
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    enum QueueResult {
+        Arg(usize),
+        Enqueue(Result<(), usize>),
+        Dequeue(Option<usize>)
+    }
+
+    #[test]
+    fn test_linearizable() {
+        let queue: BoundedQueue<usize> = BoundedQueue::new(16);
+        let sequential: Vector<usize> = Vector::new();
+        let mut linearizer: LinearizabilityTester<BoundedQueue<usize>, Vector<usize>, QueueResult>
+                = LinearizabilityTester::new(8, 1000000, queue, sequential);
+
+        fn sequential_dequeue(queue: &Vector<usize>, _val: Option<QueueResult>) -> (Vector<usize>, Option<QueueResult>) {
+            match queue.pop_front() {
+                Some((arc, vec)) => (vec, Some(QueueResult::Dequeue(Some(*arc)))),
+                None => (Vector::new(), Some(QueueResult::Dequeue(None)))
+            }
+        }
+
+        fn sequential_enqueue(queue: &Vector<usize>, val: Option<QueueResult>) -> (Vector<usize>, Option<QueueResult>) {
+            if let Some(QueueResult::Arg(val)) = val {
+                if queue.len() == 16 {
+                    (queue.clone(), Some(QueueResult::Enqueue(Err(val))))
+                } else {
+                    (queue.push_back(val), Some(QueueResult::Enqueue(Ok(()))))
+                }
+            } else {
+                panic!("Invalid argument")
+            }
+        }
+
+        fn enqueue(queue: &BoundedQueue<usize>, val: QueueResult) -> Option<QueueResult> {
+            if let QueueResult::Arg(val) = val {
+                Some(QueueResult::Enqueue(queue.try_enqueue(val)))
+            } else {
+                panic!("Invalid argument")
+            }
+        }
+
+        fn dequeue(queue: &BoundedQueue<usize>) -> Option<QueueResult> {
+            Some(QueueResult::Dequeue(queue.dequeue()))
+        }
+
+        fn worker(id: usize, log: &mut ThreadLog<BoundedQueue<usize>, Vector<usize>, QueueResult>) {
+            for _ in 0..1000 {
+                let rand = thread_rng().gen_range(0, 101);
+                if rand < 50 {
+                    let val = thread_rng().gen_range(0, 122222);
+                    log.log_val_result(id, enqueue, QueueResult::Arg(val), format!("enqueue: {}", val), sequential_enqueue);
+                } else {
+                    log.log(id, dequeue, "dequeue".to_owned(), sequential_dequeue)
+                }
+            }
+        }
+
+        let result = linearizer.run(worker);
+
+        println!("{:?}", result);
+
+        match result {
+            LinearizabilityResult::Success => assert!(true),
+            _ => assert!(false)
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::{AcqRel, Acquire, Release};
+use super::Stack;
+use super::PeekGuard;
+#[cfg(feature = "stats")]
+use stats::Stats;
+
+/// A lock-free stack with a fixed capacity, which refuses pushes once it is full.
+///
+/// This wraps a Stack, with or without elimination layer, and keeps a count of its size. A push first
+/// reserves a place in the count, and fails if the stack is at capacity, while a pop releases one once it
+/// has taken an element. A push and pop which eliminate each other therefore reserve and release a place
+/// between them, leaving the size unchanged. Since places are reserved before elements are pushed, the
+/// stack never holds more elements than its capacity, although `len` may briefly count a push which has
+/// not yet completed.
+///
+/// The stack can be used in a multithreaded context by wrapping it in an Arc.
+/// # Usage
+/// ```
+/// let stack: Arc<BoundedStack<u8>> = Arc::new(BoundedStack::new(100, true));
+/// for _ in 0..8 {
+///     let stack_clone = stack.clone();
+///     thread::spawn(move || {
+///         if let Err(val) = stack_clone.push(8) {
+///             println!("Stack full, dropping {}", val);
+///         }
+///         stack_clone.pop();
+///     });
+/// }
+/// ```
+pub struct BoundedStack<T: Send> {
+    stack: Stack<T>,
+    size: AtomicUsize,
+    capacity: usize
+}
+
+impl<T: Send> BoundedStack<T> {
+    /// Create a new stack holding at most `capacity` elements, with or without elimination layer.
+    /// # Examples
+    /// ```
+    /// let stack: BoundedStack<u8> = BoundedStack::new(100, true);
+    /// ```
+    pub fn new(capacity: usize, elimination_on: bool) -> Self {
+        BoundedStack {
+            stack: Stack::new(elimination_on),
+            size: AtomicUsize::new(0),
+            capacity
+        }
+    }
+
+    /// Push a piece of data onto the stack, or hand it back inside Err if the stack is full.
+    /// # Examples
+    /// ```
+    /// let stack: BoundedStack<u8> = BoundedStack::new(1, true);
+    /// assert_eq!(stack.push(1), Ok(()));
+    /// assert_eq!(stack.push(2), Err(2));
+    /// ```
+    pub fn push(&self, val: T) -> Result<(), T> {
+        let capacity = self.capacity;
+        match self.size.fetch_update(AcqRel, Acquire, |size| if size < capacity { Some(size + 1) } else { None }) {
+            Ok(_) => {
+                self.stack.push(val);
+                Ok(())
+            },
+            Err(_) => Err(val)
+        }
+    }
+
+    /// Pop a piece of data from the top of the stack, or return None if the stack is empty.
+    /// # Examples
+    /// ```
+    /// let stack: BoundedStack<u8> = BoundedStack::new(1, true);
+    /// stack.push(1);
+    /// assert_eq!(stack.pop(), Some(1));
+    /// ```
+    pub fn pop(&self) -> Option<T> {
+        let data = self.stack.pop();
+        if data.is_some() {
+            self.size.fetch_sub(1, Release);
+        }
+        data
+    }
+
+    /// Look at the data on top of the stack without removing it. See `Stack::peek`.
+    pub fn peek(&self) -> Option<PeekGuard<'_, T>> where T: Sync {
+        self.stack.peek()
+    }
+
+    /// The number of elements in the stack, including any pushes which have reserved a place but not yet completed.
+    pub fn len(&self) -> usize {
+        self.size.load(Acquire)
+    }
+
+    /// Whether the stack is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether the stack has reached its capacity, so that pushes will fail.
+    pub fn is_full(&self) -> bool {
+        self.len() >= self.capacity
+    }
+
+    /// The maximum number of elements the stack can hold.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Get a snapshot of the contention and reclamation counters of the underlying stack.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> Stats {
+        self.stack.stats()
+    }
+}
+
+mod tests {
+    #![allow(unused_imports)]
+    use super::BoundedStack;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_capacity() {
+        let stack: BoundedStack<u8> = BoundedStack::new(3, true);
+        assert!(stack.is_empty());
+        for i in 0..3 {
+            assert_eq!(stack.push(i), Ok(()));
+        }
+        assert!(stack.is_full());
+        assert_eq!(stack.push(3), Err(3));
+        assert_eq!(stack.pop(), Some(2));
+        assert_eq!(stack.len(), 2);
+        assert_eq!(stack.push(4), Ok(()));
+        assert_eq!(stack.push(5), Err(5));
+        assert_eq!(*stack.peek().unwrap().data(), 4);
+    }
+
+    #[test]
+    fn test_capacity_with_elimination() {
+        let stack: Arc<BoundedStack<usize>> = Arc::new(BoundedStack::new(64, true));
+        let mut waitvec: Vec<thread::JoinHandle<(usize, usize)>> = Vec::new();
+        for _ in 0..16 {
+            let stack_copy = stack.clone();
+            waitvec.push(thread::spawn(move || {
+                let mut pushed = 0;
+                let mut popped = 0;
+                for i in 0..5000 {
+                    if i % 2 == 0 {
+                        if stack_copy.push(i).is_ok() {
+                            pushed += 1;
+                        }
+                    } else if stack_copy.pop().is_some() {
+                        popped += 1;
+                    }
+                    assert!(stack_copy.len() <= 64);
+                }
+                (pushed, popped)
+            }));
+        }
+        let (pushed, popped) = waitvec.into_iter().map(|handle| handle.join().unwrap())
+                                      .fold((0, 0), |(pushed, popped), (p, q)| (pushed + p, popped + q));
+        assert_eq!(stack.len(), pushed - popped);
+        let mut remaining = 0;
+        while stack.pop().is_some() {
+            remaining += 1;
+        }
+        assert_eq!(remaining, pushed - popped);
+        assert_eq!(stack.len(), 0);
+    }
+}
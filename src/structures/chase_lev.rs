@@ -0,0 +1,352 @@
+use memory::HPBRManager;
+use std::sync::atomic::{self, AtomicIsize, AtomicPtr};
+use std::sync::atomic::Ordering::{Acquire, Release, Relaxed, SeqCst};
+use std::sync::Arc;
+use std::cell::{Cell, UnsafeCell};
+use std::mem::MaybeUninit;
+use std::marker::PhantomData;
+use std::ptr;
+
+/// The capacity of the first buffer of a new deque.
+const MIN_CAPACITY: usize = 32;
+
+/// A lock-free Chase-Lev work-stealing deque.
+///
+/// This is an implementation of the deque described in [Dynamic Circular Work-Stealing Deque](https://dl.acm.org/citation.cfm?id=1073974),
+/// with the memory orderings of [Correct and Efficient Work-Stealing for Weak Memory Models](https://dl.acm.org/citation.cfm?id=2442524).
+/// A single owner pushes and pops tasks at the bottom, without a CAS except when taking the last task,
+/// while any number of thieves steal tasks from the top. The tasks live in a circular buffer which the
+/// owner replaces with one twice the size when it fills up. Thieves may still be reading the old buffer,
+/// so it is retired through the HPBRManager rather than freed.
+///
+/// The deque is created as a `Worker` handle for the owner, which can be moved to another thread but not
+/// shared, and a `Stealer` handle, which can be cloned and shared between any number of thieves.
+/// # Usage
+/// ```
+/// let (worker, stealer) = ChaseLevDeque::handles();
+/// for _ in 0..8 {
+///     let stealer_clone = stealer.clone();
+///     thread::spawn(move || {
+///         if let Steal::Success(task) = stealer_clone.steal() {
+///             println!("Stole {}", task);
+///         }
+///     });
+/// }
+/// worker.push(8);
+/// worker.pop();
+/// ```
+pub struct ChaseLevDeque<T: Send> {
+    top: AtomicIsize,
+    bottom: AtomicIsize,
+    buffer: AtomicPtr<Buffer<T>>,
+    manager: HPBRManager<Buffer<T>>
+}
+
+unsafe impl<T: Send> Sync for ChaseLevDeque<T> {}
+unsafe impl<T: Send> Send for ChaseLevDeque<T> {}
+
+struct Buffer<T> {
+    slots: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    mask: isize
+}
+
+unsafe impl<T: Send> Send for Buffer<T> {}
+
+impl<T> Buffer<T> {
+    fn new_as_pointer(capacity: usize) -> *mut Self {
+        let slots: Vec<UnsafeCell<MaybeUninit<T>>> = (0..capacity).map(|_| UnsafeCell::new(MaybeUninit::uninit())).collect();
+        Box::into_raw(Box::new(Buffer {
+            slots: slots.into_boxed_slice(),
+            mask: capacity as isize - 1
+        }))
+    }
+
+    fn capacity(&self) -> isize {
+        self.mask + 1
+    }
+
+    /// Make a bitwise copy of the task at an index. The copy only becomes the owned task once the
+    /// caller has claimed that index, and must otherwise be forgotten.
+    unsafe fn read(&self, index: isize) -> MaybeUninit<T> {
+        ptr::read(self.slots[(index & self.mask) as usize].get())
+    }
+
+    unsafe fn write(&self, index: isize, val: T) {
+        (*self.slots[(index & self.mask) as usize].get()).as_mut_ptr().write(val);
+    }
+}
+
+/// The result of an attempt to steal from a ChaseLevDeque.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Steal<T> {
+    /// The deque was empty.
+    Empty,
+    /// A task was stolen.
+    Success(T),
+    /// Another thread took the task first. The deque may still hold more.
+    Retry
+}
+
+/// The owner's end of a ChaseLevDeque, which pushes and pops at the bottom.
+pub struct Worker<T: Send> {
+    deque: Arc<ChaseLevDeque<T>>,
+    // The worker can be sent to another thread but not shared
+    marker: PhantomData<Cell<()>>
+}
+
+/// A thief's end of a ChaseLevDeque, which steals from the top. It can be cloned to add more thieves.
+pub struct Stealer<T: Send> {
+    deque: Arc<ChaseLevDeque<T>>
+}
+
+impl<T: Send> ChaseLevDeque<T> {
+    /// Create a new, empty deque and return its worker and stealer handles.
+    /// # Examples
+    /// ```
+    /// let (worker, stealer): (Worker<u8>, Stealer<u8>) = ChaseLevDeque::handles();
+    /// ```
+    pub fn handles() -> (Worker<T>, Stealer<T>) {
+        let deque = Arc::new(ChaseLevDeque {
+            top: AtomicIsize::new(0),
+            bottom: AtomicIsize::new(0),
+            buffer: AtomicPtr::new(Buffer::new_as_pointer(MIN_CAPACITY)),
+            manager: HPBRManager::new(10, 1)
+        });
+        (Worker { deque: deque.clone(), marker: PhantomData }, Stealer { deque })
+    }
+}
+
+impl<T: Send> Worker<T> {
+    /// Push a task onto the bottom of the deque, growing the buffer if it is full.
+    /// # Examples
+    /// ```
+    /// let (worker, stealer) = ChaseLevDeque::handles();
+    /// worker.push(8);
+    /// ```
+    pub fn push(&self, val: T) {
+        let deque = &*self.deque;
+        let bottom = deque.bottom.load(Relaxed);
+        let top = deque.top.load(Acquire);
+        // Only the worker replaces the buffer, so it needs no protection here
+        let mut buffer = deque.buffer.load(Relaxed);
+        if bottom - top >= unsafe { (*buffer).capacity() } {
+            buffer = self.grow(buffer, top, bottom);
+        }
+        unsafe { (*buffer).write(bottom, val) };
+        atomic::fence(Release);
+        deque.bottom.store(bottom + 1, Relaxed);
+    }
+
+    /// Move the tasks into a buffer twice the size, and retire the old one.
+    fn grow(&self, old: *mut Buffer<T>, top: isize, bottom: isize) -> *mut Buffer<T> {
+        let deque = &*self.deque;
+        unsafe {
+            let new = Buffer::new_as_pointer((*old).capacity() as usize * 2);
+            for index in top..bottom {
+                ptr::write((*new).slots[(index & (*new).mask) as usize].get(), (*old).read(index));
+            }
+            deque.buffer.store(new, Release);
+            // Thieves may still be reading tasks from the old buffer, which are now copies of those in the new one
+            deque.manager.retire(old, 0);
+            new
+        }
+    }
+
+    /// Pop a task from the bottom of the deque, or return None if the deque is empty. Tasks are popped
+    /// in the reverse of the order they were pushed.
+    /// # Examples
+    /// ```
+    /// let (worker, stealer) = ChaseLevDeque::handles();
+    /// worker.push(1);
+    /// worker.push(2);
+    /// assert_eq!(worker.pop(), Some(2));
+    /// ```
+    pub fn pop(&self) -> Option<T> {
+        let deque = &*self.deque;
+        let bottom = deque.bottom.load(Relaxed) - 1;
+        let buffer = deque.buffer.load(Relaxed);
+        deque.bottom.store(bottom, Relaxed);
+        atomic::fence(SeqCst);
+        let top = deque.top.load(Relaxed);
+
+        if top > bottom {
+            // The deque was empty
+            deque.bottom.store(bottom + 1, Relaxed);
+            return None
+        }
+        let val = unsafe { (*buffer).read(bottom) };
+        if top == bottom {
+            // This is the last task, so thieves may be trying to take it too
+            let won = deque.top.compare_exchange(top, top + 1, SeqCst, Relaxed).is_ok();
+            deque.bottom.store(bottom + 1, Relaxed);
+            if !won {
+                return None
+            }
+        }
+        Some(unsafe { val.assume_init() })
+    }
+
+    /// Whether the deque is empty.
+    pub fn is_empty(&self) -> bool {
+        let bottom = self.deque.bottom.load(Relaxed);
+        let top = self.deque.top.load(Relaxed);
+        bottom <= top
+    }
+
+    /// Create another stealer for this worker's deque.
+    pub fn stealer(&self) -> Stealer<T> {
+        Stealer { deque: self.deque.clone() }
+    }
+}
+
+impl<T: Send> Stealer<T> {
+    /// Attempt to steal a task from the top of the deque. Tasks are stolen in the order they were pushed.
+    /// If another thread took the task at the top first, `Steal::Retry` is returned, and the caller may
+    /// try again or look for work elsewhere.
+    /// # Examples
+    /// ```
+    /// let (worker, stealer) = ChaseLevDeque::handles();
+    /// worker.push(1);
+    /// worker.push(2);
+    /// assert_eq!(stealer.steal(), Steal::Success(1));
+    /// ```
+    pub fn steal(&self) -> Steal<T> {
+        let deque = &*self.deque;
+        let top = deque.top.load(Acquire);
+        atomic::fence(SeqCst);
+        let bottom = deque.bottom.load(Acquire);
+        if top >= bottom {
+            return Steal::Empty
+        }
+
+        let buffer = loop {
+            let buffer = deque.buffer.load(Acquire);
+            deque.manager.protect(buffer, 0);
+            if ptr::eq(buffer, deque.buffer.load(Acquire)) {
+                break buffer
+            }
+        };
+        let val = unsafe { (*buffer).read(top) };
+        let won = deque.top.compare_exchange(top, top + 1, SeqCst, Relaxed).is_ok();
+        deque.manager.unprotect(0);
+        if won {
+            Steal::Success(unsafe { val.assume_init() })
+        } else {
+            Steal::Retry
+        }
+    }
+
+    /// Whether the deque is empty.
+    pub fn is_empty(&self) -> bool {
+        let top = self.deque.top.load(Acquire);
+        let bottom = self.deque.bottom.load(Acquire);
+        bottom <= top
+    }
+}
+
+impl<T: Send> Clone for Stealer<T> {
+    fn clone(&self) -> Self {
+        Stealer { deque: self.deque.clone() }
+    }
+}
+
+impl<T: Send> Drop for ChaseLevDeque<T> {
+    fn drop(&mut self) {
+        let buffer = *self.buffer.get_mut();
+        let top = *self.top.get_mut();
+        let bottom = *self.bottom.get_mut();
+        unsafe {
+            for index in top..bottom {
+                drop((*buffer).read(index).assume_init());
+            }
+            drop(Box::from_raw(buffer));
+        }
+    }
+}
+
+mod tests {
+    #![allow(unused_imports)]
+    use super::{ChaseLevDeque, Worker, Stealer, Steal};
+    use super::super::super::testing::{DropCounter, Dropper};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    #[test]
+    fn test_single_threaded() {
+        let (worker, stealer) = ChaseLevDeque::handles();
+        assert_eq!(worker.pop(), None);
+        assert_eq!(stealer.steal(), Steal::Empty);
+        // Enough to grow the buffer a few times
+        for i in 0..1000 {
+            worker.push(i);
+        }
+        assert_eq!(stealer.steal(), Steal::Success(0));
+        assert_eq!(stealer.steal(), Steal::Success(1));
+        assert_eq!(worker.pop(), Some(999));
+        for i in (2..999).rev() {
+            assert_eq!(worker.pop(), Some(i));
+        }
+        assert_eq!(worker.pop(), None);
+        assert!(worker.is_empty());
+        assert_eq!(stealer.steal(), Steal::Empty);
+    }
+
+    #[test]
+    fn test_stealing() {
+        let (worker, stealer) = ChaseLevDeque::handles();
+        let mut waitvec: Vec<thread::JoinHandle<Vec<usize>>> = Vec::new();
+        let done = Arc::new(AtomicUsize::new(0));
+        for _ in 0..4 {
+            let stealer_copy = stealer.clone();
+            let done_copy = done.clone();
+            waitvec.push(thread::spawn(move || {
+                let mut stolen = Vec::new();
+                loop {
+                    match stealer_copy.steal() {
+                        Steal::Success(val) => stolen.push(val),
+                        Steal::Retry => {},
+                        Steal::Empty => if done_copy.load(Ordering::SeqCst) == 1 { break; }
+                    }
+                }
+                stolen
+            }));
+        }
+        let mut taken = Vec::new();
+        for i in 0..20000 {
+            worker.push(i);
+            if i % 3 == 0 {
+                if let Some(val) = worker.pop() {
+                    taken.push(val);
+                }
+            }
+        }
+        while let Some(val) = worker.pop() {
+            taken.push(val);
+        }
+        done.store(1, Ordering::SeqCst);
+        for handle in waitvec {
+            taken.extend(handle.join().unwrap());
+        }
+        // Every task was taken exactly once
+        taken.sort();
+        assert_eq!(taken, (0..20000).collect::<Vec<usize>>());
+    }
+
+    #[test]
+    fn test_remaining_dropped() {
+        let drops = DropCounter::new();
+        let (worker, stealer) = ChaseLevDeque::handles();
+        for _ in 0..100 {
+            worker.push(drops.dropper());
+        }
+        drop(worker.pop());
+        if let Steal::Success(val) = stealer.steal() {
+            drop(val);
+        }
+        assert_eq!(drops.drops(), 2);
+        drop(worker);
+        drop(stealer);
+        assert_eq!(drops.drops(), 100);
+    }
+}
@@ -0,0 +1,449 @@
+use memory::HPBRManager;
+use stats::Counters;
+#[cfg(feature = "stats")]
+use stats::Stats;
+use super::utils::TaggedAtomicPtr;
+use std::sync::atomic::AtomicPtr;
+use std::sync::atomic::Ordering::SeqCst;
+use std::ptr;
+
+/// Tag of an anchor whose nodes are all correctly linked in both directions.
+const STABLE: usize = 0x0;
+/// Tag of an anchor whose rightmost node has been pushed, but is not yet linked from its left neighbour.
+const RPUSH: usize = 0x1;
+/// Tag of an anchor whose leftmost node has been pushed, but is not yet linked from its right neighbour.
+const LPUSH: usize = 0x2;
+const STATUS: usize = RPUSH | LPUSH;
+
+/// A lock-free double-ended queue.
+///
+/// This is an implementation of the deque described in [CAS-Based Lock-Free Algorithm for Shared Deques](https://link.springer.com/chapter/10.1007/978-3-540-45209-6_92).
+/// The deque is a doubly-linked list, whose two ends are held together in an anchor, so both ends are
+/// changed with a single CAS. A push first swings the anchor to its new node and marks the anchor with
+/// the end it pushed to, using two tag bits of the anchor pointer. The old end node is then linked to
+/// the new one, by the pusher or by any thread that finds the mark, and the mark is cleared. Every
+/// operation finds its nodes through the anchor, so nodes can be retired as soon as they leave it.
+///
+/// The anchor holds a pointer to an immutable pair of end nodes, replaced on every push and pop, which
+/// is reclaimed with the HPBRManager along with the nodes.
+///
+/// The deque can be used in a multithreaded context by wrapping it in an Arc.
+/// # Usage
+/// ```
+/// let deque: Arc<Deque<u8>> = Arc::new(Deque::new());
+/// for _ in 0..8 {
+///     let deque_clone = deque.clone();
+///     thread::spawn(move || {
+///         deque_clone.push_back(8);
+///         deque_clone.pop_front();
+///     });
+/// }
+/// ```
+pub struct Deque<T: Send> {
+    anchor: TaggedAtomicPtr<Ends<T>>,
+    anchor_manager: HPBRManager<Ends<T>>,
+    node_manager: HPBRManager<Node<T>>,
+    stats: Counters
+}
+
+unsafe impl<T: Send> Sync for Deque<T> {}
+unsafe impl<T: Send> Send for Deque<T> {}
+
+/// The two end nodes of the deque, both null if it is empty.
+struct Ends<T: Send> {
+    left: *mut Node<T>,
+    right: *mut Node<T>
+}
+
+unsafe impl<T: Send> Send for Ends<T> {}
+
+struct Node<T: Send> {
+    left: AtomicPtr<Node<T>>,
+    right: AtomicPtr<Node<T>>,
+    value: Option<T>
+}
+
+impl<T: Send> Ends<T> {
+    fn new_as_pointer(left: *mut Node<T>, right: *mut Node<T>, status: usize) -> *mut Self {
+        TaggedAtomicPtr::with_tag(Box::into_raw(Box::new(Ends { left, right })), status)
+    }
+}
+
+impl<T: Send> Node<T> {
+    fn new_as_pointer(value: T) -> *mut Self {
+        Box::into_raw(Box::new(Node {
+            left: AtomicPtr::new(ptr::null_mut()),
+            right: AtomicPtr::new(ptr::null_mut()),
+            value: Some(value)
+        }))
+    }
+}
+
+impl<T: Send> Deque<T> {
+    /// Create a new, empty deque.
+    /// # Examples
+    /// ```
+    /// let deque: Deque<u8> = Deque::new();
+    /// ```
+    pub fn new() -> Self {
+        Deque {
+            anchor: TaggedAtomicPtr::new(Ends::new_as_pointer(ptr::null_mut(), ptr::null_mut(), STABLE)),
+            anchor_manager: HPBRManager::new(100, 1),
+            node_manager: HPBRManager::new(100, 2),
+            stats: Counters::new()
+        }
+    }
+
+    /// Add an element to the back of the deque.
+    /// # Examples
+    /// ```
+    /// let deque: Deque<u8> = Deque::new();
+    /// deque.push_back(8);
+    /// ```
+    pub fn push_back(&self, val: T) {
+        self.push(val, RPUSH);
+    }
+
+    /// Add an element to the front of the deque.
+    /// # Examples
+    /// ```
+    /// let deque: Deque<u8> = Deque::new();
+    /// deque.push_front(8);
+    /// ```
+    pub fn push_front(&self, val: T) {
+        self.push(val, LPUSH);
+    }
+
+    /// Take the element at the back of the deque, or return None if the deque is empty.
+    /// # Examples
+    /// ```
+    /// let deque: Deque<u8> = Deque::new();
+    /// deque.push_back(1);
+    /// deque.push_back(2);
+    /// assert_eq!(deque.pop_back(), Some(2));
+    /// ```
+    pub fn pop_back(&self) -> Option<T> {
+        self.pop(true)
+    }
+
+    /// Take the element at the front of the deque, or return None if the deque is empty.
+    /// # Examples
+    /// ```
+    /// let deque: Deque<u8> = Deque::new();
+    /// deque.push_back(1);
+    /// deque.push_back(2);
+    /// assert_eq!(deque.pop_front(), Some(1));
+    /// ```
+    pub fn pop_front(&self) -> Option<T> {
+        self.pop(false)
+    }
+
+    fn push(&self, val: T, side: usize) {
+        let node = Node::new_as_pointer(val);
+        loop {
+            let anchor = self.load_anchor();
+            let ends = unsafe { &*TaggedAtomicPtr::untagged(anchor) };
+            if ends.right.is_null() {
+                let new = Ends::new_as_pointer(node, node, STABLE);
+                if self.swing_anchor(anchor, new) {
+                    return
+                }
+            } else if TaggedAtomicPtr::tag(anchor) == STABLE {
+                let new = unsafe {
+                    if side == RPUSH {
+                        (*node).left.store(ends.right, SeqCst);
+                        Ends::new_as_pointer(ends.left, node, RPUSH)
+                    } else {
+                        (*node).right.store(ends.left, SeqCst);
+                        Ends::new_as_pointer(node, ends.right, LPUSH)
+                    }
+                };
+                if self.swing_anchor(anchor, new) {
+                    // Link the old end to our node, unless another thread gets there first
+                    let anchor = self.load_anchor();
+                    if TaggedAtomicPtr::tag(anchor) != STABLE {
+                        self.stabilize(anchor);
+                    }
+                    self.anchor_manager.unprotect(0);
+                    return
+                }
+            } else {
+                self.stabilize(anchor);
+            }
+        }
+    }
+
+    fn pop(&self, back: bool) -> Option<T> {
+        let node = loop {
+            let anchor = self.load_anchor();
+            let ends = unsafe { &*TaggedAtomicPtr::untagged(anchor) };
+            if ends.right.is_null() {
+                self.anchor_manager.unprotect(0);
+                return None
+            }
+            if ptr::eq(ends.left, ends.right) {
+                // Read the node first, as the old ends may be freed as soon as they are retired
+                let end = ends.right;
+                let new = Ends::new_as_pointer(ptr::null_mut(), ptr::null_mut(), TaggedAtomicPtr::tag(anchor));
+                if self.swing_anchor(anchor, new) {
+                    break end
+                }
+            } else if TaggedAtomicPtr::tag(anchor) == STABLE {
+                let end = if back { ends.right } else { ends.left };
+                self.node_manager.protect(end, 0);
+                if self.anchor.load(SeqCst) != anchor {
+                    continue;
+                }
+                let new = unsafe {
+                    if back {
+                        Ends::new_as_pointer(ends.left, (*end).left.load(SeqCst), STABLE)
+                    } else {
+                        Ends::new_as_pointer((*end).right.load(SeqCst), ends.right, STABLE)
+                    }
+                };
+                if self.swing_anchor(anchor, new) {
+                    break end
+                }
+            } else {
+                self.stabilize(anchor);
+            }
+        };
+        // Only the thread that removed the node from the anchor takes its element and retires it
+        let val = unsafe { (*node).value.take() };
+        self.node_manager.retire(node, 0);
+        val
+    }
+
+    /// Load and protect the current anchor, returning it with its tag.
+    fn load_anchor(&self) -> *mut Ends<T> {
+        loop {
+            let anchor = self.anchor.load(SeqCst);
+            self.anchor_manager.protect(TaggedAtomicPtr::untagged(anchor), 0);
+            let current = self.anchor.load(SeqCst);
+            if ptr::eq(TaggedAtomicPtr::untagged(current), TaggedAtomicPtr::untagged(anchor)) {
+                return current
+            }
+        }
+    }
+
+    /// Replace the anchor with a new pair of ends, retiring the old pair on success and freeing the new
+    /// pair, which nobody else has seen, on failure.
+    fn swing_anchor(&self, current: *mut Ends<T>, new: *mut Ends<T>) -> bool {
+        match self.anchor.compare_exchange(current, new, SeqCst, SeqCst) {
+            Ok(_) => {
+                self.anchor_manager.retire(TaggedAtomicPtr::untagged(current), 0);
+                true
+            },
+            Err(_) => {
+                self.stats.cas_failure();
+                unsafe { drop(Box::from_raw(TaggedAtomicPtr::untagged(new))) };
+                false
+            }
+        }
+    }
+
+    /// Link the node next to a newly pushed end to it, and mark the anchor stable. The anchor must be protected.
+    fn stabilize(&self, anchor: *mut Ends<T>) {
+        let ends = unsafe { &*TaggedAtomicPtr::untagged(anchor) };
+        let (end, right_side) = match TaggedAtomicPtr::tag(anchor) {
+            RPUSH => (ends.right, true),
+            _ => (ends.left, false)
+        };
+        self.node_manager.protect(end, 0);
+        if self.anchor.load(SeqCst) != anchor {
+            return
+        }
+        unsafe {
+            // The neighbour cannot be popped while the anchor is unstable
+            let neighbour = if right_side { (*end).left.load(SeqCst) } else { (*end).right.load(SeqCst) };
+            self.node_manager.protect(neighbour, 1);
+            if self.anchor.load(SeqCst) != anchor {
+                return
+            }
+            let link = if right_side { &(*neighbour).right } else { &(*neighbour).left };
+            let current = link.load(SeqCst);
+            if !ptr::eq(current, end) {
+                if self.anchor.load(SeqCst) != anchor {
+                    return
+                }
+                if link.compare_exchange(current, end, SeqCst, SeqCst).is_err() {
+                    self.stats.cas_failure();
+                    return
+                }
+            }
+        }
+        let _ = self.anchor.compare_exchange(anchor, TaggedAtomicPtr::without_tag(anchor, STATUS), SeqCst, SeqCst);
+        self.node_manager.unprotect(1);
+    }
+
+    /// Get a snapshot of the contention and reclamation counters of the deque.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> Stats {
+        self.stats.snapshot() + self.node_manager.stats()
+    }
+}
+
+impl<T: Send> Default for Deque<T> {
+    fn default() -> Self {
+        Deque::new()
+    }
+}
+
+impl<T: Send> Drop for Deque<T> {
+    fn drop(&mut self) {
+        let anchor = self.anchor.load(SeqCst);
+        unsafe {
+            let ends = Box::from_raw(TaggedAtomicPtr::untagged(anchor));
+            if ends.right.is_null() {
+                return
+            }
+            // Finish any push left unlinked, so that the nodes can be walked from left to right
+            match TaggedAtomicPtr::tag(anchor) {
+                RPUSH => (*(*ends.right).left.load(SeqCst)).right.store(ends.right, SeqCst),
+                LPUSH => (*(*ends.left).right.load(SeqCst)).left.store(ends.left, SeqCst),
+                _ => {}
+            }
+            let mut current = ends.left;
+            loop {
+                let node = Box::from_raw(current);
+                if ptr::eq(current, ends.right) {
+                    break;
+                }
+                current = node.right.load(SeqCst);
+            }
+        }
+    }
+}
+
+mod tests {
+    #![allow(unused_imports)]
+    extern crate im;
+    use self::im::Vector;
+
+    use rand::{thread_rng, Rng};
+
+    use super::Deque;
+    use super::super::super::testing::{DropCounter, Dropper};
+    use super::super::super::testing::linearizability_tester::{LinearizabilityTester, LinearizabilityResult, ThreadLog};
+
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    #[test]
+    fn test_single_threaded() {
+        let deque: Deque<u8> = Deque::new();
+        assert_eq!(deque.pop_back(), None);
+        assert_eq!(deque.pop_front(), None);
+        deque.push_back(2);
+        deque.push_front(1);
+        deque.push_back(3);
+        deque.push_front(0);
+        assert_eq!(deque.pop_front(), Some(0));
+        assert_eq!(deque.pop_back(), Some(3));
+        assert_eq!(deque.pop_back(), Some(2));
+        assert_eq!(deque.pop_back(), Some(1));
+        assert_eq!(deque.pop_front(), None);
+        for i in 0..10 {
+            deque.push_front(i);
+        }
+        for i in 0..10 {
+            assert_eq!(deque.pop_back(), Some(i));
+        }
+    }
+
+    #[test]
+    fn test_multithreaded() {
+        let deque: Arc<Deque<usize>> = Arc::new(Deque::new());
+        let mut waitvec: Vec<thread::JoinHandle<usize>> = Vec::new();
+        for id in 0..8 {
+            let deque_copy = deque.clone();
+            waitvec.push(thread::spawn(move || {
+                let mut sum = 0;
+                for i in 0..5000 {
+                    if id % 2 == 0 { deque_copy.push_back(i) } else { deque_copy.push_front(i) }
+                    loop {
+                        let val = if i % 2 == 0 { deque_copy.pop_back() } else { deque_copy.pop_front() };
+                        if let Some(val) = val {
+                            sum += val;
+                            break;
+                        }
+                    }
+                }
+                sum
+            }));
+        }
+        let sum: usize = waitvec.into_iter().map(|handle| handle.join().unwrap()).sum();
+        assert_eq!(sum, 8 * (0..5000).sum::<usize>());
+        assert_eq!(deque.pop_back(), None);
+    }
+
+    #[test]
+    fn test_remaining_dropped() {
+        let drops = DropCounter::new();
+        let deque: Deque<Dropper> = Deque::new();
+        for i in 0..10 {
+            if i % 2 == 0 { deque.push_back(drops.dropper()) } else { deque.push_front(drops.dropper()) }
+        }
+        drop(deque.pop_back());
+        drop(deque.pop_front());
+        assert_eq!(drops.drops(), 2);
+        drop(deque);
+        assert_eq!(drops.drops(), 10);
+    }
+
+    #[test]
+    fn test_linearizable() {
+        let deque: Deque<usize> = Deque::new();
+        let sequential: Vector<usize> = Vector::new();
+        let mut linearizer: LinearizabilityTester<Deque<usize>, Vector<usize>, usize>
+                = LinearizabilityTester::new(8, 1000000, deque, sequential);
+
+        fn sequential_push_back(deque: &Vector<usize>, val: Option<usize>) -> (Vector<usize>, Option<usize>) {
+            (deque.push_back(val.unwrap()), None)
+        }
+
+        fn sequential_push_front(deque: &Vector<usize>, val: Option<usize>) -> (Vector<usize>, Option<usize>) {
+            (deque.push_front(val.unwrap()), None)
+        }
+
+        fn sequential_pop_back(deque: &Vector<usize>, _val: Option<usize>) -> (Vector<usize>, Option<usize>) {
+            match deque.pop_back() {
+                Some((arc, vec)) => (vec, Some(*arc)),
+                None => (Vector::new(), None)
+            }
+        }
+
+        fn sequential_pop_front(deque: &Vector<usize>, _val: Option<usize>) -> (Vector<usize>, Option<usize>) {
+            match deque.pop_front() {
+                Some((arc, vec)) => (vec, Some(*arc)),
+                None => (Vector::new(), None)
+            }
+        }
+
+        fn worker(id: usize, log: &mut ThreadLog<Deque<usize>, Vector<usize>, usize>) {
+            for _ in 0..1000 {
+                let rand = thread_rng().gen_range(0, 101);
+                let val = thread_rng().gen_range(0, 122222);
+                if rand < 25 {
+                    log.log_val(id, Deque::push_back, val, format!("push_back: {}", val), sequential_push_back);
+                } else if rand < 50 {
+                    log.log_val(id, Deque::push_front, val, format!("push_front: {}", val), sequential_push_front);
+                } else if rand < 75 {
+                    log.log(id, Deque::pop_back, "pop_back".to_owned(), sequential_pop_back);
+                } else {
+                    log.log(id, Deque::pop_front, "pop_front".to_owned(), sequential_pop_front);
+                }
+            }
+        }
+
+        let result = linearizer.run(worker);
+
+        println!("{:?}", result);
+
+        match result {
+            LinearizabilityResult::Success => assert!(true),
+            _ => assert!(false)
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use super::utils::FlatCombiner;
+use std::collections::VecDeque;
+
+/// A flat-combining queue.
+///
+/// Rather than every thread competing to CAS the head and tail of a linked list, threads publish their
+/// enqueues and dequeues, and whichever thread holds the combiner lock applies them all in a batch to a
+/// sequential `VecDeque`. Under very heavy contention, this can outperform the Michael-Scott Queue, as the
+/// queue stays in one thread's cache and there is no backoff. Under light contention it is usually slower.
+/// Unlike the Queue, it is blocking rather than lock-free, since a descheduled combiner delays every thread
+/// waiting on it.
+///
+/// The queue can be used in a multithreaded context by wrapping it in an Arc.
+/// # Usage
+/// ```
+/// let queue: Arc<FlatCombiningQueue<u8>> = Arc::new(FlatCombiningQueue::new());
+/// for _ in 0..8 {
+///     let queue_clone = queue.clone();
+///     thread::spawn(move || {
+///         queue_clone.enqueue(8);
+///         queue_clone.dequeue();
+///     });
+/// }
+/// ```
+pub struct FlatCombiningQueue<T: Send> {
+    combiner: FlatCombiner<VecDeque<T>, QueueOp<T>, Option<T>>
+}
+
+enum QueueOp<T> {
+    Enqueue(T),
+    Dequeue
+}
+
+fn apply<T>(queue: &mut VecDeque<T>, op: QueueOp<T>) -> Option<T> {
+    match op {
+        QueueOp::Enqueue(val) => {
+            queue.push_back(val);
+            None
+        },
+        QueueOp::Dequeue => queue.pop_front()
+    }
+}
+
+impl<T: Send> FlatCombiningQueue<T> {
+    /// Create a new, empty queue.
+    /// # Examples
+    /// ```
+    /// let queue: FlatCombiningQueue<u8> = FlatCombiningQueue::new();
+    /// ```
+    pub fn new() -> Self {
+        FlatCombiningQueue {
+            combiner: FlatCombiner::new(VecDeque::new())
+        }
+    }
+
+    /// Add a new element to the back of the queue.
+    /// # Examples
+    /// ```
+    /// let queue: FlatCombiningQueue<String> = FlatCombiningQueue::new();
+    /// queue.enqueue("hello".to_owned());
+    /// ```
+    pub fn enqueue(&self, val: T) {
+        self.combiner.execute(QueueOp::Enqueue(val), apply);
+    }
+
+    /// Take an element from the front of the queue, or return None if the queue is empty.
+    /// # Examples
+    /// ```
+    /// let queue: FlatCombiningQueue<String> = FlatCombiningQueue::new();
+    /// queue.enqueue("hello".to_owned());
+    /// assert_eq!(queue.dequeue(), Some("hello".to_owned()));
+    /// ```
+    pub fn dequeue(&self) -> Option<T> {
+        self.combiner.execute(QueueOp::Dequeue, apply)
+    }
+}
+
+impl<T: Send> Default for FlatCombiningQueue<T> {
+    fn default() -> Self {
+        FlatCombiningQueue::new()
+    }
+}
+
+mod tests {
+    #![allow(unused_imports)]
+    extern crate im;
+    use self::im::Vector;
+
+    use rand::{thread_rng, Rng};
+
+    use super::FlatCombiningQueue;
+    use super::super::super::testing::linearizability_tester::{LinearizabilityTester, LinearizabilityResult, ThreadLog};
+
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_single_threaded() {
+        let queue: FlatCombiningQueue<u8> = FlatCombiningQueue::new();
+        queue.enqueue(1);
+        queue.enqueue(2);
+        assert_eq!(queue.dequeue(), Some(1));
+        assert_eq!(queue.dequeue(), Some(2));
+        assert_eq!(queue.dequeue(), None);
+    }
+
+    #[test]
+    fn test_multithreaded() {
+        let queue: Arc<FlatCombiningQueue<u32>> = Arc::new(FlatCombiningQueue::new());
+        let mut waitvec: Vec<thread::JoinHandle<()>> = Vec::new();
+        for _ in 0..10 {
+            let queue_copy = queue.clone();
+            waitvec.push(thread::spawn(move || {
+                for i in 0..10000 {
+                    queue_copy.enqueue(i);
+                }
+            }));
+            let queue_copy = queue.clone();
+            waitvec.push(thread::spawn(move || {
+                for _ in 0..10000 {
+                    while queue_copy.dequeue().is_none() {}
+                }
+            }));
+        }
+        for handle in waitvec {
+            handle.join().unwrap();
+        }
+        assert_eq!(queue.dequeue(), None);
+    }
+
+    #[test]
+    fn test_linearizable() {
+        let queue: FlatCombiningQueue<usize> = FlatCombiningQueue::new();
+        let sequential: Vector<usize> = Vector::new();
+        let mut linearizer: LinearizabilityTester<FlatCombiningQueue<usize>, Vector<usize>, usize>
+                = LinearizabilityTester::new(8, 1000000, queue, sequential);
+
+        fn sequential_dequeue(queue: &Vector<usize>, _val: Option<usize>) -> (Vector<usize>, Option<usize>) {
+            match queue.pop_front() {
+                Some((arc, vec)) => {
+                    let res = *arc;
+                    (vec, Some(res))
+                },
+                None => (Vector::new(), None)
+            }
+        }
+
+        fn sequential_enqueue(queue: &Vector<usize>, val: Option<usize>) -> (Vector<usize>, Option<usize>) {
+            (queue.push_back(val.unwrap()), None)
+        }
+
+        fn worker(id: usize, log: &mut ThreadLog<FlatCombiningQueue<usize>, Vector<usize>, usize>) {
+            for _ in 0..1000 {
+                let rand = thread_rng().gen_range(0, 101);
+                if rand < 30 {
+                    let val = thread_rng().gen_range(0, 122222);
+                    log.log_val(id, FlatCombiningQueue::enqueue, val, format!("enqueue: {}", val), sequential_enqueue);
+                } else {
+                    log.log(id, FlatCombiningQueue::dequeue, "dequeue".to_owned(), sequential_dequeue)
+                }
+            }
+        }
+
+        let result = linearizer.run(worker);
+
+        println!("{:?}", result);
+
+        match result {
+            LinearizabilityResult::Success => assert!(true),
+            _ => assert!(false)
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use super::utils::FlatCombiner;
+
+/// A flat-combining stack.
+///
+/// Rather than every thread competing to CAS the head of a linked list, threads publish their pushes
+/// and pops, and whichever thread holds the combiner lock applies them all in a batch to a sequential
+/// `Vec`. Under very heavy contention on a single top element, this can outperform the Treiber stack,
+/// as the stack stays in one thread's cache and there are no failed CASes. Under light contention it is
+/// usually slower. Unlike the Stack, it is blocking rather than lock-free, since a descheduled combiner
+/// delays every thread waiting on it.
+///
+/// The stack can be used in a multithreaded context by wrapping it in an Arc.
+/// # Usage
+/// ```
+/// let stack: Arc<FlatCombiningStack<u8>> = Arc::new(FlatCombiningStack::new());
+/// for _ in 0..8 {
+///     let stack_clone = stack.clone();
+///     thread::spawn(move || {
+///         stack_clone.push(8);
+///         stack_clone.pop();
+///     });
+/// }
+/// ```
+pub struct FlatCombiningStack<T: Send> {
+    combiner: FlatCombiner<Vec<T>, StackOp<T>, Option<T>>
+}
+
+enum StackOp<T> {
+    Push(T),
+    Pop
+}
+
+fn apply<T>(stack: &mut Vec<T>, op: StackOp<T>) -> Option<T> {
+    match op {
+        StackOp::Push(val) => {
+            stack.push(val);
+            None
+        },
+        StackOp::Pop => stack.pop()
+    }
+}
+
+impl<T: Send> FlatCombiningStack<T> {
+    /// Create a new, empty stack.
+    /// # Examples
+    /// ```
+    /// let stack: FlatCombiningStack<u8> = FlatCombiningStack::new();
+    /// ```
+    pub fn new() -> Self {
+        FlatCombiningStack {
+            combiner: FlatCombiner::new(Vec::new())
+        }
+    }
+
+    /// Push a piece of data onto the stack.
+    /// # Examples
+    /// ```
+    /// let stack: FlatCombiningStack<String> = FlatCombiningStack::new();
+    /// stack.push("hello".to_owned());
+    /// ```
+    pub fn push(&self, val: T) {
+        self.combiner.execute(StackOp::Push(val), apply);
+    }
+
+    /// Pop a piece of data from the top of the stack, or return None if the stack is empty.
+    /// # Examples
+    /// ```
+    /// let stack: FlatCombiningStack<String> = FlatCombiningStack::new();
+    /// stack.push("hello".to_owned());
+    /// assert_eq!(stack.pop(), Some("hello".to_owned()));
+    /// ```
+    pub fn pop(&self) -> Option<T> {
+        self.combiner.execute(StackOp::Pop, apply)
+    }
+}
+
+impl<T: Send> Default for FlatCombiningStack<T> {
+    fn default() -> Self {
+        FlatCombiningStack::new()
+    }
+}
+
+mod tests {
+    #![allow(unused_imports)]
+    extern crate im;
+    use self::im::Vector;
+
+    use rand::{thread_rng, Rng};
+
+    use super::FlatCombiningStack;
+    use super::super::super::testing::linearizability_tester::{LinearizabilityTester, LinearizabilityResult, ThreadLog};
+
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_single_threaded() {
+        let stack: FlatCombiningStack<u8> = FlatCombiningStack::new();
+        stack.push(1);
+        stack.push(2);
+        assert_eq!(stack.pop(), Some(2));
+        assert_eq!(stack.pop(), Some(1));
+        assert_eq!(stack.pop(), None);
+    }
+
+    #[test]
+    fn test_multithreaded() {
+        let stack: Arc<FlatCombiningStack<u32>> = Arc::new(FlatCombiningStack::new());
+        let mut waitvec: Vec<thread::JoinHandle<()>> = Vec::new();
+        for _ in 0..10 {
+            let stack_copy = stack.clone();
+            waitvec.push(thread::spawn(move || {
+                for i in 0..10000 {
+                    stack_copy.push(i);
+                }
+            }));
+            let stack_copy = stack.clone();
+            waitvec.push(thread::spawn(move || {
+                for _ in 0..10000 {
+                    while stack_copy.pop().is_none() {}
+                }
+            }));
+        }
+        for handle in waitvec {
+            handle.join().unwrap();
+        }
+        assert_eq!(stack.pop(), None);
+    }
+
+    #[test]
+    fn test_linearizable() {
+        let stack: FlatCombiningStack<usize> = FlatCombiningStack::new();
+        let sequential: Vector<usize> = Vector::new();
+        let mut linearizer: LinearizabilityTester<FlatCombiningStack<usize>, Vector<usize>, usize>
+                = LinearizabilityTester::new(8, 1000000, stack, sequential);
+
+        fn sequential_pop(stack: &Vector<usize>, _val: Option<usize>) -> (Vector<usize>, Option<usize>) {
+            match stack.pop_back() {
+                Some((arc, vec)) => {
+                    let res = *arc;
+                    (vec, Some(res))
+                },
+                None => (Vector::new(), None)
+            }
+        }
+
+        fn sequential_push(stack: &Vector<usize>, val: Option<usize>) -> (Vector<usize>, Option<usize>) {
+            (stack.push_back(val.unwrap()), None)
+        }
+
+        fn worker(id: usize, log: &mut ThreadLog<FlatCombiningStack<usize>, Vector<usize>, usize>) {
+            for _ in 0..1000 {
+                let rand = thread_rng().gen_range(0, 101);
+                if rand < 30 {
+                    let val = thread_rng().gen_range(0, 122222);
+                    log.log_val(id, FlatCombiningStack::push, val, format!("push: {}", val), sequential_push);
+                } else {
+                    log.log(id, FlatCombiningStack::pop, "pop".to_owned(), sequential_pop)
+                }
+            }
+        }
+
+        let result = linearizer.run(worker);
+
+        println!("{:?}", result);
+
+        match result {
+            LinearizabilityResult::Success => assert!(true),
+            _ => assert!(false)
+        }
+    }
+}
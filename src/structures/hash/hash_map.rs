@@ -2,12 +2,15 @@ use std::hash::{Hash, Hasher, BuildHasher};
 use std::fmt::Debug;
 use std::fmt;
 use std::ptr;
+use std::sync::atomic::Ordering::{Acquire, Release, Relaxed};
 use std::borrow::Borrow;
 use std::marker::PhantomData;
 use std::collections::hash_map::RandomState;
 use memory::HPBRManager;
-use super::atomic_markable::AtomicMarkablePtr;
-use super::atomic_markable;
+use stats::Counters;
+#[cfg(feature = "stats")]
+use stats::Stats;
+use super::super::utils::TaggedAtomicPtr;
 use super::data_guard::DataGuard;
 
 const HEAD_SIZE: usize = 256;
@@ -15,6 +18,11 @@ const CHILD_SIZE: usize = 16;
 const KEY_SIZE: usize = 64;
 const MAX_FAILURES: u64 = 10;
 
+/// Tag for a slot whose data node must be moved into a new array node.
+const MARKED: usize = 0x1;
+/// Tag for a slot which points to an array node.
+const ARRAY: usize = 0x2;
+
 /// A wait-free HashMap based on a tree structure.
 ///
 /// This hashmap is an implementation of the Wait-Free HashMap presented in the paper [A Wait-Free HashMap]
@@ -48,11 +56,12 @@ pub struct HashMap<K, V>
 where K: Send,
       V: Send
 {
-    head: Vec<AtomicMarkablePtr<Node<K, V>>>,
+    head: Vec<TaggedAtomicPtr<Node<K, V>>>,
     hasher: RandomState,
     head_size: usize,
     shift_step: usize,
-    manager: HPBRManager<Node<K, V>>
+    manager: HPBRManager<Node<K, V>>,
+    stats: Counters
 }
 
 impl<K: Hash + Send, V: Send> HashMap<K, V> {
@@ -62,9 +71,9 @@ impl<K: Hash + Send, V: Send> HashMap<K, V> {
     /// let map: HashMap<String, u8> = HashMap::new(); // Creates a new map of String to u8
     /// ```
     pub fn new() -> Self {
-        let mut head: Vec<AtomicMarkablePtr<Node<K, V>>> = Vec::with_capacity(HEAD_SIZE);
+        let mut head: Vec<TaggedAtomicPtr<Node<K, V>>> = Vec::with_capacity(HEAD_SIZE);
         for _ in 0..HEAD_SIZE {
-            head.push(AtomicMarkablePtr::default());
+            head.push(TaggedAtomicPtr::default());
         }
 
         Self {
@@ -72,10 +81,24 @@ impl<K: Hash + Send, V: Send> HashMap<K, V> {
             hasher: RandomState::new(),
             head_size: HEAD_SIZE,
             shift_step: f64::floor((CHILD_SIZE as f64).log2()) as usize,
-            manager: HPBRManager::new(100, 1)
+            manager: HPBRManager::new(100, 1),
+            stats: Counters::new()
         }   
     }
 
+    /// Get a snapshot of the contention and reclamation counters of the map. Escalations
+    /// count the operations which exceeded their failure limit and forced a bucket to expand.
+    /// # Examples
+    /// ```
+    /// let map: HashMap<String, u8> = HashMap::new();
+    /// map.insert("hello".to_owned(), 8);
+    /// println!("{:?}", map.stats());
+    /// ```
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> Stats {
+        self.stats.snapshot() + self.manager.stats()
+    }
+
     /// Hash a single element with the default Rust hasher initialised to a random state.
     fn hash<Q: ?Sized>(&self, key: &Q) -> u64 
     where K: Borrow<Q>,
@@ -88,15 +111,15 @@ impl<K: Hash + Send, V: Send> HashMap<K, V> {
 
     /// Attempt to set the current MarkablePtr to point to an ArrayNode. This function adds the old DataNode
     /// at this position to the new ArrayNode.
-    fn expand_map(&self, bucket: &Vec<AtomicMarkablePtr<Node<K, V>>>, pos: usize, shift_amount: usize) -> *mut Node<K, V> {
+    fn expand_map(&self, bucket: &Vec<TaggedAtomicPtr<Node<K, V>>>, pos: usize, shift_amount: usize) -> *mut Node<K, V> {
         // We know this node must exist
-        let node = bucket[pos].get_ptr().unwrap();
-        self.manager.protect(atomic_markable::unmark(node), 0);
-        if atomic_markable::is_marked_second(node) {
+        let node = bucket[pos].load_non_null(Acquire).unwrap();
+        self.manager.protect(TaggedAtomicPtr::without_tag(node, MARKED), 0);
+        if TaggedAtomicPtr::has_tag(node, ARRAY) {
             //println!("already expanded: {:b}", node as usize);
             return node
         }
-        let node2 = bucket[pos].get_ptr().unwrap();
+        let node2 = bucket[pos].load_non_null(Acquire).unwrap();
         if !ptr::eq(node, node2) {
             //println!("someone else: {:b}", node2 as usize);
             return node2
@@ -104,16 +127,16 @@ impl<K: Hash + Send, V: Send> HashMap<K, V> {
 
         let array_node: ArrayNode<K, V> = ArrayNode::new(CHILD_SIZE);
         unsafe {
-            let hash = match &*atomic_markable::unmark(node) {
+            let hash = match &*TaggedAtomicPtr::without_tag(node, MARKED) {
                 &Node::Data(ref data_node) => data_node.hash,
                 &Node::Array(_) => {panic!("Unexpected array node!")}
             };
             let new_pos = (hash >> (shift_amount + self.shift_step)) as usize & (CHILD_SIZE - 1);
-            array_node.array[new_pos].store(atomic_markable::unmark(node));
+            array_node.array[new_pos].store(TaggedAtomicPtr::without_tag(node, MARKED), Release);
 
             let array_node_ptr = Box::into_raw(Box::new(Node::Array(array_node)));
-            let array_node_ptr_marked = atomic_markable::mark_second(array_node_ptr);
-            return match bucket[pos].compare_exchange(node, array_node_ptr_marked) {
+            let array_node_ptr_marked = TaggedAtomicPtr::with_tag(array_node_ptr, ARRAY);
+            return match bucket[pos].compare_exchange(node, array_node_ptr_marked, Release, Relaxed) {
                 Ok(_) => {
                     //println!("expanded on me");
                     array_node_ptr_marked
@@ -122,7 +145,7 @@ impl<K: Hash + Send, V: Send> HashMap<K, V> {
                     //println!("someone else: {:b}", current as usize);
                     // Need to remove the pointer to the old element or this will delete a valid node
                     let vec = get_bucket(array_node_ptr);
-                    vec[new_pos].store(ptr::null_mut()); 
+                    vec[new_pos].store(ptr::null_mut(), Release); 
                     Box::from_raw(array_node_ptr);
                     current
                 }
@@ -151,41 +174,42 @@ impl<K: Hash + Send, V: Send> HashMap<K, V> {
             let pos = mut_hash as usize & (bucket.len() - 1);
             mut_hash = mut_hash >> self.shift_step;
             let mut fail_count = 0;
-            let mut node = bucket[pos].get_ptr();
+            let mut node = bucket[pos].load_non_null(Acquire);
 
             loop {
                 if fail_count > MAX_FAILURES {
-                    bucket[pos].mark();
-                    node = bucket[pos].get_ptr();
+                    self.stats.escalation();
+                    mark(&bucket[pos]);
+                    node = bucket[pos].load_non_null(Acquire);
                 }
                 match node {
                     None => {
                         value = match self.try_insert(&bucket[pos], ptr::null_mut(), hash, value) {
                             Ok(_) => { return Ok(()) },
                             Err(old) => {
-                                node = bucket[pos].get_ptr();
+                                node = bucket[pos].load_non_null(Acquire);
                                 fail_count += 1;
                                 old
                             } 
                         }
                     },
                     Some(mut node_ptr) => {
-                        if atomic_markable::is_marked(node_ptr) {
+                        if TaggedAtomicPtr::has_tag(node_ptr, MARKED) {
                             // Check that doing this never breaks, ie expand_map returns a data node
                             let new_bucket_ptr = self.expand_map(bucket, pos, r);
-                            if atomic_markable::is_marked_second(new_bucket_ptr) {
+                            if TaggedAtomicPtr::has_tag(new_bucket_ptr, ARRAY) {
                                 bucket = get_bucket(new_bucket_ptr);
                                 break;
                             } else {
                                 node_ptr = new_bucket_ptr;
                             }
                         }
-                        if atomic_markable::is_marked_second(node_ptr) {
+                        if TaggedAtomicPtr::has_tag(node_ptr, ARRAY) {
                             bucket = get_bucket(node_ptr);
                             break;
                         } else {
                             self.manager.protect(node_ptr, 0);
-                            let node2 = bucket[pos].get_ptr();
+                            let node2 = bucket[pos].load_non_null(Acquire);
                             if node2 != node {
                                 node = node2;
                                 fail_count += 1;
@@ -196,10 +220,10 @@ impl<K: Hash + Send, V: Send> HashMap<K, V> {
                                 if data_node.hash == hash {
                                     return Err((key, value))
                                 }
-                                match bucket[pos].compare_and_mark(node_ptr) {
+                                match bucket[pos].compare_and_set_tag(node_ptr, MARKED, Release, Relaxed) {
                                     Ok(_) => {
                                         let new_ptr = self.expand_map(bucket, pos, r);
-                                        if atomic_markable::is_marked_second(new_ptr) {
+                                        if TaggedAtomicPtr::has_tag(new_ptr, ARRAY) {
                                             bucket = get_bucket(new_ptr);
                                             break;
                                         } else {
@@ -207,7 +231,7 @@ impl<K: Hash + Send, V: Send> HashMap<K, V> {
                                         }
                                     },
                                     Err(current) => {
-                                        if atomic_markable::is_marked_second(current) {
+                                        if TaggedAtomicPtr::has_tag(current, ARRAY) {
                                             bucket = get_bucket(current);
                                             break;
                                         } else {
@@ -224,7 +248,7 @@ impl<K: Hash + Send, V: Send> HashMap<K, V> {
             r += self.shift_step;
         }
         let pos = mut_hash as usize & (CHILD_SIZE - 1);
-        let node = bucket[pos].get_ptr();
+        let node = bucket[pos].load_non_null(Acquire);
         return match node {
             None => {
                 match self.try_insert(&bucket[pos], ptr::null_mut(), hash, value) {
@@ -262,33 +286,34 @@ impl<K: Hash + Send, V: Send> HashMap<K, V> {
         while r < (KEY_SIZE - self.shift_step) {
             let pos = mut_hash as usize & (bucket.len() - 1);
             mut_hash >>= self.shift_step;
-            let mut node = bucket[pos].get_ptr();
+            let mut node = bucket[pos].load_non_null(Acquire);
 
             match node {
                 None => { return None; }
                 Some(mut node_ptr) => {
-                    if atomic_markable::is_marked(node_ptr) {
+                    if TaggedAtomicPtr::has_tag(node_ptr, MARKED) {
                         let new_bucket_ptr = self.expand_map(bucket, pos, r);
                         node_ptr = new_bucket_ptr;
                     }
-                    if atomic_markable::is_marked_second(node_ptr) {
+                    if TaggedAtomicPtr::has_tag(node_ptr, ARRAY) {
                         bucket = get_bucket(node_ptr);
                         r += self.shift_step;
                         continue;
                     } else {
-                        self.manager.protect(atomic_markable::unmark(node_ptr), 0);
+                        self.manager.protect(TaggedAtomicPtr::without_tag(node_ptr, MARKED), 0);
                         // Check the hazard pointer
-                        if node != bucket[pos].get_ptr() {
+                        if node != bucket[pos].load_non_null(Acquire) {
                             let mut fail_count = 0;
-                            while node != bucket[pos].get_ptr() {
-                                node = bucket[pos].get_ptr();
+                            while node != bucket[pos].load_non_null(Acquire) {
+                                node = bucket[pos].load_non_null(Acquire);
                                 match node {
                                     None => { return None },
                                     Some(new_ptr) => {
-                                        self.manager.protect(atomic_markable::unmark(atomic_markable::unmark_second(new_ptr)), 0);
+                                        self.manager.protect(TaggedAtomicPtr::without_tag(TaggedAtomicPtr::without_tag(new_ptr, ARRAY), MARKED), 0);
                                         fail_count += 1;
                                         if fail_count > MAX_FAILURES {
-                                            bucket[pos].mark();
+                                            self.stats.escalation();
+                                            mark(&bucket[pos]);
                                             // Force a bucket update
                                             //println!("hello");
                                             node_ptr = self.expand_map(bucket, pos, r);
@@ -301,11 +326,11 @@ impl<K: Hash + Send, V: Send> HashMap<K, V> {
                                 }            
                             }
                             // Hazard pointer should be fine now
-                            if atomic_markable::is_marked(node_ptr) {
+                            if TaggedAtomicPtr::has_tag(node_ptr, MARKED) {
                                 bucket = get_bucket(self.expand_map(bucket, pos, r));
                                 r += self.shift_step;
                                 continue;
-                            } else if atomic_markable::is_marked_second(node_ptr) {
+                            } else if TaggedAtomicPtr::has_tag(node_ptr, ARRAY) {
                                 bucket = get_bucket(node_ptr);
                                 r += self.shift_step;
                                 continue;
@@ -313,7 +338,7 @@ impl<K: Hash + Send, V: Send> HashMap<K, V> {
                         }
                         let data_node = get_data_node(node_ptr);
                         if data_node.hash == hash {
-                            let hp_handle = self.manager.protect_dynamic(atomic_markable::unmark(node_ptr));
+                            let hp_handle = self.manager.protect_dynamic(TaggedAtomicPtr::without_tag(node_ptr, MARKED));
                             self.manager.unprotect(0);
                             return match data_node.value {
                                 None => None, // The node has already been deleted
@@ -329,12 +354,12 @@ impl<K: Hash + Send, V: Send> HashMap<K, V> {
         }
         // We should only be here if we got to the bottom
         let pos = mut_hash as usize & (CHILD_SIZE - 1);
-        if let Some(node_ptr) = bucket[pos].get_ptr() {
+        if let Some(node_ptr) = bucket[pos].load_non_null(Acquire) {
             unsafe {
                 match &*node_ptr {
                     &Node::Array(_) => panic!("Unexpected array node!"),
                     &Node::Data(ref data_node) => {
-                        let hp_handle = self.manager.protect_dynamic(atomic_markable::unmark(node_ptr));
+                        let hp_handle = self.manager.protect_dynamic(TaggedAtomicPtr::without_tag(node_ptr, MARKED));
                         self.manager.unprotect(0);
                         return match data_node.value {
                             None => None, // The node has already been removed
@@ -349,11 +374,11 @@ impl<K: Hash + Send, V: Send> HashMap<K, V> {
         }
     }
 
-    fn try_insert(&self, position: &AtomicMarkablePtr<Node<K, V>>, old: *mut Node<K, V>, hash: u64, value: V) -> Result<(), V> {
+    fn try_insert(&self, position: &TaggedAtomicPtr<Node<K, V>>, old: *mut Node<K, V>, hash: u64, value: V) -> Result<(), V> {
         let data_node: DataNode<K, V> = DataNode::new(value, hash);
         let data_node_ptr = Box::into_raw(Box::new(Node::Data(data_node)));
 
-        return match position.compare_exchange(old, data_node_ptr) {
+        return match position.compare_exchange(old, data_node_ptr, Release, Relaxed) {
             Ok(_) => Ok(()),
             Err(_) => {
                 unsafe {
@@ -402,32 +427,33 @@ impl<K: Hash + Send, V: Send> HashMap<K, V> {
         while r < (KEY_SIZE - self.shift_step) {
             let pos = mut_hash as usize & (bucket.len() - 1);
             mut_hash >>= self.shift_step;
-            let mut node = bucket[pos].get_ptr();
+            let mut node = bucket[pos].load_non_null(Acquire);
 
             match node {
                 None => { return Err(new) },
                 Some(mut node_ptr) => {
-                    if atomic_markable::is_marked(node_ptr) {
+                    if TaggedAtomicPtr::has_tag(node_ptr, MARKED) {
                         let new_bucket_ptr = self.expand_map(bucket, pos, r);
                         node_ptr = new_bucket_ptr;
                     }
-                    if atomic_markable::is_marked_second(node_ptr) {
+                    if TaggedAtomicPtr::has_tag(node_ptr, ARRAY) {
                         bucket = get_bucket(node_ptr);
                         r += self.shift_step;
                         continue;
                     } else {
-                        self.manager.protect(atomic_markable::unmark(node_ptr), 0);
-                        if node != bucket[pos].get_ptr() {
+                        self.manager.protect(TaggedAtomicPtr::without_tag(node_ptr, MARKED), 0);
+                        if node != bucket[pos].load_non_null(Acquire) {
                             let mut fail_count = 0;
-                            while node != bucket[pos].get_ptr() {
-                                node = bucket[pos].get_ptr();
+                            while node != bucket[pos].load_non_null(Acquire) {
+                                node = bucket[pos].load_non_null(Acquire);
                                 match node {
                                     None => { return Err(new); },
                                     Some(new_ptr) => {
-                                        self.manager.protect(atomic_markable::unmark(atomic_markable::unmark_second(new_ptr)), 0);
+                                        self.manager.protect(TaggedAtomicPtr::without_tag(TaggedAtomicPtr::without_tag(new_ptr, ARRAY), MARKED), 0);
                                         fail_count += 1;
                                         if fail_count > MAX_FAILURES {
-                                            bucket[pos].mark();
+                                            self.stats.escalation();
+                                            mark(&bucket[pos]);
                                             // Force a bucket update
                                             bucket = get_bucket(self.expand_map(bucket, pos, r));
                                             break;
@@ -436,11 +462,11 @@ impl<K: Hash + Send, V: Send> HashMap<K, V> {
                                     }
                                 }
                             }
-                            if atomic_markable::is_marked_second(node_ptr) {
+                            if TaggedAtomicPtr::has_tag(node_ptr, ARRAY) {
                                 bucket = get_bucket(node_ptr);
                                 r += self.shift_step;
                                 continue;
-                            } else if atomic_markable::is_marked(node_ptr) {
+                            } else if TaggedAtomicPtr::has_tag(node_ptr, MARKED) {
                                 bucket = get_bucket(self.expand_map(bucket, pos, r));
                                 r += self.shift_step;
                                 continue;
@@ -458,11 +484,11 @@ impl<K: Hash + Send, V: Send> HashMap<K, V> {
                                     return Ok(()) 
                                 },
                                 Err((value, current_ptr)) => {
-                                    if atomic_markable::is_marked_second(current_ptr) {
+                                    if TaggedAtomicPtr::has_tag(current_ptr, ARRAY) {
                                         bucket = get_bucket(current_ptr);
                                         value
-                                    } else if atomic_markable::is_marked(current_ptr) &&
-                                              ptr::eq(node_ptr, atomic_markable::unmark(current_ptr)) 
+                                    } else if TaggedAtomicPtr::has_tag(current_ptr, MARKED) &&
+                                              ptr::eq(node_ptr, TaggedAtomicPtr::without_tag(current_ptr, MARKED)) 
                                     {
                                         bucket = get_bucket(self.expand_map(bucket, pos, r));
                                         value
@@ -482,7 +508,7 @@ impl<K: Hash + Send, V: Send> HashMap<K, V> {
         
         // Since we are at the bottom of the tree, we can only have data nodes here
         let pos = mut_hash as usize & (CHILD_SIZE - 1);
-        let node = bucket[pos].get_ptr();
+        let node = bucket[pos].load_non_null(Acquire);
         match node {
             None => { Err(new) },
             Some(node_ptr) => {
@@ -504,11 +530,11 @@ impl<K: Hash + Send, V: Send> HashMap<K, V> {
         }
     }
 
-    fn try_update(&self, position: &AtomicMarkablePtr<Node<K, V>>, old: *mut Node<K, V>, hash: u64, value: V) -> Result<(), (V, *mut Node<K, V>)> {
+    fn try_update(&self, position: &TaggedAtomicPtr<Node<K, V>>, old: *mut Node<K, V>, hash: u64, value: V) -> Result<(), (V, *mut Node<K, V>)> {
         let new_data_node: DataNode<K, V> = DataNode::new(value, hash);
         let data_node_ptr = Box::into_raw(Box::new(Node::Data(new_data_node)));
 
-        match position.compare_exchange(old, data_node_ptr) {
+        match position.compare_exchange(old, data_node_ptr, Release, Relaxed) {
             Ok(_) => Ok(()),
             Err(current) => {
                 unsafe {
@@ -549,28 +575,29 @@ impl<K: Hash + Send, V: Send> HashMap<K, V> {
         while r < (KEY_SIZE - self.shift_step) {
             let pos = mut_hash as usize & (bucket.len() - 1);
             mut_hash >>= self.shift_step;
-            let mut node = bucket[pos].get_ptr();
+            let mut node = bucket[pos].load_non_null(Acquire);
 
             match node {
                 None => { return None; },
                 Some(mut node_ptr) => {
-                    if atomic_markable::is_marked_second(node_ptr) {
+                    if TaggedAtomicPtr::has_tag(node_ptr, ARRAY) {
                         bucket = get_bucket(node_ptr);
-                    } else if atomic_markable::is_marked(node_ptr) {
+                    } else if TaggedAtomicPtr::has_tag(node_ptr, MARKED) {
                         bucket = get_bucket(self.expand_map(bucket, pos, r));
                     } else {
-                        self.manager.protect(atomic_markable::unmark(node_ptr), 0);
-                        if node != bucket[pos].get_ptr() {
+                        self.manager.protect(TaggedAtomicPtr::without_tag(node_ptr, MARKED), 0);
+                        if node != bucket[pos].load_non_null(Acquire) {
                             let mut fail_count = 0;
-                            while node != bucket[pos].get_ptr() {
-                                node = bucket[pos].get_ptr();
+                            while node != bucket[pos].load_non_null(Acquire) {
+                                node = bucket[pos].load_non_null(Acquire);
                                 match node {
                                     None => { return None; },
                                     Some(new_ptr) => {
-                                        self.manager.protect(atomic_markable::unmark(atomic_markable::unmark_second(new_ptr)), 0);
+                                        self.manager.protect(TaggedAtomicPtr::without_tag(TaggedAtomicPtr::without_tag(new_ptr, ARRAY), MARKED), 0);
                                         fail_count += 1;
                                         if fail_count > MAX_FAILURES {
-                                            bucket[pos].mark();
+                                            self.stats.escalation();
+                                            mark(&bucket[pos]);
                                             // Force a bucket update
                                             bucket = get_bucket(self.expand_map(bucket, pos, r));
                                             continue;
@@ -580,11 +607,11 @@ impl<K: Hash + Send, V: Send> HashMap<K, V> {
                                 }
                             }
                             // Hazard pointer is safe here
-                            if atomic_markable::is_marked_second(node_ptr) {
+                            if TaggedAtomicPtr::has_tag(node_ptr, ARRAY) {
                                 bucket = get_bucket(node_ptr);
                                 r += self.shift_step;
                                 continue;
-                            } else if atomic_markable::is_marked(node_ptr) {
+                            } else if TaggedAtomicPtr::has_tag(node_ptr, MARKED) {
                                 bucket = get_bucket(self.expand_map(bucket, pos, r));
                                 r += self.shift_step;
                                 continue;
@@ -611,10 +638,10 @@ impl<K: Hash + Send, V: Send> HashMap<K, V> {
                                     }
                                 },
                                 Err(current) => {
-                                    if atomic_markable::is_marked_second(current) {
+                                    if TaggedAtomicPtr::has_tag(current, ARRAY) {
                                         bucket = get_bucket(current);
-                                    } else if atomic_markable::is_marked(current)
-                                        && ptr::eq(atomic_markable::unmark(current), node_ptr) 
+                                    } else if TaggedAtomicPtr::has_tag(current, MARKED)
+                                        && ptr::eq(TaggedAtomicPtr::without_tag(current, MARKED), node_ptr) 
                                     {
                                         bucket = get_bucket(self.expand_map(bucket, pos, r));
                                     } else {
@@ -631,7 +658,7 @@ impl<K: Hash + Send, V: Send> HashMap<K, V> {
             r += self.shift_step;
         }
         let pos = mut_hash as usize & (bucket.len() - 1);
-        let node = bucket[pos].get_ptr();
+        let node = bucket[pos].load_non_null(Acquire);
         match node {
             None => None,
             Some(node_ptr) => {
@@ -683,15 +710,15 @@ impl<K: Hash + Send, V: Send> HashMap<K, V> {
         while r < (KEY_SIZE - self.shift_step) {
             let pos = mut_hash as usize & (bucket.len() - 1);
             mut_hash >>= self.shift_step;
-            let mut node = bucket[pos].get_ptr();
+            let mut node = bucket[pos].load_non_null(Acquire);
 
             match node {
                 None => { return None; }
                 Some(mut node_ptr) => {
-                    if atomic_markable::is_marked(node_ptr) {
+                    if TaggedAtomicPtr::has_tag(node_ptr, MARKED) {
                         let new_bucket_ptr = self.expand_map(bucket, pos, r);
                         node_ptr = new_bucket_ptr;
-                        /* if atomic_markable::is_marked_second(new_bucket_ptr) {
+                        /* if TaggedAtomicPtr::has_tag(new_bucket_ptr, ARRAY) {
                             //println!("hello 1: {:b}", new_bucket_ptr as usize);
                             bucket = get_bucket(new_bucket_ptr);
                             //println!("fart");
@@ -701,24 +728,25 @@ impl<K: Hash + Send, V: Send> HashMap<K, V> {
                             //println!("fart");
                         } */
                     }
-                    if atomic_markable::is_marked_second(node_ptr) {
+                    if TaggedAtomicPtr::has_tag(node_ptr, ARRAY) {
                         bucket = get_bucket(node_ptr);
                         r += self.shift_step;
                         continue;
                     } else {
-                        self.manager.protect(atomic_markable::unmark(node_ptr), 0);
+                        self.manager.protect(TaggedAtomicPtr::without_tag(node_ptr, MARKED), 0);
                         // Check the hazard pointer
-                        if node != bucket[pos].get_ptr() {
+                        if node != bucket[pos].load_non_null(Acquire) {
                             let mut fail_count = 0;
-                            while node != bucket[pos].get_ptr() {
-                                node = bucket[pos].get_ptr();
+                            while node != bucket[pos].load_non_null(Acquire) {
+                                node = bucket[pos].load_non_null(Acquire);
                                 match node {
                                     None => { return None },
                                     Some(new_ptr) => {
-                                        self.manager.protect(atomic_markable::unmark(atomic_markable::unmark_second(new_ptr)), 0);
+                                        self.manager.protect(TaggedAtomicPtr::without_tag(TaggedAtomicPtr::without_tag(new_ptr, ARRAY), MARKED), 0);
                                         fail_count += 1;
                                         if fail_count > MAX_FAILURES {
-                                            bucket[pos].mark();
+                                            self.stats.escalation();
+                                            mark(&bucket[pos]);
                                             // Force a bucket update
                                             //println!("hello");
                                             node_ptr = self.expand_map(bucket, pos, r);
@@ -731,11 +759,11 @@ impl<K: Hash + Send, V: Send> HashMap<K, V> {
                                 }            
                             }
                             // Hazard pointer should be fine now
-                            if atomic_markable::is_marked(node_ptr) {
+                            if TaggedAtomicPtr::has_tag(node_ptr, MARKED) {
                                 bucket = get_bucket(self.expand_map(bucket, pos, r));
                                 r += self.shift_step;
                                 continue;
-                            } else if atomic_markable::is_marked_second(node_ptr) {
+                            } else if TaggedAtomicPtr::has_tag(node_ptr, ARRAY) {
                                 bucket = get_bucket(node_ptr);
                                 r += self.shift_step;
                                 continue;
@@ -753,7 +781,7 @@ impl<K: Hash + Send, V: Send> HashMap<K, V> {
         }
         // We should only be here if we got to the bottom
         let pos = mut_hash as usize & (CHILD_SIZE - 1);
-        if let Some(node_ptr) = bucket[pos].get_ptr() {
+        if let Some(node_ptr) = bucket[pos].load_non_null(Acquire) {
             unsafe {
                 match &*node_ptr {
                     &Node::Array(_) => panic!("Unexpected array node!"),
@@ -767,8 +795,8 @@ impl<K: Hash + Send, V: Send> HashMap<K, V> {
         }
     }
 
-    fn try_remove(&self, position: &AtomicMarkablePtr<Node<K, V>>, old: *mut Node<K, V>) -> Result<(), *mut Node<K, V>> {
-        match position.compare_exchange(old, ptr::null_mut()) {
+    fn try_remove(&self, position: &TaggedAtomicPtr<Node<K, V>>, old: *mut Node<K, V>) -> Result<(), *mut Node<K, V>> {
+        match position.compare_exchange(old, ptr::null_mut(), Release, Relaxed) {
             Ok(_) => Ok(()),
             Err(current) => Err(current)
         }
@@ -780,9 +808,31 @@ impl<K: Hash + Send, V: Send> HashMap<K, V> {
     }
 }
 
-fn get_bucket<'a, K: Send, V: Send>(node_ptr: *mut Node<K, V>) -> &'a Vec<AtomicMarkablePtr<Node<K, V>>> {
+/// Mark the given slot so that its data node is moved into a new array node, unless it
+/// already points to an array node.
+fn mark<K: Send, V: Send>(position: &TaggedAtomicPtr<Node<K, V>>) {
+    let _ = position.fetch_update(Release, Acquire, |ptr| {
+        if TaggedAtomicPtr::has_tag(ptr, ARRAY) { None } else { Some(TaggedAtomicPtr::with_tag(ptr, MARKED)) }
+    });
+}
+
+/// Free every node reachable from the given bucket, descending into array nodes.
+fn free_bucket<K: Send, V: Send>(bucket: &Vec<TaggedAtomicPtr<Node<K, V>>>) {
+    for position in bucket {
+        let node_ptr = position.load(Relaxed);
+        if TaggedAtomicPtr::has_tag(node_ptr, ARRAY) {
+            free_bucket(get_bucket(node_ptr));
+        }
+        let node_ptr = TaggedAtomicPtr::untagged(node_ptr);
+        if !node_ptr.is_null() {
+            unsafe { Box::from_raw(node_ptr) };
+        }
+    }
+}
+
+fn get_bucket<'a, K: Send, V: Send>(node_ptr: *mut Node<K, V>) -> &'a Vec<TaggedAtomicPtr<Node<K, V>>> {
     unsafe {
-        match &*(atomic_markable::unmark_second(node_ptr)) {
+        match &*(TaggedAtomicPtr::without_tag(node_ptr, ARRAY)) {
             &Node::Data(_) => panic!("Unexpected data node!: {:b}", node_ptr as usize),
             &Node::Array(ref array_node) => &array_node.array
         }
@@ -791,7 +841,7 @@ fn get_bucket<'a, K: Send, V: Send>(node_ptr: *mut Node<K, V>) -> &'a Vec<Atomic
 
 fn get_data_node<'a, K: Send, V: Send>(node_ptr: *mut Node<K, V>) -> &'a DataNode<K, V> {
     unsafe {
-        match &*(atomic_markable::unmark(node_ptr)) {
+        match &*(TaggedAtomicPtr::without_tag(node_ptr, MARKED)) {
             &Node::Data(ref data_node) => data_node,
             &Node::Array(_) => panic!("Unexpected array node!: {:b}", node_ptr as usize)
         }
@@ -807,13 +857,13 @@ where K: PartialEq + Hash + Send + Debug,
         let mut string = "".to_owned();
         let mut none_count = 0;
         for node in &self.head {
-            if let Some(mut node_ptr) = node.get_ptr() {
+            if let Some(mut node_ptr) = node.load_non_null(Acquire) {
                 string.push_str("\n");
                 if none_count > 0 {
                     string.push_str(&format!("None x {}\n", none_count));
                     none_count = 0;
                 }
-                node_ptr = atomic_markable::unmark_second(atomic_markable::unmark(node_ptr));
+                node_ptr = TaggedAtomicPtr::without_tag(TaggedAtomicPtr::without_tag(node_ptr, MARKED), ARRAY);
                 unsafe {
                     match &*node_ptr {
                         &Node::Array(ref array_node) => {array_node.to_string(&mut string, 1);},
@@ -833,14 +883,14 @@ where K: PartialEq + Hash + Send + Debug,
 }
 
 pub struct Iter<'a, K: Send + 'a, V: Send + 'a> {
-    current_array: &'a Vec<AtomicMarkablePtr<Node<K, V>>>,
+    current_array: &'a Vec<TaggedAtomicPtr<Node<K, V>>>,
     index: usize,
-    node_stack: Vec<&'a Vec<AtomicMarkablePtr<Node<K, V>>>>,
+    node_stack: Vec<&'a Vec<TaggedAtomicPtr<Node<K, V>>>>,
     manager: &'a HPBRManager<Node<K, V>>
 }
 
 impl<'a, K: Send, V: Send> Iter<'a, K, V> {
-    fn new(start: &'a Vec<AtomicMarkablePtr<Node<K, V>>>, manager: &'a HPBRManager<Node<K, V>>) -> Self {
+    fn new(start: &'a Vec<TaggedAtomicPtr<Node<K, V>>>, manager: &'a HPBRManager<Node<K, V>>) -> Self {
         Self {
             current_array: start,
             index: 0,
@@ -857,20 +907,20 @@ impl<'a, K: Send, V: Send> Iterator for Iter<'a, K, V> {
         self.index += 1;
         if index < self.current_array.len() {
             // Check if data or array
-            match self.current_array[index].get_ptr() {
+            match self.current_array[index].load_non_null(Acquire) {
                 Some(mut node_ptr) => {
                     // Protect with a HPHandle
-                    if atomic_markable::is_marked(node_ptr) {
+                    if TaggedAtomicPtr::has_tag(node_ptr, MARKED) {
                         // Protect
-                        let mut hphandle = self.manager.protect_dynamic(atomic_markable::unmark(node_ptr));
+                        let mut hphandle = self.manager.protect_dynamic(TaggedAtomicPtr::without_tag(node_ptr, MARKED));
                         // need to loop here
-                        while Some(node_ptr) != self.current_array[index].get_ptr() {
-                            let new_node = self.current_array[index].get_ptr();
+                        while Some(node_ptr) != self.current_array[index].load_non_null(Acquire) {
+                            let new_node = self.current_array[index].load_non_null(Acquire);
                             match new_node {
                                 None => return self.next(),
                                 Some(new_ptr) => {
-                                    hphandle = self.manager.protect_dynamic(atomic_markable::unmark(atomic_markable::unmark_second(node_ptr)));
-                                    if atomic_markable::is_marked_second(new_ptr) {
+                                    hphandle = self.manager.protect_dynamic(TaggedAtomicPtr::without_tag(TaggedAtomicPtr::without_tag(node_ptr, ARRAY), MARKED));
+                                    if TaggedAtomicPtr::has_tag(new_ptr, ARRAY) {
                                         let bucket = get_bucket(new_ptr);
                                         self.node_stack.push(bucket);
                                         return self.next()
@@ -879,21 +929,21 @@ impl<'a, K: Send, V: Send> Iterator for Iter<'a, K, V> {
                                 }
                             }
                         }
-                        let data_node = get_data_node(atomic_markable::unmark(node_ptr));
+                        let data_node = get_data_node(TaggedAtomicPtr::without_tag(node_ptr, MARKED));
                         Some(DataGuard::new(&data_node.value.as_ref().unwrap(), hphandle))
-                    } else if atomic_markable::is_marked_second(node_ptr) {
+                    } else if TaggedAtomicPtr::has_tag(node_ptr, ARRAY) {
                         let bucket = get_bucket(node_ptr);
                         self.node_stack.push(bucket);
                         return self.next()
                     } else {
                         let mut hphandle = self.manager.protect_dynamic(node_ptr);
-                        while Some(node_ptr) != self.current_array[index].get_ptr() {
-                            let new_node = self.current_array[index].get_ptr();
+                        while Some(node_ptr) != self.current_array[index].load_non_null(Acquire) {
+                            let new_node = self.current_array[index].load_non_null(Acquire);
                             match new_node {
                                 None => return self.next(),
                                 Some(new_ptr) => {
-                                    hphandle = self.manager.protect_dynamic(atomic_markable::unmark(atomic_markable::unmark_second(node_ptr)));
-                                    if atomic_markable::is_marked_second(new_ptr) {
+                                    hphandle = self.manager.protect_dynamic(TaggedAtomicPtr::without_tag(TaggedAtomicPtr::without_tag(node_ptr, ARRAY), MARKED));
+                                    if TaggedAtomicPtr::has_tag(new_ptr, ARRAY) {
                                         let bucket = get_bucket(new_ptr);
                                         self.node_stack.push(bucket);
                                         return self.next()
@@ -903,7 +953,7 @@ impl<'a, K: Send, V: Send> Iterator for Iter<'a, K, V> {
                             }
                         }
 
-                        let data_node = get_data_node(atomic_markable::unmark(node_ptr));
+                        let data_node = get_data_node(TaggedAtomicPtr::without_tag(node_ptr, MARKED));
                         Some(DataGuard::new(&data_node.value.as_ref().unwrap(), hphandle))
                     }
                 },
@@ -934,6 +984,14 @@ where K: PartialEq + Hash + Send,
     }
 }
 
+impl<K: Send, V: Send> Drop for HashMap<K, V> {
+    // No other thread can hold a reference to the map here, so every node left in the tree can be freed.
+    // The depth of the tree is bounded, so the recursion cannot overflow the stack.
+    fn drop(&mut self) {
+        free_bucket(&self.head);
+    }
+}
+
 pub enum Node<K: Send, V: Send> {
     Data(DataNode<K, V>),
     Array(ArrayNode<K, V>)
@@ -966,7 +1024,7 @@ impl<K: Send, V: Send> Default for DataNode<K, V> {
 }
 
 pub struct ArrayNode<K: Send, V: Send> {
-    array: Vec<AtomicMarkablePtr<Node<K, V>>>,
+    array: Vec<TaggedAtomicPtr<Node<K, V>>>,
     size: usize
 }
 
@@ -974,7 +1032,7 @@ impl<K: Send, V: Send> ArrayNode<K, V> {
     fn new(size: usize) -> Self {
         let mut array = Vec::with_capacity(size);
         for _ in 0..size {
-            array.push(AtomicMarkablePtr::default());
+            array.push(TaggedAtomicPtr::default());
         }
 
         ArrayNode {
@@ -994,7 +1052,7 @@ impl<K: Send, V: Send> ArrayNode<K, V> {
         }
         start.push_str("ArrayNode: ");
         for markable in &self.array {
-            if let Some(mut node_ptr) = markable.get_ptr() {
+            if let Some(mut node_ptr) = markable.load_non_null(Acquire) {
                 start.push_str("\n");
                 for _ in 0..depth {
                     start.push_str("\t");
@@ -1006,7 +1064,7 @@ impl<K: Send, V: Send> ArrayNode<K, V> {
                     }
                     none_count = 0;
                 }
-                node_ptr = atomic_markable::unmark_second(atomic_markable::unmark(node_ptr));
+                node_ptr = TaggedAtomicPtr::without_tag(TaggedAtomicPtr::without_tag(node_ptr, MARKED), ARRAY);
                 match &*node_ptr {
                     &Node::Array(ref array_node) => {
                         array_node.to_string(start, depth + 1);
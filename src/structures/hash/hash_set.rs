@@ -1,11 +1,14 @@
 use std::hash::{Hash, Hasher, BuildHasher};
 use std::ptr;
+use std::sync::atomic::Ordering::{Acquire, Release, Relaxed};
 use std::borrow::Borrow;
 use std::collections::hash_map::RandomState;
 use std::iter::Chain;
 use memory::HPBRManager;
-use super::atomic_markable::AtomicMarkablePtr;
-use super::atomic_markable;
+use stats::Counters;
+#[cfg(feature = "stats")]
+use stats::Stats;
+use super::super::utils::TaggedAtomicPtr;
 use super::data_guard::DataGuard;
 
 const HEAD_SIZE: usize = 256;
@@ -13,6 +16,11 @@ const CHILD_SIZE: usize = 16;
 const KEY_SIZE: usize = 64;
 const MAX_FAILURES: u64 = 10;
 
+/// Tag for a slot whose data node must be moved into a new array node.
+const MARKED: usize = 0x1;
+/// Tag for a slot which points to an array node.
+const ARRAY: usize = 0x2;
+
 /// A wait-free HashSet based on a tree structure.
 ///
 /// This set is an adaptation of the Wait-Free HashMap presented in the paper [A Wait-Free HashMap]
@@ -42,11 +50,12 @@ const MAX_FAILURES: u64 = 10;
 /// that means the maximum depth is 14. This is used to justify the implementation of 
 /// recursive destructors: they should not be able to overflow the stack.
 pub struct HashSet<T: Send> {
-    head: Vec<AtomicMarkablePtr<Node<T>>>,
+    head: Vec<TaggedAtomicPtr<Node<T>>>,
     hasher: RandomState,
     head_size: usize,
     shift_step: usize,
-    manager: HPBRManager<Node<T>>
+    manager: HPBRManager<Node<T>>,
+    stats: Counters
 }
 
 impl<T: Hash + Send> HashSet<T> {
@@ -56,9 +65,9 @@ impl<T: Hash + Send> HashSet<T> {
     /// let set = HashSet::new();
     /// ```
     pub fn new() -> Self {
-        let mut head: Vec<AtomicMarkablePtr<Node<T>>> = Vec::with_capacity(HEAD_SIZE);
+        let mut head: Vec<TaggedAtomicPtr<Node<T>>> = Vec::with_capacity(HEAD_SIZE);
         for _ in 0..HEAD_SIZE {
-            head.push(AtomicMarkablePtr::default());
+            head.push(TaggedAtomicPtr::default());
         }
 
         Self {
@@ -66,10 +75,24 @@ impl<T: Hash + Send> HashSet<T> {
             hasher: RandomState::new(),
             head_size: HEAD_SIZE,
             shift_step: f64::floor((CHILD_SIZE as f64).log2()) as usize,
-            manager: HPBRManager::new(100, 1)
+            manager: HPBRManager::new(100, 1),
+            stats: Counters::new()
         }
     }
 
+    /// Get a snapshot of the contention and reclamation counters of the set. Escalations
+    /// count the operations which exceeded their failure limit and forced a bucket to expand.
+    /// # Examples
+    /// ```
+    /// let set: HashSet<u8> = HashSet::new();
+    /// set.insert(8);
+    /// println!("{:?}", set.stats());
+    /// ```
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> Stats {
+        self.stats.snapshot() + self.manager.stats()
+    }
+
     fn hash<Q: ?Sized>(&self, value: &Q) -> u64
     where T: Borrow<Q>,
           Q: Hash + Send
@@ -79,35 +102,35 @@ impl<T: Hash + Send> HashSet<T> {
         hasher.finish()
     }
 
-    fn expand(&self, bucket: &Vec<AtomicMarkablePtr<Node<T>>>, pos: usize, shift_amount:usize) -> *mut Node<T> {
-        let node = bucket[pos].get_ptr().unwrap();
-        self.manager.protect(atomic_markable::unmark(node), 0);
-        if atomic_markable::is_marked_second(node) {
+    fn expand(&self, bucket: &Vec<TaggedAtomicPtr<Node<T>>>, pos: usize, shift_amount:usize) -> *mut Node<T> {
+        let node = bucket[pos].load_non_null(Acquire).unwrap();
+        self.manager.protect(TaggedAtomicPtr::without_tag(node, MARKED), 0);
+        if TaggedAtomicPtr::has_tag(node, ARRAY) {
             return node
         }
 
-        let node2 = bucket[pos].get_ptr().unwrap();
+        let node2 = bucket[pos].load_non_null(Acquire).unwrap();
         if !ptr::eq(node, node2) {
             return node2
         }
 
         let array_node: ArrayNode<T> = ArrayNode::new(CHILD_SIZE);
-        let hash = unsafe { match &*atomic_markable::unmark(node) {
+        let hash = unsafe { match &*TaggedAtomicPtr::without_tag(node, MARKED) {
             &Node::Data(ref data_node) => data_node.hash,
             &Node::Array(_) => { panic!("Unexpected array node!") }
         }};
 
         let new_pos = (hash >> (shift_amount + self.shift_step)) as usize & (CHILD_SIZE - 1);
-        array_node.array[new_pos].store(atomic_markable::unmark(node));
+        array_node.array[new_pos].store(TaggedAtomicPtr::without_tag(node, MARKED), Release);
 
         let array_node_ptr = Box::into_raw(Box::new(Node::Array(array_node)));
-        let array_node_ptr_marked = atomic_markable::mark_second(array_node_ptr);
+        let array_node_ptr_marked = TaggedAtomicPtr::with_tag(array_node_ptr, ARRAY);
 
-        return match bucket[pos].compare_exchange(node, array_node_ptr_marked) {
+        return match bucket[pos].compare_exchange(node, array_node_ptr_marked, Release, Relaxed) {
             Ok(_) => array_node_ptr_marked,
             Err(current) => {
                 let vec = get_bucket(array_node_ptr);
-                vec[new_pos].store(ptr::null_mut());
+                vec[new_pos].store(ptr::null_mut(), Release);
                 unsafe { Box::from_raw(array_node_ptr) };
                 current
             }
@@ -131,40 +154,41 @@ impl<T: Hash + Send> HashSet<T> {
             let pos = mut_hash as usize & (bucket.len() - 1);
             mut_hash = mut_hash >> self.shift_step;
             let mut fail_count = 0;
-            let mut node = bucket[pos].get_ptr();
+            let mut node = bucket[pos].load_non_null(Acquire);
 
             loop {
                 if fail_count > MAX_FAILURES {
-                    bucket[pos].mark();
-                    node = bucket[pos].get_ptr();
+                    self.stats.escalation();
+                    mark(&bucket[pos]);
+                    node = bucket[pos].load_non_null(Acquire);
                 }
                 match node {
                     None => {
                         data = match self.try_insert(&bucket[pos], ptr::null_mut(), hash, data) {
                             Ok(()) => return Ok(()),
                             Err(old_data) => {
-                                node = bucket[pos].get_ptr();
+                                node = bucket[pos].load_non_null(Acquire);
                                 fail_count += 1;
                                 old_data
                             }
                         }
                     },
                     Some(mut node_ptr) => {
-                        if atomic_markable::is_marked(node_ptr) {
+                        if TaggedAtomicPtr::has_tag(node_ptr, MARKED) {
                             let new_bucket_ptr = self.expand(bucket, pos, r);
-                            if atomic_markable::is_marked_second(new_bucket_ptr) {
+                            if TaggedAtomicPtr::has_tag(new_bucket_ptr, ARRAY) {
                                 bucket = get_bucket(new_bucket_ptr);
                                 break;
                             } else {
                                 node_ptr = new_bucket_ptr;
                             }
                         }
-                        if atomic_markable::is_marked_second(node_ptr) {
+                        if TaggedAtomicPtr::has_tag(node_ptr, ARRAY) {
                             bucket = get_bucket(node_ptr);
                             break;
                         } else {
                             self.manager.protect(node_ptr, 0);
-                            let node2 = bucket[pos].get_ptr();
+                            let node2 = bucket[pos].load_non_null(Acquire);
                             if node2 != node {
                                 node = node2;
                                 fail_count += 1;
@@ -174,10 +198,10 @@ impl<T: Hash + Send> HashSet<T> {
                                 if data_node.hash == hash {
                                     return Err(data)
                                 }
-                                match bucket[pos].compare_and_mark(node_ptr) {
+                                match bucket[pos].compare_and_set_tag(node_ptr, MARKED, Release, Relaxed) {
                                     Ok(_) => {
                                         let new_ptr = self.expand(bucket, pos, r);
-                                        if atomic_markable::is_marked_second(new_ptr) {
+                                        if TaggedAtomicPtr::has_tag(new_ptr, ARRAY) {
                                             bucket = get_bucket(new_ptr);
                                             break;
                                         } else {
@@ -185,7 +209,7 @@ impl<T: Hash + Send> HashSet<T> {
                                         }
                                     },
                                     Err(current) => {
-                                        if atomic_markable::is_marked_second(current) {
+                                        if TaggedAtomicPtr::has_tag(current, ARRAY) {
                                             bucket = get_bucket(current);
                                             break;
                                         } else {
@@ -203,7 +227,7 @@ impl<T: Hash + Send> HashSet<T> {
         }
 
         let pos = mut_hash as usize & (CHILD_SIZE - 1);
-        let node = bucket[pos].get_ptr();
+        let node = bucket[pos].load_non_null(Acquire);
         return match node {
             None => {
                 match self.try_insert(&bucket[pos], ptr::null_mut(), hash, data) {
@@ -217,11 +241,11 @@ impl<T: Hash + Send> HashSet<T> {
         }
     }
 
-    fn try_insert(&self, position: &AtomicMarkablePtr<Node<T>>, old: *mut Node<T>, hash: u64, value: T) -> Result<(), T> {
+    fn try_insert(&self, position: &TaggedAtomicPtr<Node<T>>, old: *mut Node<T>, hash: u64, value: T) -> Result<(), T> {
         let data_node = DataNode::new(value, hash);
         let data_node_ptr = Box::into_raw(Box::new(Node::Data(data_node)));
 
-        return match position.compare_exchange(old, data_node_ptr) {
+        return match position.compare_exchange(old, data_node_ptr, Release, Relaxed) {
             Ok(_) => Ok(()),
             Err(_) => {
                 unsafe {
@@ -257,32 +281,33 @@ impl<T: Hash + Send> HashSet<T> {
         while r < KEY_SIZE - self.shift_step {
             let pos = mut_hash as usize & (bucket.len() - 1);
             mut_hash >>= self.shift_step;
-            let mut node = bucket[pos].get_ptr();
+            let mut node = bucket[pos].load_non_null(Acquire);
 
             match node {
                 None => { return false },
                 Some(mut node_ptr) => {
-                    if atomic_markable::is_marked(node_ptr) {
+                    if TaggedAtomicPtr::has_tag(node_ptr, MARKED) {
                         let new_bucket_ptr = self.expand(bucket, pos, r);
                         node_ptr = new_bucket_ptr;
                     }
-                    if atomic_markable::is_marked_second(node_ptr) {
+                    if TaggedAtomicPtr::has_tag(node_ptr, ARRAY) {
                         bucket = get_bucket(node_ptr);
                         r += self.shift_step;
                         continue;
                     } else {
-                        self.manager.protect(atomic_markable::unmark(node_ptr), 0);
-                        if node != bucket[pos].get_ptr() {
+                        self.manager.protect(TaggedAtomicPtr::without_tag(node_ptr, MARKED), 0);
+                        if node != bucket[pos].load_non_null(Acquire) {
                             let mut fail_count = 0;
-                            while node != bucket[pos].get_ptr() {
-                                node = bucket[pos].get_ptr();
+                            while node != bucket[pos].load_non_null(Acquire) {
+                                node = bucket[pos].load_non_null(Acquire);
                                 match node {
                                     None => return false,
                                     Some(new_ptr) => {
-                                        self.manager.protect(atomic_markable::unmark(atomic_markable::unmark_second(new_ptr)), 0);
+                                        self.manager.protect(TaggedAtomicPtr::without_tag(TaggedAtomicPtr::without_tag(new_ptr, ARRAY), MARKED), 0);
                                         fail_count += 1;
                                         if fail_count > MAX_FAILURES {
-                                            bucket[pos].mark();
+                                            self.stats.escalation();
+                                            mark(&bucket[pos]);
                                             node_ptr = self.expand(bucket, pos, r);
                                             bucket = get_bucket(node_ptr);
                                             break;
@@ -291,11 +316,11 @@ impl<T: Hash + Send> HashSet<T> {
                                     }
                                 }
                             }
-                            if atomic_markable::is_marked(node_ptr) {
+                            if TaggedAtomicPtr::has_tag(node_ptr, MARKED) {
                                 bucket = get_bucket(self.expand(bucket, pos, r));
                                 r += self.shift_step;
                                 continue;
-                            } else if atomic_markable::is_marked_second(node_ptr) {
+                            } else if TaggedAtomicPtr::has_tag(node_ptr, ARRAY) {
                                 bucket = get_bucket(node_ptr);
                                 r += self.shift_step;
                                 continue;
@@ -309,7 +334,7 @@ impl<T: Hash + Send> HashSet<T> {
         }
 
         let pos = mut_hash as usize & (CHILD_SIZE - 1);
-        if let Some(node_ptr) = bucket[pos].get_ptr() {
+        if let Some(node_ptr) = bucket[pos].load_non_null(Acquire) {
             match unsafe { &*node_ptr } {
                 &Node::Array(_) => panic!("Unexpected array node!"),
                 &Node::Data(ref data_node) => {
@@ -343,28 +368,29 @@ impl<T: Hash + Send> HashSet<T> {
         while r < KEY_SIZE - self.shift_step {
             let pos = mut_hash as usize & (bucket.len() - 1);
             mut_hash >>= self.shift_step;
-            let mut node = bucket[pos].get_ptr();
+            let mut node = bucket[pos].load_non_null(Acquire);
 
             match node {
                 None => return None,
                 Some(mut node_ptr) => {
-                    if atomic_markable::is_marked_second(node_ptr) {
+                    if TaggedAtomicPtr::has_tag(node_ptr, ARRAY) {
                         bucket = get_bucket(node_ptr);
-                    } else if atomic_markable::is_marked(node_ptr) {
+                    } else if TaggedAtomicPtr::has_tag(node_ptr, MARKED) {
                         bucket = get_bucket(self.expand(bucket, pos, r));
                     } else {
-                        self.manager.protect(atomic_markable::unmark(node_ptr), 0);
-                        if node != bucket[pos].get_ptr() {
+                        self.manager.protect(TaggedAtomicPtr::without_tag(node_ptr, MARKED), 0);
+                        if node != bucket[pos].load_non_null(Acquire) {
                             let mut fail_count = 0;
-                            while node != bucket[pos].get_ptr() {
-                                node = bucket[pos].get_ptr();
+                            while node != bucket[pos].load_non_null(Acquire) {
+                                node = bucket[pos].load_non_null(Acquire);
                                 match node {
                                     None => return None,
                                     Some(new_ptr) => {
-                                        self.manager.protect(atomic_markable::unmark(atomic_markable::unmark_second(new_ptr)), 0);
+                                        self.manager.protect(TaggedAtomicPtr::without_tag(TaggedAtomicPtr::without_tag(new_ptr, ARRAY), MARKED), 0);
                                         fail_count += 1;
                                         if fail_count > MAX_FAILURES {
-                                            bucket[pos].mark();
+                                            self.stats.escalation();
+                                            mark(&bucket[pos]);
                                             bucket = get_bucket(self.expand(bucket, pos, r));
                                             continue;
                                         }
@@ -372,11 +398,11 @@ impl<T: Hash + Send> HashSet<T> {
                                     }
                                 }
                             }
-                            if atomic_markable::is_marked_second(node_ptr) {
+                            if TaggedAtomicPtr::has_tag(node_ptr, ARRAY) {
                                 bucket = get_bucket(node_ptr);
                                 r += self.shift_step;
                                 continue;
-                            } else if atomic_markable::is_marked(node_ptr) {
+                            } else if TaggedAtomicPtr::has_tag(node_ptr, MARKED) {
                                 bucket = get_bucket(self.expand(bucket, pos, r));
                                 r += self.shift_step;
                                 continue;
@@ -387,9 +413,9 @@ impl<T: Hash + Send> HashSet<T> {
                             match self.try_remove(&bucket[pos], node_ptr) {
                                 Ok(val) => return val,
                                 Err(current) => {
-                                    if atomic_markable::is_marked_second(current) {
+                                    if TaggedAtomicPtr::has_tag(current, ARRAY) {
                                         bucket = get_bucket(current);
-                                    } else if atomic_markable::is_marked(current) && ptr::eq(atomic_markable::unmark(current), node_ptr) {
+                                    } else if TaggedAtomicPtr::has_tag(current, MARKED) && ptr::eq(TaggedAtomicPtr::without_tag(current, MARKED), node_ptr) {
                                         bucket = get_bucket(self.expand(bucket, pos, r));
                                     } else {
                                         return None
@@ -407,7 +433,7 @@ impl<T: Hash + Send> HashSet<T> {
         }
 
         let pos = mut_hash as usize & (bucket.len() - 1);
-        let node = bucket[pos].get_ptr();
+        let node = bucket[pos].load_non_null(Acquire);
         match node {
             None => None,
             Some(node_ptr) => {
@@ -424,8 +450,8 @@ impl<T: Hash + Send> HashSet<T> {
         }
     }
 
-    fn try_remove(&self, position: &AtomicMarkablePtr<Node<T>>, old: *mut Node<T>) -> Result<Option<T>, *mut Node<T>> {
-        match position.compare_exchange(old, ptr::null_mut()) {
+    fn try_remove(&self, position: &TaggedAtomicPtr<Node<T>>, old: *mut Node<T>) -> Result<Option<T>, *mut Node<T>> {
+        match position.compare_exchange(old, ptr::null_mut(), Release, Relaxed) {
             Ok(_) => {
                 let owned = unsafe { ptr::replace(old, Node::Data(DataNode::default())) };
                 if let Node::Data(node) = owned {
@@ -471,9 +497,39 @@ impl<T: Hash + Send> HashSet<T> {
     }
 }
 
-fn get_bucket<'a, T: Send>(node_ptr: *mut Node<T>) -> &'a Vec<AtomicMarkablePtr<Node<T>>> {
+impl<T: Send> Drop for HashSet<T> {
+    // No other thread can hold a reference to the set here, so every node left in the tree can be freed.
+    // The depth of the tree is bounded, so the recursion cannot overflow the stack.
+    fn drop(&mut self) {
+        free_bucket(&self.head);
+    }
+}
+
+/// Mark the given slot so that its data node is moved into a new array node, unless it
+/// already points to an array node.
+fn mark<T: Send>(position: &TaggedAtomicPtr<Node<T>>) {
+    let _ = position.fetch_update(Release, Acquire, |ptr| {
+        if TaggedAtomicPtr::has_tag(ptr, ARRAY) { None } else { Some(TaggedAtomicPtr::with_tag(ptr, MARKED)) }
+    });
+}
+
+/// Free every node reachable from the given bucket, descending into array nodes.
+fn free_bucket<T: Send>(bucket: &Vec<TaggedAtomicPtr<Node<T>>>) {
+    for position in bucket {
+        let node_ptr = position.load(Relaxed);
+        if TaggedAtomicPtr::has_tag(node_ptr, ARRAY) {
+            free_bucket(get_bucket(node_ptr));
+        }
+        let node_ptr = TaggedAtomicPtr::untagged(node_ptr);
+        if !node_ptr.is_null() {
+            unsafe { Box::from_raw(node_ptr) };
+        }
+    }
+}
+
+fn get_bucket<'a, T: Send>(node_ptr: *mut Node<T>) -> &'a Vec<TaggedAtomicPtr<Node<T>>> {
     unsafe {
-        match &*(atomic_markable::unmark_second(node_ptr)) {
+        match &*(TaggedAtomicPtr::without_tag(node_ptr, ARRAY)) {
             &Node::Data(_) => panic!("Unexpected data node!: {:b}", node_ptr as usize),
             &Node::Array(ref array_node) => &array_node.array
         }
@@ -482,7 +538,7 @@ fn get_bucket<'a, T: Send>(node_ptr: *mut Node<T>) -> &'a Vec<AtomicMarkablePtr<
 
 fn get_data_node<'a, T: Send>(node_ptr: *mut Node<T>) -> &'a DataNode<T> {
     unsafe {
-        match &*(atomic_markable::unmark(node_ptr)) {
+        match &*(TaggedAtomicPtr::without_tag(node_ptr, MARKED)) {
             &Node::Data(ref data_node) => data_node,
             &Node::Array(_) => panic!("Unexpected array node!: {:b}", node_ptr as usize)
         }
@@ -490,9 +546,9 @@ fn get_data_node<'a, T: Send>(node_ptr: *mut Node<T>) -> &'a DataNode<T> {
 }
 
 pub struct Iter<'a, T: Send + 'a> {
-    current_array: &'a Vec<AtomicMarkablePtr<Node<T>>>,
+    current_array: &'a Vec<TaggedAtomicPtr<Node<T>>>,
     index: usize,
-    node_stack: Vec<&'a Vec<AtomicMarkablePtr<Node<T>>>>,
+    node_stack: Vec<&'a Vec<TaggedAtomicPtr<Node<T>>>>,
     manager: &'a HPBRManager<Node<T>>
 }
 
@@ -542,7 +598,7 @@ impl<'a, T: Send + Hash + 'a> Iterator for Union<'a, T> {
 }
 
 impl<'a, T:Send> Iter<'a, T> {
-    fn new(start: &'a Vec<AtomicMarkablePtr<Node<T>>>, manager: &'a HPBRManager<Node<T>>) -> Self {
+    fn new(start: &'a Vec<TaggedAtomicPtr<Node<T>>>, manager: &'a HPBRManager<Node<T>>) -> Self {
         Self {
             current_array: start,
             index: 0,
@@ -559,19 +615,19 @@ impl<'a, T: Send> Iterator for Iter<'a, T> {
         self.index += 1;
         if index < self.current_array.len() {
             // Check if data or array
-            match self.current_array[index].get_ptr() {
+            match self.current_array[index].load_non_null(Acquire) {
                 Some(mut node_ptr) => {
                     // Protect with a HPHandle
-                    if atomic_markable::is_marked(node_ptr) {
+                    if TaggedAtomicPtr::has_tag(node_ptr, MARKED) {
                         // Protect
-                        let mut hphandle = self.manager.protect_dynamic(atomic_markable::unmark(node_ptr));
-                        while Some(node_ptr) != self.current_array[index].get_ptr() {
-                            let new_node = self.current_array[index].get_ptr();
+                        let mut hphandle = self.manager.protect_dynamic(TaggedAtomicPtr::without_tag(node_ptr, MARKED));
+                        while Some(node_ptr) != self.current_array[index].load_non_null(Acquire) {
+                            let new_node = self.current_array[index].load_non_null(Acquire);
                             match new_node {
                                 None => return self.next(),
                                 Some(new_ptr) => {
-                                    hphandle = self.manager.protect_dynamic(atomic_markable::unmark(atomic_markable::unmark_second(node_ptr)));
-                                    if atomic_markable::is_marked_second(new_ptr) {
+                                    hphandle = self.manager.protect_dynamic(TaggedAtomicPtr::without_tag(TaggedAtomicPtr::without_tag(node_ptr, ARRAY), MARKED));
+                                    if TaggedAtomicPtr::has_tag(new_ptr, ARRAY) {
                                         let bucket = get_bucket(new_ptr);
                                         self.node_stack.push(bucket);
                                         return self.next()
@@ -580,21 +636,21 @@ impl<'a, T: Send> Iterator for Iter<'a, T> {
                                 }
                             }
                         }
-                        let data_node = get_data_node(atomic_markable::unmark(node_ptr));
+                        let data_node = get_data_node(TaggedAtomicPtr::without_tag(node_ptr, MARKED));
                         Some(DataGuard::new(&data_node.value.as_ref().unwrap(), hphandle))
-                    } else if atomic_markable::is_marked_second(node_ptr) {
+                    } else if TaggedAtomicPtr::has_tag(node_ptr, ARRAY) {
                         let bucket = get_bucket(node_ptr);
                         self.node_stack.push(bucket);
                         return self.next()
                     } else {
                         let mut hphandle = self.manager.protect_dynamic(node_ptr);
-                        while Some(node_ptr) != self.current_array[index].get_ptr() {
-                            let new_node = self.current_array[index].get_ptr();
+                        while Some(node_ptr) != self.current_array[index].load_non_null(Acquire) {
+                            let new_node = self.current_array[index].load_non_null(Acquire);
                             match new_node {
                                 None => return self.next(),
                                 Some(new_ptr) => {
-                                    hphandle = self.manager.protect_dynamic(atomic_markable::unmark(atomic_markable::unmark_second(node_ptr)));
-                                    if atomic_markable::is_marked_second(new_ptr) {
+                                    hphandle = self.manager.protect_dynamic(TaggedAtomicPtr::without_tag(TaggedAtomicPtr::without_tag(node_ptr, ARRAY), MARKED));
+                                    if TaggedAtomicPtr::has_tag(new_ptr, ARRAY) {
                                         let bucket = get_bucket(new_ptr);
                                         self.node_stack.push(bucket);
                                         return self.next()
@@ -604,7 +660,7 @@ impl<'a, T: Send> Iterator for Iter<'a, T> {
                             }
                         }
 
-                        let data_node = get_data_node(atomic_markable::unmark(node_ptr));
+                        let data_node = get_data_node(TaggedAtomicPtr::without_tag(node_ptr, MARKED));
                         Some(DataGuard::new(&data_node.value.as_ref().unwrap(), hphandle))
                     }
                 },
@@ -655,7 +711,7 @@ impl<T: Send> Default for DataNode<T> {
 }
 
 pub struct ArrayNode<T: Send> {
-    array: Vec<AtomicMarkablePtr<Node<T>>>,
+    array: Vec<TaggedAtomicPtr<Node<T>>>,
     size: usize
 }
 
@@ -663,7 +719,7 @@ impl<T: Send> ArrayNode<T> {
     fn new(size: usize) -> Self {
         let mut array = Vec::with_capacity(size);
         for _ in 0..size {
-            array.push(AtomicMarkablePtr::default());
+            array.push(TaggedAtomicPtr::default());
         }
 
         ArrayNode {
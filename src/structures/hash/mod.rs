@@ -4,5 +4,4 @@ pub use self::hash_set::HashSet;
 
 mod hash_map;
 mod hash_set;
-mod data_guard;
-mod atomic_markable;
\ No newline at end of file
+mod data_guard;
\ No newline at end of file
@@ -7,14 +7,45 @@
 //! The structures in this crate can be used in a multi-threaded context by wrapping
 //! them inside an Arc, as they can all be modified with an immutable reference.
 
-pub use self::stack::Stack;
-pub use self::queue::Queue; 
+pub use self::stack::{Stack, PopAll, TryPushError, TryPopError};
+pub use self::versioned_stack::VersionedStack;
+pub use self::bounded_stack::BoundedStack;
+pub use self::fc_stack::FlatCombiningStack;
+pub use self::queue::{Queue, TryDequeueError};
 pub use self::seg_queue::SegQueue;
+pub use self::fc_queue::FlatCombiningQueue;
+pub use self::bounded_queue::BoundedQueue;
+pub use self::spsc_queue::{SpscQueue, SpscProducer, SpscConsumer};
+pub use self::mpsc_queue::{MpscQueue, MpscProducer, MpscConsumer};
+pub use self::wait_free_queue::WaitFreeQueue;
+pub use self::chase_lev::{ChaseLevDeque, Worker, Stealer, Steal};
+pub use self::deque::Deque;
+pub use self::priority_queue::PriorityQueue;
+pub use self::blocking_queue::{BlockingQueue, ConcurrentQueue};
+#[cfg(feature = "futures")]
+pub use self::async_queue::{AsyncQueue, Sender, Receiver, SendError};
 pub use self::hash::HashMap;
 pub use self::hash::HashSet;
+pub use self::rcu_cell::{RcuCell, RcuGuard};
+pub use self::utils::{TaggedAtomicPtr, AtomicVersionedPtr, VersionedPtr, PeekGuard};
 
 mod stack;
+mod versioned_stack;
+mod bounded_stack;
+mod fc_stack;
 mod queue;
 mod seg_queue;
+mod fc_queue;
+mod bounded_queue;
+mod spsc_queue;
+mod mpsc_queue;
+mod wait_free_queue;
+mod chase_lev;
+mod deque;
+mod priority_queue;
+mod blocking_queue;
+#[cfg(feature = "futures")]
+mod async_queue;
 mod hash;
+mod rcu_cell;
 mod utils;
\ No newline at end of file
@@ -0,0 +1,216 @@
+use std::sync::atomic::AtomicPtr;
+use std::sync::atomic::Ordering::{Acquire, Release, AcqRel, Relaxed};
+use std::hint;
+use std::sync::Arc;
+use std::cell::UnsafeCell;
+use std::ptr;
+
+/// An unbounded multi-producer single-consumer queue, lock-free for producers.
+///
+/// This is an implementation of [Dmitry Vyukov's intrusive MPSC node-based queue](http://www.1024cores.net/home/lock-free-algorithms/queues/intrusive-mpsc-node-based-queue),
+/// with each element stored inside the node that links it. A producer enqueues with a single atomic swap
+/// of the back of the queue followed by a store linking the previous node to its own, so producers never
+/// retry. Since only the consumer ever frees nodes, no memory manager is needed.
+///
+/// Between a producer's swap and its link, the nodes after the previous back are unreachable. A dequeue
+/// which meets this waits for the link, which is only a store away, but if the producer is descheduled
+/// between the two, the consumer is blocked until it runs again. The queue is therefore not lock-free
+/// for the consumer. Elements are dequeued in the order their swaps happened.
+///
+/// The queue is created as a pair of handles. The `MpscProducer` can be cloned and shared between any
+/// number of threads, while the `MpscConsumer` can only be moved, so the single-consumer requirement is
+/// enforced by the compiler.
+/// # Usage
+/// ```
+/// let (producer, mut consumer) = MpscQueue::handles();
+/// for _ in 0..8 {
+///     let producer_clone = producer.clone();
+///     thread::spawn(move || {
+///         producer_clone.enqueue(8);
+///     });
+/// }
+/// let mut received = 0;
+/// while received < 8 {
+///     if consumer.dequeue().is_some() {
+///         received += 1;
+///     }
+/// }
+/// ```
+pub struct MpscQueue<T: Send> {
+    back: AtomicPtr<Node<T>>,
+    front: UnsafeCell<*mut Node<T>>
+}
+
+unsafe impl<T: Send> Sync for MpscQueue<T> {}
+unsafe impl<T: Send> Send for MpscQueue<T> {}
+
+struct Node<T> {
+    next: AtomicPtr<Node<T>>,
+    value: Option<T>
+}
+
+impl<T> Node<T> {
+    fn new_as_pointer(value: Option<T>) -> *mut Node<T> {
+        Box::into_raw(Box::new(Node {
+            next: AtomicPtr::new(ptr::null_mut()),
+            value
+        }))
+    }
+}
+
+/// The enqueuing end of an MpscQueue. It can be cloned to add more producers.
+pub struct MpscProducer<T: Send> {
+    queue: Arc<MpscQueue<T>>
+}
+
+/// The dequeuing end of an MpscQueue.
+pub struct MpscConsumer<T: Send> {
+    queue: Arc<MpscQueue<T>>
+}
+
+impl<T: Send> MpscQueue<T> {
+    /// Create a new, empty queue and return its producer and consumer handles.
+    /// # Examples
+    /// ```
+    /// let (producer, consumer): (MpscProducer<u8>, MpscConsumer<u8>) = MpscQueue::handles();
+    /// ```
+    pub fn handles() -> (MpscProducer<T>, MpscConsumer<T>) {
+        // The front node is always a dummy, whose value has already been taken
+        let stub = Node::new_as_pointer(None);
+        let queue = Arc::new(MpscQueue {
+            back: AtomicPtr::new(stub),
+            front: UnsafeCell::new(stub)
+        });
+        (MpscProducer { queue: queue.clone() }, MpscConsumer { queue })
+    }
+}
+
+impl<T: Send> MpscProducer<T> {
+    /// Add an element to the back of the queue.
+    /// # Examples
+    /// ```
+    /// let (producer, consumer) = MpscQueue::handles();
+    /// producer.enqueue("hello".to_owned());
+    /// ```
+    pub fn enqueue(&self, val: T) {
+        let node = Node::new_as_pointer(Some(val));
+        let prev = self.queue.back.swap(node, AcqRel);
+        // Until this store, the consumer cannot reach our node
+        unsafe { (*prev).next.store(node, Release) };
+    }
+}
+
+impl<T: Send> Clone for MpscProducer<T> {
+    fn clone(&self) -> Self {
+        MpscProducer { queue: self.queue.clone() }
+    }
+}
+
+impl<T: Send> MpscConsumer<T> {
+    /// Take an element from the front of the queue, or return None if the queue is empty.
+    /// # Examples
+    /// ```
+    /// let (producer, mut consumer) = MpscQueue::handles();
+    /// producer.enqueue("hello".to_owned());
+    /// assert_eq!(consumer.dequeue(), Some("hello".to_owned()));
+    /// assert_eq!(consumer.dequeue(), None);
+    /// ```
+    pub fn dequeue(&mut self) -> Option<T> {
+        let queue = &*self.queue;
+        unsafe {
+            let front = *queue.front.get();
+            let mut next = (*front).next.load(Acquire);
+            if next.is_null() {
+                if ptr::eq(front, queue.back.load(Acquire)) {
+                    return None
+                }
+                // A producer has swapped in its node but not yet linked it
+                while next.is_null() {
+                    hint::spin_loop();
+                    next = (*front).next.load(Acquire);
+                }
+            }
+            // The next node becomes the dummy once its value is taken
+            *queue.front.get() = next;
+            let val = (*next).value.take();
+            drop(Box::from_raw(front));
+            val
+        }
+    }
+}
+
+impl<T: Send> Drop for MpscQueue<T> {
+    fn drop(&mut self) {
+        // Both handles are gone, so every node is linked
+        let mut current = *self.front.get_mut();
+        while !current.is_null() {
+            let node = unsafe { Box::from_raw(current) };
+            current = node.next.load(Relaxed);
+        }
+    }
+}
+
+mod tests {
+    #![allow(unused_imports)]
+    use super::{MpscQueue, MpscProducer, MpscConsumer};
+    use super::super::super::testing::{DropCounter, Dropper};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    #[test]
+    fn test_single_threaded() {
+        let (producer, mut consumer) = MpscQueue::handles();
+        assert_eq!(consumer.dequeue(), None);
+        for i in 0..10 {
+            producer.enqueue(i);
+        }
+        for i in 0..10 {
+            assert_eq!(consumer.dequeue(), Some(i));
+        }
+        assert_eq!(consumer.dequeue(), None);
+    }
+
+    #[test]
+    fn test_multithreaded() {
+        let (producer, mut consumer) = MpscQueue::handles();
+        let mut waitvec: Vec<thread::JoinHandle<()>> = Vec::new();
+        for id in 0..8 {
+            let producer_copy = producer.clone();
+            waitvec.push(thread::spawn(move || {
+                for i in 0..10000 {
+                    producer_copy.enqueue((id, i));
+                }
+            }));
+        }
+        // Each producer's elements arrive in the order it enqueued them
+        let mut next = [0; 8];
+        for _ in 0..80000 {
+            loop {
+                if let Some((id, i)) = consumer.dequeue() {
+                    assert_eq!(next[id], i);
+                    next[id] += 1;
+                    break;
+                }
+            }
+        }
+        for handle in waitvec {
+            handle.join().unwrap();
+        }
+        assert_eq!(consumer.dequeue(), None);
+    }
+
+    #[test]
+    fn test_remaining_dropped() {
+        let drops = DropCounter::new();
+        let (producer, mut consumer) = MpscQueue::handles();
+        for _ in 0..5 {
+            producer.enqueue(drops.dropper());
+        }
+        drop(consumer.dequeue());
+        assert_eq!(drops.drops(), 1);
+        drop(producer);
+        drop(consumer);
+        assert_eq!(drops.drops(), 5);
+    }
+}
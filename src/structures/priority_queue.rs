@@ -0,0 +1,467 @@
+use memory::HPBRManager;
+use stats::Counters;
+#[cfg(feature = "stats")]
+use stats::Stats;
+use super::utils::{TaggedAtomicPtr, random_usize};
+use std::sync::atomic::{self, AtomicBool, AtomicUsize};
+use std::sync::atomic::Ordering::SeqCst;
+use std::cell::UnsafeCell;
+use std::ptr;
+
+/// The maximum height of a node in the skip list.
+const MAX_LEVEL: usize = 16;
+/// The number of deleted nodes a delete_min has to walk past before it tries to remove them.
+const BOUND: usize = 32;
+/// Tag on the bottom level link to a node, marking the node it points to as deleted.
+const DELETED: usize = 0x1;
+
+/// A lock-free priority queue, based on a skip list.
+///
+/// This is an implementation of the priority queue described by Lindén and Jonsson in A Skiplist-Based
+/// Concurrent Priority Queue with Minimal Memory Contention.
+/// Elements are kept in priority order, and a delete_min takes the first element whose node is not yet
+/// deleted. A node is deleted by tagging the bottom level link pointing to it, so the deleted nodes always
+/// form a prefix of the list, and a delete_min never has to unlink its node. Instead, once a delete_min
+/// has walked past enough deleted nodes, it removes the whole prefix at once by swinging the links of the
+/// head past it, so deleting threads only contend on the head every so often.
+///
+/// The removed nodes are reclaimed with the HPBRManager. As they are only reachable from the head and
+/// from each other, every removal bumps a generation counter before retiring them, and a thread that
+/// finds the generation has changed after protecting a node starts its search again. Removal is done by
+/// one thread at a time, while every other thread carries on inserting and deleting.
+///
+/// Elements with equal priorities are not guaranteed to come out in the order they were inserted.
+/// Priorities are compared and cloned through shared references by many threads at once, so they must
+/// be Sync as well as Send.
+///
+/// The priority queue can be used in a multithreaded context by wrapping it in an Arc.
+/// # Usage
+/// ```
+/// let queue: Arc<PriorityQueue<u32, String>> = Arc::new(PriorityQueue::new());
+/// for i in 0..8 {
+///     let queue_clone = queue.clone();
+///     thread::spawn(move || {
+///         queue_clone.insert(i, "job".to_owned());
+///         queue_clone.delete_min();
+///     });
+/// }
+/// ```
+pub struct PriorityQueue<P: Ord + Clone + Send + Sync, V: Send> {
+    head: *mut Node<P, V>,
+    tail: *mut Node<P, V>,
+    generation: AtomicUsize,
+    removing: AtomicBool,
+    manager: HPBRManager<Node<P, V>>,
+    stats: Counters
+}
+
+unsafe impl<P: Ord + Clone + Send + Sync, V: Send> Sync for PriorityQueue<P, V> {}
+unsafe impl<P: Ord + Clone + Send + Sync, V: Send> Send for PriorityQueue<P, V> {}
+
+struct Node<P, V> {
+    priority: Option<P>,
+    value: UnsafeCell<Option<V>>,
+    next: Vec<TaggedAtomicPtr<Node<P, V>>>,
+    /// Set before any attempt to delete the node, so a node without it is not deleted
+    claimed: AtomicBool,
+    /// Set until all the levels of the node are linked, during which the node may not be removed
+    inserting: AtomicBool,
+    /// Only used by the thread removing deleted nodes
+    removed: AtomicBool
+}
+
+unsafe impl<P: Send, V: Send> Send for Node<P, V> {}
+
+impl<P, V> Node<P, V> {
+    fn new_as_pointer(priority: Option<P>, value: Option<V>, height: usize, next: *mut Node<P, V>) -> *mut Self {
+        Box::into_raw(Box::new(Node {
+            priority,
+            value: UnsafeCell::new(value),
+            next: (0..height).map(|_| TaggedAtomicPtr::new(next)).collect(),
+            claimed: AtomicBool::new(false),
+            inserting: AtomicBool::new(true),
+            removed: AtomicBool::new(false)
+        }))
+    }
+
+    fn link(&self, level: usize) -> &TaggedAtomicPtr<Node<P, V>> {
+        &self.next[level]
+    }
+}
+
+impl<P: Ord + Clone + Send + Sync, V: Send> PriorityQueue<P, V> {
+    /// Create a new, empty priority queue.
+    /// # Examples
+    /// ```
+    /// let queue: PriorityQueue<u32, String> = PriorityQueue::new();
+    /// ```
+    pub fn new() -> Self {
+        let tail = Node::new_as_pointer(None, None, 0, ptr::null_mut());
+        let head = Node::new_as_pointer(None, None, MAX_LEVEL, tail);
+        unsafe {
+            (*head).inserting.store(false, SeqCst);
+            (*tail).inserting.store(false, SeqCst);
+        }
+        PriorityQueue {
+            head,
+            tail,
+            generation: AtomicUsize::new(0),
+            removing: AtomicBool::new(false),
+            manager: HPBRManager::new(100, 2 * MAX_LEVEL),
+            stats: Counters::new()
+        }
+    }
+
+    /// Insert a value with the given priority. Lower priorities are taken first.
+    /// # Examples
+    /// ```
+    /// let queue: PriorityQueue<u32, String> = PriorityQueue::new();
+    /// queue.insert(1, "urgent".to_owned());
+    /// ```
+    pub fn insert(&self, priority: P, value: V) {
+        let height = (random_usize().trailing_ones() as usize + 1).min(MAX_LEVEL);
+        let node = Node::new_as_pointer(Some(priority), Some(value), height, ptr::null_mut());
+        let mut preds = [ptr::null_mut(); MAX_LEVEL];
+        let mut succs = [ptr::null_mut(); MAX_LEVEL];
+        unsafe {
+            loop {
+                self.locate(node, &mut preds, &mut succs);
+                (*node).link(0).store(succs[0], SeqCst);
+                // Fails if the successor has been deleted, or the predecessor removed
+                if (*preds[0]).link(0).compare_exchange(succs[0], node, SeqCst, SeqCst).is_ok() {
+                    break;
+                }
+                self.stats.cas_failure();
+            }
+            let mut level = 1;
+            while level < height {
+                // Only link in front of a node which is still live, so that every link at every level
+                // points forward along the bottom level, and removed nodes can only be reached from the
+                // head and from each other
+                let succ = succs[level];
+                if (!ptr::eq(succ, self.tail) && (*succ).claimed.load(SeqCst)) || (*node).claimed.load(SeqCst) {
+                    break;
+                }
+                (*node).link(level).store(succ, SeqCst);
+                if (*preds[level]).link(level).compare_exchange(succ, node, SeqCst, SeqCst).is_ok() {
+                    level += 1;
+                } else {
+                    self.stats.cas_failure();
+                    self.locate(node, &mut preds, &mut succs);
+                }
+            }
+            (*node).inserting.store(false, SeqCst);
+        }
+        for slot in 0..2 * MAX_LEVEL {
+            self.manager.unprotect(slot);
+        }
+    }
+
+    /// Take the value with the lowest priority, along with its priority, or return None if the queue is empty.
+    /// # Examples
+    /// ```
+    /// let queue: PriorityQueue<u32, String> = PriorityQueue::new();
+    /// queue.insert(2, "later".to_owned());
+    /// queue.insert(1, "urgent".to_owned());
+    /// assert_eq!(queue.delete_min(), Some((1, "urgent".to_owned())));
+    /// ```
+    pub fn delete_min(&self) -> Option<(P, V)> {
+        let node = 'retry: loop {
+            let generation = self.generation.load(SeqCst);
+            let mut pred = self.head;
+            let mut offset = 0;
+            let mut link = unsafe { (*pred).link(0).load(SeqCst) };
+            loop {
+                let current = TaggedAtomicPtr::untagged(link);
+                if ptr::eq(current, self.tail) {
+                    self.manager.unprotect(0);
+                    self.manager.unprotect(1);
+                    return None
+                }
+                if !self.protect(current, 1, generation) {
+                    continue 'retry;
+                }
+                unsafe {
+                    if TaggedAtomicPtr::has_tag(link, DELETED) {
+                        pred = current;
+                        self.manager.protect(pred, 0);
+                        offset += 1;
+                        link = (*pred).link(0).load(SeqCst);
+                        continue;
+                    }
+                    (*current).claimed.store(true, SeqCst);
+                    match (*pred).link(0).compare_exchange(link, TaggedAtomicPtr::with_tag(current, DELETED), SeqCst, SeqCst) {
+                        Ok(_) => {
+                            if offset >= BOUND {
+                                self.remove_deleted();
+                            }
+                            break 'retry current
+                        },
+                        Err(actual) => {
+                            self.stats.cas_failure();
+                            link = actual;
+                        }
+                    }
+                }
+            }
+        };
+        // Only the thread that deleted the node takes its value, and it stays protected until then
+        let result = unsafe { ((*node).priority.clone().unwrap(), (*(*node).value.get()).take().unwrap()) };
+        self.manager.unprotect(0);
+        self.manager.unprotect(1);
+        Some(result)
+    }
+
+    /// Find, at every level, the last node before the given node and the first node after it, leaving
+    /// both protected. Deleted nodes are treated as coming before every live node.
+    fn locate(&self, node: *mut Node<P, V>, preds: &mut [*mut Node<P, V>; MAX_LEVEL], succs: &mut [*mut Node<P, V>; MAX_LEVEL]) {
+        'retry: loop {
+            let generation = self.generation.load(SeqCst);
+            let mut pred = self.head;
+            for level in (0..MAX_LEVEL).rev() {
+                self.manager.protect(pred, 2 * level);
+                let mut link = unsafe { (*pred).link(level).load(SeqCst) };
+                loop {
+                    let current = TaggedAtomicPtr::untagged(link);
+                    if !self.protect(current, 2 * level + 1, generation) {
+                        continue 'retry;
+                    }
+                    if ptr::eq(current, self.tail) || !(self.is_deleted(current, link, level) || self.precedes(current, node)) {
+                        preds[level] = pred;
+                        succs[level] = current;
+                        break;
+                    }
+                    pred = current;
+                    self.manager.protect(pred, 2 * level);
+                    link = unsafe { (*pred).link(level).load(SeqCst) };
+                }
+            }
+            return
+        }
+    }
+
+    /// Check whether a node reached through the given link at the given level is known to be deleted.
+    /// Above the bottom level, only nodes followed by a deleted node are known to be deleted.
+    fn is_deleted(&self, node: *mut Node<P, V>, link: *mut Node<P, V>, level: usize) -> bool {
+        if level == 0 {
+            TaggedAtomicPtr::has_tag(link, DELETED)
+        } else {
+            unsafe { TaggedAtomicPtr::has_tag((*node).link(0).load(SeqCst), DELETED) }
+        }
+    }
+
+    /// Check whether the first node comes before the second, breaking ties between equal priorities by
+    /// address, so that every live node has a distinct place in the list.
+    fn precedes(&self, first: *mut Node<P, V>, second: *mut Node<P, V>) -> bool {
+        unsafe { (&(*first).priority, first as usize) < (&(*second).priority, second as usize) }
+    }
+
+    /// Protect a node, and check that no nodes have been removed since the search started.
+    fn protect(&self, node: *mut Node<P, V>, slot: usize, generation: usize) -> bool {
+        self.manager.protect(node, slot);
+        atomic::fence(SeqCst);
+        self.generation.load(SeqCst) == generation
+    }
+
+    /// Remove the prefix of deleted nodes, unless another thread is already doing so.
+    fn remove_deleted(&self) {
+        if self.removing.compare_exchange(false, true, SeqCst, SeqCst).is_err() {
+            return
+        }
+        unsafe {
+            let first = (*self.head).link(0).load(SeqCst);
+            if TaggedAtomicPtr::has_tag(first, DELETED) {
+                // Keep the last deleted node, whose link to the first live node may still be inserted
+                // after, and any node still being inserted, as its upper levels may still be linked to
+                let mut removed = Vec::new();
+                let mut current = TaggedAtomicPtr::untagged(first);
+                while !(*current).inserting.load(SeqCst) && TaggedAtomicPtr::has_tag((*current).link(0).load(SeqCst), DELETED) {
+                    (*current).removed.store(true, SeqCst);
+                    removed.push(current);
+                    current = TaggedAtomicPtr::untagged((*current).link(0).load(SeqCst));
+                }
+                if !removed.is_empty() {
+                    // Nobody else changes a tagged link, so the bottom level can simply be stored
+                    (*self.head).link(0).store(TaggedAtomicPtr::with_tag(current, DELETED), SeqCst);
+                    for level in 1..MAX_LEVEL {
+                        loop {
+                            let first = (*self.head).link(level).load(SeqCst);
+                            let mut next = first;
+                            while !ptr::eq(next, self.tail) && (*next).removed.load(SeqCst) {
+                                next = (*next).link(level).load(SeqCst);
+                            }
+                            if ptr::eq(next, first) || (*self.head).link(level).compare_exchange(first, next, SeqCst, SeqCst).is_ok() {
+                                break;
+                            }
+                            self.stats.cas_failure();
+                        }
+                    }
+                    // Searches still inside the removed nodes see the new generation and start again
+                    self.generation.fetch_add(1, SeqCst);
+                    for node in removed {
+                        self.manager.retire(node, 2);
+                    }
+                }
+            }
+        }
+        self.removing.store(false, SeqCst);
+    }
+
+    /// Get a snapshot of the contention and reclamation counters of the priority queue.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> Stats {
+        self.stats.snapshot() + self.manager.stats()
+    }
+}
+
+impl<P: Ord + Clone + Send + Sync, V: Send> Default for PriorityQueue<P, V> {
+    fn default() -> Self {
+        PriorityQueue::new()
+    }
+}
+
+impl<P: Ord + Clone + Send + Sync, V: Send> Drop for PriorityQueue<P, V> {
+    fn drop(&mut self) {
+        let mut current = self.head;
+        while !ptr::eq(current, self.tail) {
+            let node = unsafe { Box::from_raw(current) };
+            current = TaggedAtomicPtr::untagged(node.next[0].load(SeqCst));
+        }
+        unsafe { drop(Box::from_raw(self.tail)) };
+    }
+}
+
+mod tests {
+    #![allow(unused_imports)]
+    extern crate im;
+    use self::im::OrdSet;
+
+    use rand::{thread_rng, Rng};
+
+    use super::PriorityQueue;
+    use super::super::super::testing::{DropCounter, Dropper};
+    use super::super::super::testing::linearizability_tester::{LinearizabilityTester, LinearizabilityResult, ThreadLog};
+
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    #[test]
+    fn test_single_threaded() {
+        let queue: PriorityQueue<u32, String> = PriorityQueue::new();
+        assert_eq!(queue.delete_min(), None);
+        for &i in [5, 3, 8, 1, 9, 2, 7, 4, 6, 0].iter() {
+            queue.insert(i, i.to_string());
+        }
+        for i in 0..10 {
+            assert_eq!(queue.delete_min(), Some((i, i.to_string())));
+        }
+        assert_eq!(queue.delete_min(), None);
+
+        // Enough to remove deleted nodes a few times over
+        for i in (0..1000).rev() {
+            queue.insert(i, i.to_string());
+        }
+        for i in 0..1000 {
+            assert_eq!(queue.delete_min(), Some((i, i.to_string())));
+        }
+        assert_eq!(queue.delete_min(), None);
+    }
+
+    #[test]
+    fn test_multithreaded() {
+        let queue: Arc<PriorityQueue<usize, usize>> = Arc::new(PriorityQueue::new());
+        let taken = Arc::new(AtomicUsize::new(0));
+        let mut waitvec: Vec<thread::JoinHandle<()>> = Vec::new();
+        for id in 0..8 {
+            let queue_copy = queue.clone();
+            let taken_copy = taken.clone();
+            waitvec.push(thread::spawn(move || {
+                for i in 0..5000 {
+                    let priority = thread_rng().gen_range(0, 1000);
+                    queue_copy.insert(priority, id * 5000 + i);
+                    if i % 2 == 0 {
+                        if let Some((_, val)) = queue_copy.delete_min() {
+                            taken_copy.fetch_add(val, Ordering::Relaxed);
+                        }
+                    }
+                }
+            }));
+        }
+        for handle in waitvec {
+            handle.join().unwrap();
+        }
+        // With no more inserts, the rest come out in priority order
+        let mut last = 0;
+        while let Some((priority, val)) = queue.delete_min() {
+            assert!(priority >= last);
+            last = priority;
+            taken.fetch_add(val, Ordering::Relaxed);
+        }
+        assert_eq!(taken.load(Ordering::Relaxed), (0..40000).sum::<usize>());
+    }
+
+    #[test]
+    fn test_remaining_dropped() {
+        let drops = DropCounter::new();
+        let queue: PriorityQueue<usize, Dropper> = PriorityQueue::new();
+        for i in 0..100 {
+            queue.insert(i, drops.dropper());
+        }
+        for _ in 0..50 {
+            drop(queue.delete_min());
+        }
+        assert_eq!(drops.drops(), 50);
+        drop(queue);
+        assert_eq!(drops.drops(), 100);
+    }
+
+    #[test]
+    fn test_linearizable() {
+        let queue: PriorityQueue<usize, usize> = PriorityQueue::new();
+        let sequential: OrdSet<usize> = OrdSet::new();
+        let mut linearizer: LinearizabilityTester<PriorityQueue<usize, usize>, OrdSet<usize>, usize>
+                = LinearizabilityTester::new(8, 1000000, queue, sequential);
+
+        fn insert(queue: &PriorityQueue<usize, usize>, val: usize) {
+            queue.insert(val, val);
+        }
+
+        fn delete_min(queue: &PriorityQueue<usize, usize>) -> Option<usize> {
+            queue.delete_min().map(|(_, val)| val)
+        }
+
+        fn sequential_insert(queue: &OrdSet<usize>, val: Option<usize>) -> (OrdSet<usize>, Option<usize>) {
+            (queue.insert(val.unwrap()), None)
+        }
+
+        fn sequential_delete_min(queue: &OrdSet<usize>, _val: Option<usize>) -> (OrdSet<usize>, Option<usize>) {
+            match queue.pop_min() {
+                (Some(arc), set) => (set, Some(*arc)),
+                (None, set) => (set, None)
+            }
+        }
+
+        fn worker(id: usize, log: &mut ThreadLog<PriorityQueue<usize, usize>, OrdSet<usize>, usize>) {
+            for i in 0..1000 {
+                let rand = thread_rng().gen_range(0, 101);
+                // Keep priorities distinct, so the sequential queue has only one minimum
+                let val = thread_rng().gen_range(0, 1000) * 10000 + id * 1000 + i;
+                if rand < 50 {
+                    log.log_val(id, insert, val, format!("insert: {}", val), sequential_insert);
+                } else {
+                    log.log(id, delete_min, "delete_min".to_owned(), sequential_delete_min);
+                }
+            }
+        }
+
+        let result = linearizer.run(worker);
+
+        println!("{:?}", result);
+
+        match result {
+            LinearizabilityResult::Success => assert!(true),
+            _ => assert!(false)
+        }
+    }
+}
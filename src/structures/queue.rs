@@ -1,33 +1,54 @@
 use memory::HPBRManager;
+use stats::Counters;
+use super::utils::{TaggedAtomicPtr, PeekGuard};
+#[cfg(feature = "stats")]
+use stats::Stats;
 use std::sync::atomic::{AtomicPtr, Ordering};
+use std::mem::ManuallyDrop;
 use std::ptr;
-use std::thread;
-use std::time::Duration;
-use rand::{SmallRng, NewRng, Rng};
-use std::cell::UnsafeCell;
-use std::cmp;
+use backoff::{Backoff, ExponentialSpin};
 
-const MAX_BACKOFF: u32 = 2048;
+/// Tag on the next link of the last node, once the queue has been closed.
+const CLOSED: usize = 0x1;
 
 /// A lock-free Michael-Scott queue.
 ///
 /// This queue is an implementation of that described in [Simple, Fast, and Practical
 /// Non-blocking and Blocking Concurrent Queue Algorithms](https://dl.acm.org/citation.cfm?id=248106). 
 /// It is implemented as a linked-list of nodes.
+///
+/// The queue can be closed, after which enqueues fail and hand their element back. Closing marks the
+/// next link of the last node, so nothing can be linked after it, and dequeues drain the remaining
+/// elements before reporting that the queue is closed rather than empty.
+///
+/// Failed operations wait according to a Backoff strategy before retrying, which is an
+/// `ExponentialSpin` unless the queue is created with `new_with_backoff`.
 #[derive(Debug)]
 pub struct Queue<T: Send> {
     head: AtomicPtr<Node<T>>,
     tail: AtomicPtr<Node<T>>,
     manager: HPBRManager<Node<T>>,
-    rng: UnsafeCell<SmallRng>
+    backoff: Box<dyn Backoff>,
+    stats: Counters
 }
 
 unsafe impl<T: Send> Sync for Queue<T> {}
 
+/// The reason a call to `Queue::try_dequeue` or `SegQueue::try_dequeue` returned no element.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryDequeueError {
+    /// The queue was empty.
+    Empty,
+    /// The queue was empty and has been closed, so it will stay empty.
+    Closed
+}
+
+/// The value is read out of a dequeued node rather than taken, so that a PeekGuard still pointing at it
+/// sees it unchanged. A node therefore never drops its value, which is dropped by whoever removes it.
 #[derive(Debug)]
 struct Node<T: Send> {
-    next: AtomicPtr<Node<T>>,
-    value: Option<T>
+    next: TaggedAtomicPtr<Node<T>>,
+    value: ManuallyDrop<Option<T>>
 }
 
 impl<T: Send> Queue<T> {
@@ -37,71 +58,130 @@ impl<T: Send> Queue<T> {
     /// let queue: Queue<String> = Queue::new();
     /// ```
     pub fn new() -> Self {
+        Queue::new_with_backoff(ExponentialSpin::default())
+    }
+
+    /// Create a new Queue which waits between failed attempts according to the given strategy.
+    /// # Examples
+    /// ```
+    /// let queue: Queue<String> = Queue::new_with_backoff(YieldBackoff);
+    /// ```
+    pub fn new_with_backoff<B: Backoff + 'static>(backoff: B) -> Self {
         let dummy_node = Box::into_raw(Box::new(Node::new_dummy_node()));
         Queue {
             head: AtomicPtr::new(dummy_node),
             tail: AtomicPtr::new(dummy_node),
             manager: HPBRManager::new(100, 2),
-            rng: UnsafeCell::new(SmallRng::new())
+            backoff: Box::new(backoff),
+            stats: Counters::new()
         }
     }
-    
-    fn backoff(&self, max_backoff: u32) -> u32 {
-        unsafe {
-            let rng = &mut *self.rng.get();
-            let backoff_time = rng.gen_range(0, max_backoff);
-            thread::sleep(Duration::new(0, backoff_time * 10));    
-        }
-        cmp::min(max_backoff * 2, MAX_BACKOFF)
+
+    fn backoff(&self, failures: u32) {
+        self.stats.backoff();
+        self.backoff.backoff(failures);
     }
 
-    /// Add a new element to the back of the queue.
+    /// Add a new element to the back of the queue. If the queue has been closed, the element is handed
+    /// back inside Err.
     /// # Examples
     /// ```
     /// let queue: Queue<String> = Queue::new();
-    /// queue.enqueue("hello".to_owned());
+    /// assert_eq!(queue.enqueue("hello".to_owned()), Ok(()));
+    /// queue.close();
+    /// assert_eq!(queue.enqueue("world".to_owned()), Err("world".to_owned()));
     /// ```
-    pub fn enqueue(&self, val: T) {
-        let mut backoff = 1;
-        let mut node = Box::new(Node::new(val));
+    pub fn enqueue(&self, val: T) -> Result<(), T> {
+        let node_ptr = Box::into_raw(Box::new(Node::new(val)));
+        if self.enqueue_chain(node_ptr, node_ptr) {
+            return Ok(())
+        }
+        let node = unsafe { Box::from_raw(node_ptr) };
+        Err(ManuallyDrop::into_inner(node.value).unwrap())
+    }
+
+    /// Add every element of the iterator to the back of the queue, in order. The elements are linked
+    /// together privately first and then appended with a single CAS, so they are enqueued atomically
+    /// and contiguously: no other element is interleaved with them. If the queue has been closed, none of
+    /// the elements are enqueued, and they are handed back inside Err.
+    /// # Examples
+    /// ```
+    /// let queue: Queue<u8> = Queue::new();
+    /// queue.enqueue_batch(vec![1, 2, 3]).unwrap();
+    /// assert_eq!(queue.dequeue(), Some(1));
+    /// ```
+    pub fn enqueue_batch<I: IntoIterator<Item = T>>(&self, vals: I) -> Result<(), Vec<T>> {
+        let mut iter = vals.into_iter();
+        let first = match iter.next() {
+            Some(val) => Box::into_raw(Box::new(Node::new(val))),
+            None => return Ok(())
+        };
+        let mut last = first;
+        for val in iter {
+            let node_ptr = Box::into_raw(Box::new(Node::new(val)));
+            // Nobody else can see the chain yet
+            unsafe { (*last).next.store(node_ptr, Ordering::Relaxed) };
+            last = node_ptr;
+        }
+        if self.enqueue_chain(first, last) {
+            return Ok(())
+        }
+        // The chain was never shared, so it can be taken apart again
+        let mut vals = Vec::new();
+        let mut current = first;
+        while !current.is_null() {
+            let node = unsafe { Box::from_raw(current) };
+            vals.push(ManuallyDrop::into_inner(node.value).unwrap());
+            current = node.next.load(Ordering::Relaxed);
+        }
+        Err(vals)
+    }
+
+    /// Append the chain, returning false if the queue is closed.
+    fn enqueue_chain(&self, first: *mut Node<T>, last: *mut Node<T>) -> bool {
+        let mut failures = 0;
         loop {
-            node = match self.try_enqueue(node) {
-                Ok(_) => { return; },
-                Err(old_node) => old_node
-            };
-            backoff = self.backoff(backoff);
+            if let Ok(appended) = self.attempt_enqueue(first, last) {
+                return appended
+            }
+            self.stats.cas_failure();
+            failures += 1;
+            self.backoff(failures);
         }
     }
 
-    fn try_enqueue(&self, val: Box<Node<T>>) -> Result<(), Box<Node<T>>> {
+    fn attempt_enqueue(&self, first: *mut Node<T>, last: *mut Node<T>) -> Result<bool, ()> {
         let tail = self.tail.load(Ordering::Acquire);
         self.manager.protect(tail, 0);
         // Is the tail still consistent? Required for the hazard pointer to work
         if !ptr::eq(tail, self.tail.load(Ordering::Acquire)) {
-            return Err(val)
+            return Err(())
         }
         let next = unsafe { (*tail).next.load(Ordering::Acquire) };
 
+        // Nothing can be linked after a closed link
+        if TaggedAtomicPtr::has_tag(next, CLOSED) {
+            return Ok(false)
+        }
+
         // Is the tail actually the end of the queue?
         if !next.is_null() {
             // If it isn't, try to make next the end of the queue
             let _ = self.tail.compare_exchange(tail, next, Ordering::Release, Ordering::Relaxed);
-            return Err(val)
+            return Err(())
         }
 
-        let node_ptr = Box::into_raw(val);
-        // Try to CAS our node onto the end of the queue
+        // Try to CAS our chain onto the end of the queue
         unsafe {
-            match (*tail).next.compare_exchange(ptr::null_mut(), node_ptr, Ordering::Release, Ordering::Relaxed) {
+            match (*tail).next.compare_exchange(ptr::null_mut(), first, Ordering::Release, Ordering::Relaxed) {
                 Ok(_) => {
-                    // Success! Set our new node to the tail
-                    let _ = self.tail.compare_exchange(tail, node_ptr, Ordering::Release, Ordering::Relaxed);
-                    return Ok(())
+                    // Success! Set the end of our chain to the tail. If another thread has already
+                    // moved the tail into the chain, it is walked forward one node at a time instead
+                    let _ = self.tail.compare_exchange(tail, last, Ordering::Release, Ordering::Relaxed);
+                    Ok(true)
                 },
                 // Failure :( try again
-                Err(_) => {
-                    return Err(Box::from_raw(node_ptr))
-                }
+                Err(_) => Err(())
             }
         }
     }
@@ -110,20 +190,38 @@ impl<T: Send> Queue<T> {
     /// # Examples
     /// ```
     /// let queue: Queue<String> = Queue::new();
-    /// queue.enqueue("hello".to_owned());
+    /// queue.enqueue("hello".to_owned()).unwrap();
     /// assert_eq!(queue.dequeue(), Some("hello".to_owned()));
     /// ```
     pub fn dequeue(&self) -> Option<T> {
-        let mut backoff = 1;
+        self.try_dequeue().ok()
+    }
+
+    /// Take an element from the front of the queue, or tell whether the queue is empty or closed. A
+    /// closed queue still hands out its remaining elements, and only reports `TryDequeueError::Closed`
+    /// once it has been drained. Retries on contention, as `dequeue` does.
+    /// # Examples
+    /// ```
+    /// let queue: Queue<u8> = Queue::new();
+    /// assert_eq!(queue.try_dequeue(), Err(TryDequeueError::Empty));
+    /// queue.enqueue(8).unwrap();
+    /// queue.close();
+    /// assert_eq!(queue.try_dequeue(), Ok(8));
+    /// assert_eq!(queue.try_dequeue(), Err(TryDequeueError::Closed));
+    /// ```
+    pub fn try_dequeue(&self) -> Result<T, TryDequeueError> {
+        let mut failures = 0;
         loop {
-            if let Ok(val) = self.try_dequeue() {
+            if let Ok(val) = self.attempt_dequeue() {
                 return val
             }
-            backoff = self.backoff(backoff);
+            self.stats.cas_failure();
+            failures += 1;
+            self.backoff(failures);
         }
     }
 
-    fn try_dequeue(&self) -> Result<Option<T>, ()> {
+    fn attempt_dequeue(&self) -> Result<Result<T, TryDequeueError>, ()> {
         let head = self.head.load(Ordering::Acquire);
         self.manager.protect(head, 0);
         if !ptr::eq(head, self.head.load(Ordering::Acquire)) {
@@ -138,8 +236,11 @@ impl<T: Send> Queue<T> {
 
         let tail = self.tail.load(Ordering::Acquire);
         
+        if TaggedAtomicPtr::has_tag(next, CLOSED) {
+            return Ok(Err(TryDequeueError::Closed))
+        }
         if next.is_null() {
-            return Ok(None)
+            return Ok(Err(TryDequeueError::Empty))
         }
 
         if ptr::eq(head, tail) {
@@ -149,25 +250,210 @@ impl<T: Send> Queue<T> {
 
         match self.head.compare_exchange(head, next, Ordering::AcqRel, Ordering::Acquire) {
             Ok(_) => {
-                let node = unsafe { ptr::read(next) };
-                let data = node.value;
+                // The node becomes the new dummy node, so its value is read out and left in place
+                let data = unsafe { ptr::read(&*(*next).value).unwrap() };
                 self.manager.retire(head, 0);
-                return Ok(data)
+                return Ok(Ok(data))
             },
             Err(_) => {
                 return Err(())
             }
         }
     }
+
+    /// Take up to `max` elements from the front of the queue, moving the head past all of them with a
+    /// single CAS. Returns fewer elements if the queue holds fewer, and an empty Vec if it is empty.
+    /// # Examples
+    /// ```
+    /// let queue: Queue<u8> = Queue::new();
+    /// queue.enqueue_batch(vec![1, 2, 3]).unwrap();
+    /// assert_eq!(queue.dequeue_batch(2), vec![1, 2]);
+    /// assert_eq!(queue.dequeue_batch(2), vec![3]);
+    /// ```
+    pub fn dequeue_batch(&self, max: usize) -> Vec<T> {
+        if max == 0 {
+            return Vec::new()
+        }
+        let mut failures = 0;
+        loop {
+            if let Ok(vals) = self.try_dequeue_batch(max) {
+                return vals
+            }
+            self.stats.cas_failure();
+            failures += 1;
+            self.backoff(failures);
+        }
+    }
+
+    fn try_dequeue_batch(&self, max: usize) -> Result<Vec<T>, ()> {
+        let head = self.head.load(Ordering::Acquire);
+        self.manager.protect(head, 0);
+        if !ptr::eq(head, self.head.load(Ordering::Acquire)) {
+            return Err(())
+        }
+        let tail = self.tail.load(Ordering::Acquire);
+
+        // Walk the nodes after the head, protecting each in turn. While the head is unchanged, none of
+        // them can have been retired, so each is safe to read once protected and validated
+        let mut nodes: Vec<*mut Node<T>> = Vec::new();
+        let mut current = head;
+        while nodes.len() < max {
+            // The head must never pass the tail, so the walk stops at the tail
+            if ptr::eq(current, tail) && !nodes.is_empty() {
+                break;
+            }
+            let next = unsafe { (*current).next.load(Ordering::Acquire) };
+            if TaggedAtomicPtr::untagged(next).is_null() {
+                break;
+            }
+            self.manager.protect(next, 1);
+            if !ptr::eq(head, self.head.load(Ordering::Acquire)) {
+                return Err(())
+            }
+            if ptr::eq(current, tail) {
+                // The tail is lagging behind at the head, so help it on before dequeuing past it
+                let _ = self.tail.compare_exchange(tail, next, Ordering::Release, Ordering::Relaxed);
+                return Err(())
+            }
+            nodes.push(next);
+            current = next;
+        }
+
+        let new_head = match nodes.last() {
+            Some(&new_head) => new_head,
+            None => return Ok(Vec::new())
+        };
+        match self.head.compare_exchange(head, new_head, Ordering::AcqRel, Ordering::Acquire) {
+            Ok(_) => {
+                // The last node becomes the new dummy node, and stays protected until its value is taken
+                let vals = nodes.iter().map(|&node| unsafe { ptr::read(&*(*node).value).unwrap() }).collect();
+                self.manager.retire(head, 0);
+                for &node in &nodes[..nodes.len() - 1] {
+                    self.manager.retire(node, 0);
+                }
+                Ok(vals)
+            },
+            Err(_) => Err(())
+        }
+    }
+
+    /// Look at the element at the front of the queue without removing it, or return None if the queue
+    /// is empty. The node holding the element is protected by a hazard pointer inside the PeekGuard, so it
+    /// is not freed while the guard is alive, even if the element is dequeued in the meantime.
+    /// # Examples
+    /// ```
+    /// let queue: Queue<u64> = Queue::new();
+    /// queue.enqueue(8).unwrap();
+    /// let due = queue.peek().map_or(false, |guard| *guard.data() <= now());
+    /// if due {
+    ///     queue.dequeue();
+    /// }
+    /// ```
+    pub fn peek(&self) -> Option<PeekGuard<'_, T>> where T: Sync {
+        loop {
+            let head = self.head.load(Ordering::Acquire);
+            let _head_handle = self.manager.protect_dynamic(head);
+            if !ptr::eq(head, self.head.load(Ordering::Acquire)) {
+                continue;
+            }
+            let next = unsafe { (*head).next.load(Ordering::Acquire) };
+            if TaggedAtomicPtr::untagged(next).is_null() {
+                return None
+            }
+            let handle = self.manager.protect_dynamic(next);
+            // The front node is only retired once the head has moved past it, so if the head is unchanged
+            // once the node is protected, the node stays allocated until the guard is dropped
+            if ptr::eq(head, self.head.load(Ordering::Acquire)) {
+                if let Some(ref value) = *unsafe { &*next }.value {
+                    return Some(PeekGuard::new(value, handle))
+                }
+            }
+        }
+    }
+
+    /// Close the queue. Further enqueues fail and hand their element back, while dequeues carry on taking
+    /// the remaining elements, and `try_dequeue` then reports `TryDequeueError::Closed`. Closing a closed
+    /// queue does nothing.
+    /// # Examples
+    /// ```
+    /// let queue: Queue<u8> = Queue::new();
+    /// queue.enqueue(1).unwrap();
+    /// queue.close();
+    /// assert_eq!(queue.enqueue(2), Err(2));
+    /// assert_eq!(queue.dequeue(), Some(1));
+    /// ```
+    pub fn close(&self) {
+        let mut failures = 0;
+        loop {
+            let (last, next) = self.find_last();
+            if TaggedAtomicPtr::has_tag(next, CLOSED) {
+                return
+            }
+            let closed = TaggedAtomicPtr::with_tag(ptr::null_mut(), CLOSED);
+            if unsafe { (*last).next.compare_exchange(next, closed, Ordering::Release, Ordering::Relaxed) }.is_ok() {
+                return
+            }
+            self.stats.cas_failure();
+            failures += 1;
+            self.backoff(failures);
+        }
+    }
+
+    /// Check whether the queue has been closed. It may still hold elements.
+    /// # Examples
+    /// ```
+    /// let queue: Queue<u8> = Queue::new();
+    /// assert!(!queue.is_closed());
+    /// queue.close();
+    /// assert!(queue.is_closed());
+    /// ```
+    pub fn is_closed(&self) -> bool {
+        TaggedAtomicPtr::has_tag(self.find_last().1, CLOSED)
+    }
+
+    /// Find the last node of the queue, helping the tail on to it, and return it protected along with its
+    /// next link, which is either null or closed.
+    fn find_last(&self) -> (*mut Node<T>, *mut Node<T>) {
+        loop {
+            let tail = self.tail.load(Ordering::Acquire);
+            self.manager.protect(tail, 0);
+            if !ptr::eq(tail, self.tail.load(Ordering::Acquire)) {
+                continue;
+            }
+            let next = unsafe { (*tail).next.load(Ordering::Acquire) };
+            if TaggedAtomicPtr::untagged(next).is_null() {
+                return (tail, next)
+            }
+            let _ = self.tail.compare_exchange(tail, next, Ordering::Release, Ordering::Relaxed);
+        }
+    }
+
+    /// Get a snapshot of the contention and reclamation counters of the queue.
+    /// # Examples
+    /// ```
+    /// let queue: Queue<u8> = Queue::new();
+    /// queue.enqueue(8).unwrap();
+    /// queue.dequeue();
+    /// assert_eq!(queue.stats().retired, 1);
+    /// ```
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> Stats {
+        self.stats.snapshot() + self.manager.stats()
+    }
 }
 
 impl<T: Send> Drop for Queue<T> {
     fn drop(&mut self) {
-        let mut current = self.head.load(Ordering::Relaxed);
+        // The value of the dummy node at the head has already been read out, if it ever had one
+        let head = self.head.load(Ordering::Relaxed);
+        let mut current = head;
         while !current.is_null() {
             unsafe {
-                let next = (*current).next.load(Ordering::Relaxed);
-                Box::from_raw(current);
+                let next = TaggedAtomicPtr::untagged((*current).next.load(Ordering::Relaxed));
+                let node = Box::from_raw(current);
+                if !ptr::eq(current, head) {
+                    drop(ManuallyDrop::into_inner(node.value));
+                }
                 current = next;
             }
         }
@@ -177,23 +463,23 @@ impl<T: Send> Drop for Queue<T> {
 impl<T: Send> Node<T> {
     fn new(value: T) -> Self {
         Node {
-            next: AtomicPtr::default(),
-            value: Some(value)
+            next: TaggedAtomicPtr::default(),
+            value: ManuallyDrop::new(Some(value))
         }
     }
 
     fn new_dummy_node() -> Self {
         Node {
-            next: AtomicPtr::default(),
-            value: None
+            next: TaggedAtomicPtr::default(),
+            value: ManuallyDrop::new(None)
         }
     }
 
     unsafe fn replace(dest: *mut Self) -> Self {
         let next_ptr = (*dest).next.load(Ordering::Acquire);
         let node = Node {
-            next: AtomicPtr::new(next_ptr),
-            value: None
+            next: TaggedAtomicPtr::new(next_ptr),
+            value: ManuallyDrop::new(None)
         };
         ptr::replace(dest, node)
     }
@@ -201,10 +487,7 @@ impl<T: Send> Node<T> {
 
 impl<T: Send> Default for Node<T> {
     fn default() -> Self {
-        Node {
-            next: AtomicPtr::default(),
-            value: None
-        }
+        Node::new_dummy_node()
     }
 }
 
@@ -215,10 +498,13 @@ mod tests {
 
     use rand::{thread_rng, Rng};
 
-    use super::Queue;
+    use super::{Queue, TryDequeueError};
+    use super::super::super::testing::{DropCounter, Dropper};
+    use backoff::YieldBackoff;
+    use std::cmp;
     use std::sync::Arc;
     use std::thread;
-    use std::sync::atomic::Ordering;
+    use std::sync::atomic::{AtomicUsize, Ordering};
 
     use super::super::super::testing::linearizability_tester::{LinearizabilityTester, LinearizabilityResult, ThreadLog};
 
@@ -226,19 +512,19 @@ mod tests {
      
     fn test_queue_single_threaded() {
         let mut queue : Queue<u8> = Queue::new();
-        queue.enqueue(8);
+        queue.enqueue(8).unwrap();
         unsafe {
             println!("{:?}", *queue.head.load(Ordering::Relaxed));
             let head = (*queue.head.load(Ordering::Relaxed)).next.load(Ordering::Relaxed);
-            assert_eq!((*head).value, Some(8));
+            assert_eq!(*(*head).value, Some(8));
         }
-        queue.enqueue(7);
+        queue.enqueue(7).unwrap();
         assert_eq!(queue.dequeue(), Some(8));
         assert_eq!(queue.dequeue(), Some(7));
         assert_eq!(queue.dequeue(), None);
 
         for i in 0..100 {
-            queue.enqueue(i);
+            queue.enqueue(i).unwrap();
         }
         for i in 0..100 {
             assert_eq!(queue.dequeue(), Some(i));
@@ -256,7 +542,7 @@ mod tests {
             let mut queue_copy = queue.clone();
             waitvec.push(thread::spawn(move || {
                 for i in 0..10000 {
-                    queue_copy.enqueue(i);
+                    queue_copy.enqueue(i).unwrap();
                 }
                 //println!("Push thread {} complete", i);
             }));
@@ -284,6 +570,227 @@ mod tests {
         assert_eq!(None, queue.dequeue());
     }
 
+    #[test]
+    fn test_with_backoff() {
+        let queue: Arc<Queue<usize>> = Arc::new(Queue::new_with_backoff(YieldBackoff));
+        let mut waitvec: Vec<thread::JoinHandle<()>> = Vec::new();
+        for _ in 0..8 {
+            let queue_copy = queue.clone();
+            waitvec.push(thread::spawn(move || {
+                for i in 0..10000 {
+                    queue_copy.enqueue(i).unwrap();
+                }
+            }));
+            let queue_copy = queue.clone();
+            waitvec.push(thread::spawn(move || {
+                for _ in 0..10000 {
+                    while queue_copy.dequeue().is_none() {}
+                }
+            }));
+        }
+        for handle in waitvec {
+            handle.join().unwrap();
+        }
+        assert_eq!(queue.dequeue(), None);
+    }
+
+    #[test]
+    fn test_batch() {
+        let queue: Queue<u8> = Queue::new();
+        queue.enqueue_batch(Vec::new()).unwrap();
+        assert_eq!(queue.dequeue_batch(4), Vec::<u8>::new());
+        queue.enqueue(0).unwrap();
+        queue.enqueue_batch(1..6).unwrap();
+        queue.enqueue(6).unwrap();
+        assert_eq!(queue.dequeue_batch(0), Vec::<u8>::new());
+        assert_eq!(queue.dequeue_batch(3), vec![0, 1, 2]);
+        assert_eq!(queue.dequeue(), Some(3));
+        assert_eq!(queue.dequeue_batch(10), vec![4, 5, 6]);
+        assert_eq!(queue.dequeue(), None);
+
+        // Batches are enqueued contiguously, and elements from each producer stay in order
+        let queue: Arc<Queue<(usize, usize)>> = Arc::new(Queue::new());
+        let mut waitvec: Vec<thread::JoinHandle<()>> = Vec::new();
+        for id in 0..4 {
+            let queue_copy = queue.clone();
+            waitvec.push(thread::spawn(move || {
+                for batch in 0..500 {
+                    queue_copy.enqueue_batch((0..10).map(|i| (id, batch * 10 + i))).unwrap();
+                }
+            }));
+        }
+        let mut order = Vec::new();
+        while order.len() < 20000 {
+            order.extend(queue.dequeue_batch(7));
+        }
+        for handle in waitvec {
+            handle.join().unwrap();
+        }
+        assert_eq!(queue.dequeue(), None);
+        let mut next = [0; 4];
+        for (index, &(id, seq)) in order.iter().enumerate() {
+            assert_eq!(next[id], seq);
+            next[id] += 1;
+            if seq % 10 != 0 {
+                assert_eq!(order[index - 1], (id, seq - 1));
+            }
+        }
+    }
+
+    #[test]
+    fn test_batch_multithreaded() {
+        let queue: Arc<Queue<usize>> = Arc::new(Queue::new());
+        let mut waitvec: Vec<thread::JoinHandle<usize>> = Vec::new();
+        for _ in 0..4 {
+            let queue_copy = queue.clone();
+            waitvec.push(thread::spawn(move || {
+                for batch in 0..1000 {
+                    queue_copy.enqueue_batch(batch * 10..batch * 10 + 10).unwrap();
+                }
+                0
+            }));
+            let queue_copy = queue.clone();
+            waitvec.push(thread::spawn(move || {
+                let mut taken = 0;
+                let mut sum = 0;
+                while taken < 10000 {
+                    let vals = queue_copy.dequeue_batch(cmp::min(8, 10000 - taken));
+                    taken += vals.len();
+                    sum += vals.iter().sum::<usize>();
+                }
+                sum
+            }));
+        }
+        let sum: usize = waitvec.into_iter().map(|handle| handle.join().unwrap()).sum();
+        assert_eq!(sum, 4 * (0..10000).sum::<usize>());
+        assert_eq!(queue.dequeue(), None);
+    }
+
+    #[test]
+    fn test_peek() {
+        let queue: Queue<u8> = Queue::new();
+        assert!(queue.peek().is_none());
+        queue.enqueue(1).unwrap();
+        queue.enqueue(2).unwrap();
+        assert_eq!(*queue.peek().unwrap().data(), 1);
+        assert_eq!(queue.dequeue(), Some(1));
+        assert_eq!(queue.peek().unwrap().cloned(), 2);
+        assert_eq!(queue.dequeue(), Some(2));
+        assert!(queue.peek().is_none());
+
+        // A guard stays valid after its element is dequeued, even by the same thread
+        queue.enqueue_batch(vec![3, 4]).unwrap();
+        let guard = queue.peek().unwrap();
+        assert_eq!(queue.dequeue_batch(2), vec![3, 4]);
+        assert_eq!(*guard.data(), 3);
+        drop(guard);
+
+        // Peekers and dequeuers race over the same elements
+        let queue: Arc<Queue<[usize; 4]>> = Arc::new(Queue::new());
+        let mut waitvec: Vec<thread::JoinHandle<()>> = Vec::new();
+        for _ in 0..4 {
+            let queue_copy = queue.clone();
+            waitvec.push(thread::spawn(move || {
+                for i in 0..5000 {
+                    queue_copy.enqueue([i; 4]).unwrap();
+                    queue_copy.dequeue();
+                }
+            }));
+            let queue_copy = queue.clone();
+            waitvec.push(thread::spawn(move || {
+                for _ in 0..5000 {
+                    if let Some(guard) = queue_copy.peek() {
+                        let data = guard.data();
+                        assert!(data[0] < 5000 && data.iter().all(|&x| x == data[0]));
+                    }
+                }
+            }));
+        }
+        for handle in waitvec {
+            handle.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_close() {
+        let queue: Queue<u8> = Queue::new();
+        assert!(!queue.is_closed());
+        queue.enqueue(1).unwrap();
+        queue.enqueue_batch(vec![2, 3, 4]).unwrap();
+        queue.close();
+        queue.close();
+        assert!(queue.is_closed());
+        assert_eq!(queue.enqueue(5), Err(5));
+        assert_eq!(queue.enqueue_batch(vec![6, 7]), Err(vec![6, 7]));
+
+        // The remaining elements are still drained
+        assert_eq!(*queue.peek().unwrap().data(), 1);
+        assert_eq!(queue.try_dequeue(), Ok(1));
+        assert_eq!(queue.dequeue_batch(2), vec![2, 3]);
+        assert_eq!(queue.dequeue(), Some(4));
+        assert!(queue.peek().is_none());
+        assert_eq!(queue.try_dequeue(), Err(TryDequeueError::Closed));
+        assert_eq!(queue.dequeue(), None);
+
+        let queue: Queue<u8> = Queue::new();
+        assert_eq!(queue.try_dequeue(), Err(TryDequeueError::Empty));
+        queue.close();
+        assert_eq!(queue.try_dequeue(), Err(TryDequeueError::Closed));
+    }
+
+    #[test]
+    fn test_close_multithreaded() {
+        let queue: Arc<Queue<usize>> = Arc::new(Queue::new());
+        let mut producers: Vec<thread::JoinHandle<usize>> = Vec::new();
+        for _ in 0..4 {
+            let queue_copy = queue.clone();
+            producers.push(thread::spawn(move || {
+                let mut sum = 0;
+                for i in 1.. {
+                    if queue_copy.enqueue(i).is_err() {
+                        return sum
+                    }
+                    sum += i;
+                }
+                sum
+            }));
+        }
+        let mut consumers: Vec<thread::JoinHandle<usize>> = Vec::new();
+        for _ in 0..4 {
+            let queue_copy = queue.clone();
+            consumers.push(thread::spawn(move || {
+                let mut sum = 0;
+                loop {
+                    match queue_copy.try_dequeue() {
+                        Ok(val) => sum += val,
+                        Err(TryDequeueError::Empty) => {},
+                        Err(TryDequeueError::Closed) => return sum
+                    }
+                }
+            }));
+        }
+        thread::sleep(std::time::Duration::from_millis(10));
+        queue.close();
+        // Every element accepted before the queue was closed is drained exactly once
+        let enqueued: usize = producers.into_iter().map(|handle| handle.join().unwrap()).sum();
+        let dequeued: usize = consumers.into_iter().map(|handle| handle.join().unwrap()).sum();
+        assert_eq!(enqueued, dequeued);
+    }
+
+    #[test]
+    fn test_values_dropped_once() {
+        let drops = DropCounter::new();
+        {
+            let queue: Queue<Dropper> = Queue::new();
+            queue.enqueue(drops.dropper()).unwrap();
+            queue.enqueue(drops.dropper()).unwrap();
+            queue.enqueue(drops.dropper()).unwrap();
+            drop(queue.dequeue());
+            drop(queue.dequeue());
+        }
+        assert_eq!(drops.drops(), 3);
+    }
+
     #[test]
     fn test_linearizable() {
         let queue: Queue<usize> = Queue::new();
@@ -311,7 +818,7 @@ mod tests {
                 let rand = thread_rng().gen_range(0, 101);
                 if rand < 30 {
                     let val = thread_rng().gen();
-                    log.log_val(id, Queue::enqueue, val, format!("enqueue: {}", val), sequential_enqueue);
+                    log.log_val(id, |queue: &Queue<usize>, val| queue.enqueue(val).unwrap(), val, format!("enqueue: {}", val), sequential_enqueue);
                 } else {
                     log.log(id, Queue::dequeue, "dequeue".to_owned(), sequential_dequeue);
                 }
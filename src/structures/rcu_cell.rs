@@ -0,0 +1,189 @@
+use std::sync::atomic::AtomicPtr;
+use std::sync::atomic::Ordering::{Acquire, AcqRel, Relaxed};
+use std::fmt::Debug;
+use std::fmt;
+use std::ptr;
+use memory::{HPBRManager, HPHandle};
+
+/// A lock-free cell for read-mostly values, in the style of read-copy-update.
+///
+/// Readers borrow the current value through an RcuGuard, which costs a single load protected
+/// by a hazard pointer and never blocks writers. Writers never modify the value in place: `update`
+/// builds a new value from the current one and installs it with a CAS, retrying if another writer
+/// got there first. The replaced value is retired through an HPBRManager, so it is only dropped once
+/// every reader borrowing it has released its guard.
+///
+/// This makes the cell well suited to data such as configuration, which is read on every request
+/// but only reloaded occasionally.
+/// # Usage
+/// ```
+/// let config: Arc<RcuCell<String>> = Arc::new(RcuCell::new("v1".to_owned()));
+/// let config_clone = config.clone();
+/// thread::spawn(move || {
+///     config_clone.update(|old| format!("{} -> v2", old));
+/// });
+/// println!("{}", config.read().data());
+/// ```
+pub struct RcuCell<T: Send + Sync> {
+    ptr: AtomicPtr<T>,
+    manager: HPBRManager<T>
+}
+
+impl<T: Send + Sync> RcuCell<T> {
+    /// Create a new RcuCell holding the given value.
+    /// # Examples
+    /// ```
+    /// let cell: RcuCell<u8> = RcuCell::new(8);
+    /// ```
+    pub fn new(value: T) -> Self {
+        RcuCell {
+            ptr: AtomicPtr::new(Box::into_raw(Box::new(value))),
+            manager: HPBRManager::new(100, 1)
+        }
+    }
+
+    /// Borrow the current value, protected by an RcuGuard. The value is guaranteed
+    /// to live for as long as the guard, even if it is replaced in the meantime.
+    /// # Examples
+    /// ```
+    /// let cell: RcuCell<u8> = RcuCell::new(8);
+    /// assert_eq!(cell.read().data(), &8);
+    /// ```
+    pub fn read(&self) -> RcuGuard<'_, T> {
+        loop {
+            let current = self.ptr.load(Acquire);
+            let handle = self.manager.protect_dynamic(current);
+            if ptr::eq(current, self.ptr.load(Acquire)) {
+                return RcuGuard::new(unsafe { &*current }, handle)
+            }
+        }
+    }
+
+    /// Replace the current value with one computed from it. If another thread replaces the value
+    /// first, the function is called again on the newer value, so it may run more than once.
+    /// # Examples
+    /// ```
+    /// let cell: RcuCell<u8> = RcuCell::new(8);
+    /// cell.update(|old| old + 1);
+    /// assert_eq!(cell.read().data(), &9);
+    /// ```
+    pub fn update<F>(&self, mut f: F)
+    where F: FnMut(&T) -> T
+    {
+        loop {
+            let current = self.ptr.load(Acquire);
+            self.manager.protect(current, 0);
+            if !ptr::eq(current, self.ptr.load(Acquire)) {
+                continue;
+            }
+
+            let new_ptr = Box::into_raw(Box::new(f(unsafe { &*current })));
+            match self.ptr.compare_exchange(current, new_ptr, AcqRel, Relaxed) {
+                Ok(_) => {
+                    self.manager.retire(current, 0);
+                    return;
+                },
+                Err(_) => {
+                    unsafe { drop(Box::from_raw(new_ptr)) };
+                }
+            }
+        }
+    }
+}
+
+impl<T: Send + Sync + Debug> Debug for RcuCell<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "RcuCell({:?})", self.read().data())
+    }
+}
+
+impl<T: Send + Sync> Drop for RcuCell<T> {
+    fn drop(&mut self) {
+        let current = self.ptr.load(Relaxed);
+        unsafe { drop(Box::from_raw(current)) };
+    }
+}
+
+/// A borrow of the value held in an RcuCell, protected by a HPHandle. When the guard goes out
+/// of scope, the value is unprotected and may be dropped if it has been replaced.
+pub struct RcuGuard<'a, T: Send + Sync + 'a> {
+    data: &'a T,
+    handle: HPHandle<'a, T>
+}
+
+impl<'a, T: Send + Sync + 'a> RcuGuard<'a, T> {
+    fn new(data: &'a T, handle: HPHandle<'a, T>) -> Self {
+        RcuGuard {
+            data,
+            handle
+        }
+    }
+
+    /// Access the data inside the guard. The reference cannot outlive the guard.
+    /// # Example
+    /// ```
+    /// let guard = cell.read();
+    /// println!("{}", guard.data());
+    /// ```
+    pub fn data(&self) -> &T {
+        self.data
+    }
+}
+
+impl<'a, T: Send + Sync + Clone + 'a> RcuGuard<'a, T> {
+    /// Consume the guard to obtain a clone of the protected data.
+    /// # Example
+    /// ```
+    /// let guard = cell.read();
+    /// let data = guard.cloned(); // The value is unprotected
+    /// ```
+    pub fn cloned(self) -> T {
+        self.data.clone()
+    }
+}
+
+impl<'a, T: Debug + Send + Sync + 'a> Debug for RcuGuard<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "RcuGuard({:?})", self.data)
+    }
+}
+
+mod tests {
+    #![allow(unused_imports)]
+    use super::RcuCell;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_single_threaded() {
+        let cell: RcuCell<String> = RcuCell::new("hello".to_owned());
+        let guard = cell.read();
+        cell.update(|old| format!("{} world", old));
+        // The old value is still borrowed by the guard
+        assert_eq!(guard.data(), "hello");
+        assert_eq!(cell.read().data(), "hello world");
+        assert_eq!(cell.read().cloned(), "hello world".to_owned());
+    }
+
+    #[test]
+    fn test_multithreaded_update() {
+        let cell: Arc<RcuCell<usize>> = Arc::new(RcuCell::new(0));
+        let mut waitvec: Vec<thread::JoinHandle<()>> = Vec::new();
+
+        for _ in 0..20 {
+            let cell_copy = cell.clone();
+            waitvec.push(thread::spawn(move || {
+                for _ in 0..1000 {
+                    cell_copy.update(|old| old + 1);
+                    let guard = cell_copy.read();
+                    assert!(*guard.data() <= 20000);
+                }
+            }));
+        }
+
+        for handle in waitvec {
+            handle.join().unwrap();
+        }
+        assert_eq!(cell.read().data(), &20000);
+    }
+}
@@ -1,11 +1,19 @@
 use memory::HPBRManager;
+use stats::Counters;
+#[cfg(feature = "stats")]
+use stats::Stats;
 use std::sync::atomic::{AtomicPtr};
 use std::sync::atomic::Ordering::{Acquire, Release, Relaxed};
 use std::ptr;
-use std::cell::UnsafeCell;
-use super::utils::atomic_markable::AtomicMarkablePtr;
-use super::utils::atomic_markable;
-use rand::{Rng, SmallRng, NewRng};
+use super::utils::{TaggedAtomicPtr, random_usize};
+use super::TryDequeueError;
+use backoff::{Backoff, NoBackoff};
+
+/// Tag for a cell whose item has been dequeued, or which was sealed while empty when the queue was closed.
+const MARKED: usize = 0x1;
+
+/// Tag on the next link of the last segment, once the queue has been closed.
+const CLOSED: usize = 0x1;
 
 /// A lock-free k-FIFO segmented queue.
 ///
@@ -20,16 +28,22 @@ use rand::{Rng, SmallRng, NewRng};
 /// 
 /// If relaxed consistency is undesirable, do not set `k` to 1. Instead, use the Queue structure
 /// from the `rustcurrent` library as it is far better optimised for that scenario.
+///
+/// The queue can be closed, which marks the next link of the last segment so no segment can follow it,
+/// and then seals the empty cells of that segment. Enqueues then fail and hand their element back, while
+/// dequeues drain the remaining elements before reporting that the queue is closed rather than empty.
+///
+/// Failed operations retry at once, unless the queue is created with a Backoff strategy through
+/// `new_with_backoff`.
 pub struct SegQueue<T: Send> {
     head:AtomicPtr<Segment<T>>,
     tail: AtomicPtr<Segment<T>>,
     manager: HPBRManager<Segment<T>>,
-    rng: UnsafeCell<SmallRng>,
-    k: usize
+    backoff: Box<dyn Backoff>,
+    k: usize,
+    stats: Counters
 }
 
-unsafe impl<T: Send> Sync for SegQueue<T> {}
-
 impl<T: Send> SegQueue<T> {
     /// Create a new SegQueue with a given node size. The node size must be
     /// a power of 2.
@@ -38,6 +52,16 @@ impl<T: Send> SegQueue<T> {
     /// let queue: SegQueue<u8> = SegQueue::new(8);
     /// ```
     pub fn new(k: usize) -> Self {
+        SegQueue::new_with_backoff(k, NoBackoff)
+    }
+
+    /// Create a new SegQueue with a given node size, which must be a power of 2, and which
+    /// waits between failed attempts according to the given strategy.
+    /// # Examples
+    /// ```
+    /// let queue: SegQueue<u8> = SegQueue::new_with_backoff(8, YieldBackoff);
+    /// ```
+    pub fn new_with_backoff<B: Backoff + 'static>(k: usize, backoff: B) -> Self {
         if (k & !(k - 1)) != k {
             panic!("k must be a non-zero power of 2!")
         }
@@ -46,28 +70,41 @@ impl<T: Send> SegQueue<T> {
             head: AtomicPtr::new(init_node),
             tail: AtomicPtr::new(init_node),
             manager: HPBRManager::new(100, 2),
-            rng: UnsafeCell::new(SmallRng::new()),
-            k
+            backoff: Box::new(backoff),
+            k,
+            stats: Counters::new()
         }
     }
 
-    /// Enqueue the given data.
+    /// Enqueue the given data. If the queue has been closed, the data is handed back inside Err.
     /// # Examples
     /// ```
     /// let queue: SegQueue<u8> = SegQueue::new(8);
-    /// queue.enqueue(8);
+    /// assert_eq!(queue.enqueue(8), Ok(()));
+    /// queue.close();
+    /// assert_eq!(queue.enqueue(9), Err(9));
     /// ``` 
-    pub fn enqueue(&self, data: T) {
-        let mut data_box = Box::new(data);
+    pub fn enqueue(&self, data: T) -> Result<(), T> {
+        let mut data_box = Box::new(Item(data));
+        let mut failures = 0;
         loop {
-            data_box = match self.try_enqueue(data_box) {
-                Ok(()) => { return; },
+            data_box = match self.attempt_enqueue(data_box) {
+                Ok(None) => { return Ok(()); },
+                Ok(Some(val)) => { return Err(val.0); },
                 Err(val) => val
             };
+            failures += 1;
+            self.backoff(failures);
         }
     }
 
-    fn try_enqueue(&self, mut data: Box<T>) -> Result<(), Box<T>> {
+    fn backoff(&self, failures: u32) {
+        self.stats.backoff();
+        self.backoff.backoff(failures);
+    }
+
+    /// Returns the data inside Ok if the queue is closed, and inside Err if the attempt should be retried.
+    fn attempt_enqueue(&self, mut data: Box<Item<T>>) -> Result<Option<Box<Item<T>>>, Box<Item<T>>> {
         let tail = self.tail.load(Acquire);
         self.manager.protect(tail, 0);
 
@@ -76,25 +113,32 @@ impl<T: Send> SegQueue<T> {
             return Err(data)
         }
 
-        let rand: usize = unsafe { (*self.rng.get()).gen() };
-        let permutation_start = rand & (self.k - 1);
+        let permutation_start = random_usize() & (self.k - 1);
         let permutation = OrderGenerator::new(permutation_start, self.k);
 
         for index in permutation.iter() {
             let cell = &Segment::get_cells_from_ptr(tail)[index];
-            data = match cell.get_ptr() {
+            data = match cell.load_non_null(Acquire) {
                 None => {
                     let item_ptr = Box::into_raw(data);
-                    match cell.compare_exchange(ptr::null_mut(), item_ptr) {
-                        Ok(_) => { return Ok(()) },
-                        Err(_) => { unsafe { Box::from_raw(item_ptr) } }
+                    match cell.compare_exchange(ptr::null_mut(), item_ptr, Release, Relaxed) {
+                        Ok(_) => { return Ok(None) },
+                        Err(_) => { 
+                            self.stats.cas_failure();
+                            unsafe { Box::from_raw(item_ptr) } 
+                        }
                     }
                 },
                 Some(_) => { continue; }
             }
         }
 
-        // No available position, need to create a new segment
+        // No available position, so the queue is closed if no segment can follow this one
+        if TaggedAtomicPtr::has_tag(unsafe { (*tail).next.load(Acquire) }, CLOSED) {
+            return Ok(Some(data))
+        }
+
+        // Otherwise we need to create a new segment
         self.advance_tail(tail);
         Err(data)
     }
@@ -104,45 +148,62 @@ impl<T: Send> SegQueue<T> {
     /// # Examples
     /// ```
     /// let queue: SegQueue<u8> = SegQueue::new(8);
-    /// queue.enqueue(8);
+    /// queue.enqueue(8).unwrap();
     /// assert_eq!(queue.dequeue(), Some(8));
     /// ```
     pub fn dequeue(&self) -> Option<T> {
+        self.try_dequeue().ok()
+    }
+
+    /// Attempt to dequeue a piece of data, or tell whether the queue is empty or closed. A closed
+    /// queue still hands out its remaining data, and only reports `TryDequeueError::Closed` once
+    /// it has been drained.
+    /// # Examples
+    /// ```
+    /// let queue: SegQueue<u8> = SegQueue::new(8);
+    /// assert_eq!(queue.try_dequeue(), Err(TryDequeueError::Empty));
+    /// queue.enqueue(8).unwrap();
+    /// queue.close();
+    /// assert_eq!(queue.try_dequeue(), Ok(8));
+    /// assert_eq!(queue.try_dequeue(), Err(TryDequeueError::Closed));
+    /// ```
+    pub fn try_dequeue(&self) -> Result<T, TryDequeueError> {
+        let mut failures = 0;
         loop {
-            if let Ok(val) = self.try_dequeue() {
+            if let Ok(val) = self.attempt_dequeue() {
                 return val
             }
+            failures += 1;
+            self.backoff(failures);
         }
     }
 
-    fn try_dequeue(&self) -> Result<Option<T>, ()> {
+    fn attempt_dequeue(&self) -> Result<Result<T, TryDequeueError>, ()> {
         let head = self.head.load(Acquire);
         self.manager.protect(head, 0);
         if !ptr::eq(head, self.head.load(Acquire)) {
             return Err(())
         }
 
-        let rand: usize = unsafe { (*self.rng.get()).gen() };
-        let permutation_start = rand & (self.k - 1);
+        let permutation_start = random_usize() & (self.k - 1);
         let permutation = OrderGenerator::new(permutation_start, self.k);
 
         let mut has_empty = false;
         for index in permutation.iter() {
             let cell = &Segment::get_cells_from_ptr(head)[index];
-            match cell.get_ptr() {
+            match cell.load_non_null(Acquire) {
                 Some(item_ptr) => {
-                    if !atomic_markable::is_marked(item_ptr) {
+                    if !TaggedAtomicPtr::has_tag(item_ptr, MARKED) {
                         // Try to mark it as deleted
-                        match cell.compare_and_mark(item_ptr) {
+                        match cell.compare_and_set_tag(item_ptr, MARKED, Release, Relaxed) {
                             Ok(_) => { 
-                                // We got it, read
-                                // Need to now treat this as unitialised memory
-                                let data = unsafe { ptr::read(item_ptr) };
-                                unsafe { Box::from_raw(item_ptr) } ; 
-                                return Ok(Some(data)) 
+                                // We got it, the marked cell no longer owns the item
+                                let item = unsafe { Box::from_raw(item_ptr) };
+                                return Ok(Ok(item.0)) 
                             },
                             Err(_) => {
                                 // We didn't get it
+                                self.stats.cas_failure();
                             }
                         }
                     }
@@ -153,11 +214,20 @@ impl<T: Send> SegQueue<T> {
             }
         }
 
-        // How do we tell if the queue is empty?
-        if ptr::eq(head, self.tail.load(Acquire)) || has_empty {
+        if has_empty {
             // Must be the last node, because there are empty slots
-            // If we reach the end and there are empty spots, we return None
-            return Ok(None)
+            return Ok(Err(TryDequeueError::Empty))
+        }
+
+        // Every cell has been dequeued or sealed, so if the segment is closed, the queue is drained
+        if TaggedAtomicPtr::has_tag(unsafe { (*head).next.load(Acquire) }, CLOSED) {
+            return Ok(Err(TryDequeueError::Closed))
+        }
+
+        // How do we tell if the queue is empty?
+        if ptr::eq(head, self.tail.load(Acquire)) {
+            // If we reach the end and every slot is taken, we return None
+            return Ok(Err(TryDequeueError::Empty))
         }
 
         // Queue is not empty but we didn't find a slot - need to advance the head
@@ -168,6 +238,9 @@ impl<T: Send> SegQueue<T> {
     fn advance_tail(&self, tail_old: *mut Segment<T>) {
         if ptr::eq(tail_old, self.tail.load(Acquire)) {
             let next = unsafe { (*tail_old).next.load(Acquire)}; 
+            if TaggedAtomicPtr::has_tag(next, CLOSED) {
+                return;
+            }
             if next.is_null() {
                 // Create a new segment
                 let new_seg_ptr: *mut Segment<T> = Box::into_raw(Box::new(Segment::new(self.k)));
@@ -198,7 +271,7 @@ impl<T: Send> SegQueue<T> {
             }
             if ptr::eq(tail, head_old) {
                 let tail_next = unsafe { (*tail).next.load(Acquire) }; 
-                if tail_next.is_null() {
+                if TaggedAtomicPtr::untagged(tail_next).is_null() {
                     // Queue only has one segment
                     return;
                 }    
@@ -214,6 +287,81 @@ impl<T: Send> SegQueue<T> {
         }
         
     }
+
+    /// Close the queue. Further enqueues fail and hand their data back, while dequeues carry on taking
+    /// the remaining data, and `try_dequeue` then reports `TryDequeueError::Closed`. Closing a closed
+    /// queue does nothing.
+    /// # Examples
+    /// ```
+    /// let queue: SegQueue<u8> = SegQueue::new(8);
+    /// queue.enqueue(1).unwrap();
+    /// queue.close();
+    /// assert_eq!(queue.enqueue(2), Err(2));
+    /// assert_eq!(queue.dequeue(), Some(1));
+    /// ```
+    pub fn close(&self) {
+        let mut failures = 0;
+        let last = loop {
+            let (last, next) = self.find_last();
+            if TaggedAtomicPtr::has_tag(next, CLOSED) {
+                break last
+            }
+            let closed = TaggedAtomicPtr::with_tag(ptr::null_mut(), CLOSED);
+            if unsafe { (*last).next.compare_exchange(next, closed, Release, Relaxed) }.is_ok() {
+                break last
+            }
+            self.stats.cas_failure();
+            failures += 1;
+            self.backoff(failures);
+        };
+        // Enqueues can still fill the empty cells of the last segment, so those are sealed, as if
+        // their item had already been dequeued. Another closing thread may be sealing them too
+        for cell in Segment::get_cells_from_ptr(last) {
+            let sealed = TaggedAtomicPtr::with_tag(ptr::null_mut(), MARKED);
+            let _ = cell.compare_exchange(ptr::null_mut(), sealed, Release, Relaxed);
+        }
+    }
+
+    /// Check whether the queue has been closed. It may still hold data.
+    /// # Examples
+    /// ```
+    /// let queue: SegQueue<u8> = SegQueue::new(8);
+    /// assert!(!queue.is_closed());
+    /// queue.close();
+    /// assert!(queue.is_closed());
+    /// ```
+    pub fn is_closed(&self) -> bool {
+        TaggedAtomicPtr::has_tag(self.find_last().1, CLOSED)
+    }
+
+    /// Find the last segment of the queue, helping the tail on to it, and return it protected along
+    /// with its next link, which is either null or closed.
+    fn find_last(&self) -> (*mut Segment<T>, *mut Segment<T>) {
+        loop {
+            let tail = self.tail.load(Acquire);
+            self.manager.protect(tail, 0);
+            if !ptr::eq(tail, self.tail.load(Acquire)) {
+                continue;
+            }
+            let next = unsafe { (*tail).next.load(Acquire) };
+            if TaggedAtomicPtr::untagged(next).is_null() {
+                return (tail, next)
+            }
+            let _ = self.tail.compare_exchange(tail, next, Release, Relaxed);
+        }
+    }
+
+    /// Get a snapshot of the contention and reclamation counters of the queue.
+    /// # Examples
+    /// ```
+    /// let queue: SegQueue<u8> = SegQueue::new(8);
+    /// queue.enqueue(8).unwrap();
+    /// println!("{:?}", queue.stats());
+    /// ```
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> Stats {
+        self.stats.snapshot() + self.manager.stats()
+    }
 }
 
 impl<T: Send> Drop for SegQueue<T> {
@@ -221,7 +369,7 @@ impl<T: Send> Drop for SegQueue<T> {
         let mut current = self.head.load(Relaxed);
         while !current.is_null() {
             unsafe {
-                let next = (*current).next.load(Relaxed);
+                let next = TaggedAtomicPtr::untagged((*current).next.load(Relaxed));
                 Box::from_raw(current);
                 current = next;
             }
@@ -229,28 +377,45 @@ impl<T: Send> Drop for SegQueue<T> {
     }
 }
 
+/// Items are boxed in this wrapper so that pointers to them always have a free bit for the
+/// mark, whatever the alignment of `T`.
+#[repr(align(2))]
+struct Item<T>(T);
+
 struct Segment<T: Send> {
-    cells: Vec<AtomicMarkablePtr<T>>,
-    next: AtomicPtr<Segment<T>>
+    cells: Vec<TaggedAtomicPtr<Item<T>>>,
+    next: TaggedAtomicPtr<Segment<T>>
 }
 
 impl<T: Send> Segment<T> {
     fn new(k: usize) -> Self {
-        let mut cells: Vec<AtomicMarkablePtr<T>> = Vec::new();
+        let mut cells: Vec<TaggedAtomicPtr<Item<T>>> = Vec::new();
         for _ in 0..k {
-            cells.push(AtomicMarkablePtr::default())
+            cells.push(TaggedAtomicPtr::default())
         }
         Segment {
             cells,
-            next: AtomicPtr::default()
+            next: TaggedAtomicPtr::default()
         }
     }
 
-    fn get_cells_from_ptr<'a>(ptr: *mut Segment<T>) -> &'a Vec<AtomicMarkablePtr<T>> {
+    fn get_cells_from_ptr<'a>(ptr: *mut Segment<T>) -> &'a Vec<TaggedAtomicPtr<Item<T>>> {
         unsafe { &(*ptr).cells }
     }
 }
 
+impl<T: Send> Drop for Segment<T> {
+    fn drop(&mut self) {
+        // Marked cells point to items that have already been dequeued
+        for cell in &self.cells {
+            let item_ptr = cell.load(Relaxed);
+            if !TaggedAtomicPtr::has_tag(item_ptr, MARKED) && !item_ptr.is_null() {
+                unsafe { Box::from_raw(item_ptr) };
+            }
+        }
+    }
+}
+
 struct OrderGenerator {
     start: usize,
     size: usize
@@ -303,6 +468,7 @@ mod tests {
 
     use rand::{thread_rng, Rng};
     use super::{SegQueue, OrderGenerator};
+    use super::super::TryDequeueError;
     use std::sync::Arc;
     use std::thread;
     
@@ -328,7 +494,7 @@ mod tests {
             let mut queue_copy = queue.clone();
             waitvec.push(thread::spawn(move || {
                 for i in 0..10000 {
-                    queue_copy.enqueue(i);
+                    queue_copy.enqueue(i).unwrap();
                 }
                 println!("Push thread {} complete", thread_no);
             }));
@@ -364,6 +530,70 @@ mod tests {
         assert_eq!(None, queue.dequeue());
     }
 
+    #[test]
+    fn test_close() {
+        let queue: SegQueue<u8> = SegQueue::new(4);
+        assert!(!queue.is_closed());
+        for i in 0..6 {
+            queue.enqueue(i).unwrap();
+        }
+        queue.close();
+        queue.close();
+        assert!(queue.is_closed());
+        assert_eq!(queue.enqueue(6), Err(6));
+
+        // The remaining data is still drained, in any order within a segment
+        let mut drained: Vec<u8> = (0..6).map(|_| queue.try_dequeue().unwrap()).collect();
+        drained.sort();
+        assert_eq!(drained, vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(queue.try_dequeue(), Err(TryDequeueError::Closed));
+        assert_eq!(queue.dequeue(), None);
+
+        let queue: SegQueue<u8> = SegQueue::new(4);
+        assert_eq!(queue.try_dequeue(), Err(TryDequeueError::Empty));
+        queue.close();
+        assert_eq!(queue.try_dequeue(), Err(TryDequeueError::Closed));
+    }
+
+    #[test]
+    fn test_close_multithreaded() {
+        let queue: Arc<SegQueue<usize>> = Arc::new(SegQueue::new(8));
+        let mut producers: Vec<thread::JoinHandle<usize>> = Vec::new();
+        for _ in 0..4 {
+            let queue_copy = queue.clone();
+            producers.push(thread::spawn(move || {
+                let mut sum = 0;
+                for i in 1.. {
+                    if queue_copy.enqueue(i).is_err() {
+                        return sum
+                    }
+                    sum += i;
+                }
+                sum
+            }));
+        }
+        let mut consumers: Vec<thread::JoinHandle<usize>> = Vec::new();
+        for _ in 0..4 {
+            let queue_copy = queue.clone();
+            consumers.push(thread::spawn(move || {
+                let mut sum = 0;
+                loop {
+                    match queue_copy.try_dequeue() {
+                        Ok(val) => sum += val,
+                        Err(TryDequeueError::Empty) => {},
+                        Err(TryDequeueError::Closed) => return sum
+                    }
+                }
+            }));
+        }
+        thread::sleep(std::time::Duration::from_millis(10));
+        queue.close();
+        // Every element accepted before the queue was closed is drained exactly once
+        let enqueued: usize = producers.into_iter().map(|handle| handle.join().unwrap()).sum();
+        let dequeued: usize = consumers.into_iter().map(|handle| handle.join().unwrap()).sum();
+        assert_eq!(enqueued, dequeued);
+    }
+
     #[test]
     fn test_linearizabile_k_one() {
         let queue: SegQueue<usize> = SegQueue::new(1);
@@ -391,7 +621,7 @@ mod tests {
                 let rand = thread_rng().gen_range(0, 101);
                 if rand < 30 {
                     let val = thread_rng().gen();
-                    log.log_val(id, SegQueue::enqueue, val, format!("enqueue: {}", val), sequential_enqueue);
+                    log.log_val(id, |queue: &SegQueue<usize>, val| queue.enqueue(val).unwrap(), val, format!("enqueue: {}", val), sequential_enqueue);
                 } else {
                     log.log(id, SegQueue::dequeue, "dequeue".to_owned(), sequential_dequeue);
                 }
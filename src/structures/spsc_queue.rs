@@ -0,0 +1,228 @@
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering::{Acquire, Release, Relaxed};
+use std::sync::Arc;
+use std::cell::UnsafeCell;
+use std::mem::MaybeUninit;
+
+/// A wait-free bounded single-producer single-consumer queue, backed by a ring buffer.
+///
+/// With only one thread at each end, the producer and consumer never compete for the same index, so
+/// neither needs a CAS or a memory manager: each publishes its own index with a release store. Each
+/// side also keeps a cached copy of the other's index, and only reloads it when the queue looks full
+/// or empty, so in the common case the two threads do not touch each other's cache lines at all.
+///
+/// The queue is created as a pair of handles. The `SpscProducer` can only enqueue and the `SpscConsumer`
+/// can only dequeue, and neither can be cloned or shared, so the single-producer single-consumer
+/// requirement is enforced by the compiler. Each handle can be moved to its own thread.
+/// # Usage
+/// ```
+/// let (mut producer, mut consumer) = SpscQueue::with_capacity(64);
+/// thread::spawn(move || {
+///     for i in 0..100 {
+///         while producer.enqueue(i).is_err() {}
+///     }
+/// });
+/// for _ in 0..100 {
+///     loop {
+///         if let Some(val) = consumer.dequeue() {
+///             println!("{}", val);
+///             break;
+///         }
+///     }
+/// }
+/// ```
+pub struct SpscQueue<T: Send> {
+    buffer: Box<[UnsafeCell<MaybeUninit<T>>]>,
+    mask: usize,
+    head: Position,
+    tail: Position
+}
+
+unsafe impl<T: Send> Sync for SpscQueue<T> {}
+unsafe impl<T: Send> Send for SpscQueue<T> {}
+
+/// An index on its own cache line, so that the producer and consumer do not slow each other down.
+#[repr(align(64))]
+struct Position(AtomicUsize);
+
+/// The enqueuing end of an SpscQueue.
+pub struct SpscProducer<T: Send> {
+    queue: Arc<SpscQueue<T>>,
+    tail: usize,
+    cached_head: usize
+}
+
+/// The dequeuing end of an SpscQueue.
+pub struct SpscConsumer<T: Send> {
+    queue: Arc<SpscQueue<T>>,
+    head: usize,
+    cached_tail: usize
+}
+
+impl<T: Send> SpscQueue<T> {
+    /// Create a new queue with the given capacity, which must be a power of 2, and return its
+    /// producer and consumer handles.
+    /// # Examples
+    /// ```
+    /// let (producer, consumer): (SpscProducer<u8>, SpscConsumer<u8>) = SpscQueue::with_capacity(64);
+    /// ```
+    pub fn with_capacity(capacity: usize) -> (SpscProducer<T>, SpscConsumer<T>) {
+        if capacity == 0 || (capacity & (capacity - 1)) != 0 {
+            panic!("capacity must be a non-zero power of 2!")
+        }
+        let buffer: Vec<UnsafeCell<MaybeUninit<T>>> = (0..capacity).map(|_| UnsafeCell::new(MaybeUninit::uninit())).collect();
+        let queue = Arc::new(SpscQueue {
+            buffer: buffer.into_boxed_slice(),
+            mask: capacity - 1,
+            head: Position(AtomicUsize::new(0)),
+            tail: Position(AtomicUsize::new(0))
+        });
+        let producer = SpscProducer {
+            queue: queue.clone(),
+            tail: 0,
+            cached_head: 0
+        };
+        let consumer = SpscConsumer {
+            queue,
+            head: 0,
+            cached_tail: 0
+        };
+        (producer, consumer)
+    }
+
+    fn capacity(&self) -> usize {
+        self.mask + 1
+    }
+}
+
+impl<T: Send> SpscProducer<T> {
+    /// Add an element to the back of the queue, or hand it back inside Err if the queue is full.
+    /// # Examples
+    /// ```
+    /// let (mut producer, mut consumer) = SpscQueue::with_capacity(1);
+    /// assert_eq!(producer.enqueue(1), Ok(()));
+    /// assert_eq!(producer.enqueue(2), Err(2));
+    /// ```
+    pub fn enqueue(&mut self, val: T) -> Result<(), T> {
+        let queue = &*self.queue;
+        if self.tail.wrapping_sub(self.cached_head) == queue.capacity() {
+            self.cached_head = queue.head.0.load(Acquire);
+            if self.tail.wrapping_sub(self.cached_head) == queue.capacity() {
+                return Err(val)
+            }
+        }
+        unsafe { (*queue.buffer[self.tail & queue.mask].get()).as_mut_ptr().write(val) };
+        self.tail = self.tail.wrapping_add(1);
+        queue.tail.0.store(self.tail, Release);
+        Ok(())
+    }
+
+    /// The maximum number of elements the queue can hold.
+    pub fn capacity(&self) -> usize {
+        self.queue.capacity()
+    }
+}
+
+impl<T: Send> SpscConsumer<T> {
+    /// Take an element from the front of the queue, or return None if the queue is empty.
+    /// # Examples
+    /// ```
+    /// let (mut producer, mut consumer) = SpscQueue::with_capacity(64);
+    /// producer.enqueue(1);
+    /// assert_eq!(consumer.dequeue(), Some(1));
+    /// assert_eq!(consumer.dequeue(), None);
+    /// ```
+    pub fn dequeue(&mut self) -> Option<T> {
+        let queue = &*self.queue;
+        if self.head == self.cached_tail {
+            self.cached_tail = queue.tail.0.load(Acquire);
+            if self.head == self.cached_tail {
+                return None
+            }
+        }
+        let val = unsafe { (*queue.buffer[self.head & queue.mask].get()).as_ptr().read() };
+        self.head = self.head.wrapping_add(1);
+        queue.head.0.store(self.head, Release);
+        Some(val)
+    }
+
+    /// The maximum number of elements the queue can hold.
+    pub fn capacity(&self) -> usize {
+        self.queue.capacity()
+    }
+}
+
+impl<T: Send> Drop for SpscQueue<T> {
+    fn drop(&mut self) {
+        // Both handles are gone, so the indices are final
+        let tail = self.tail.0.load(Relaxed);
+        let mut head = self.head.0.load(Relaxed);
+        while head != tail {
+            unsafe { (*self.buffer[head & self.mask].get()).as_ptr().read() };
+            head = head.wrapping_add(1);
+        }
+    }
+}
+
+mod tests {
+    #![allow(unused_imports)]
+    use super::{SpscQueue, SpscProducer, SpscConsumer};
+    use super::super::super::testing::{DropCounter, Dropper};
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    #[test]
+    fn test_single_threaded() {
+        let (mut producer, mut consumer) = SpscQueue::with_capacity(4);
+        assert_eq!(producer.capacity(), 4);
+        assert_eq!(consumer.dequeue(), None);
+        for lap in 0..3 {
+            for i in 0..4 {
+                assert_eq!(producer.enqueue(lap * 4 + i), Ok(()));
+            }
+            assert_eq!(producer.enqueue(100), Err(100));
+            for i in 0..4 {
+                assert_eq!(consumer.dequeue(), Some(lap * 4 + i));
+            }
+            assert_eq!(consumer.dequeue(), None);
+        }
+    }
+
+    #[test]
+    fn test_multithreaded() {
+        let (mut producer, mut consumer) = SpscQueue::with_capacity(64);
+        let handle = thread::spawn(move || {
+            for i in 0..10000 {
+                let mut val = i;
+                while let Err(back) = producer.enqueue(val) {
+                    val = back;
+                }
+            }
+        });
+        for i in 0..10000 {
+            loop {
+                if let Some(val) = consumer.dequeue() {
+                    assert_eq!(val, i);
+                    break;
+                }
+            }
+        }
+        handle.join().unwrap();
+        assert_eq!(consumer.dequeue(), None);
+    }
+
+    #[test]
+    fn test_remaining_dropped() {
+        let drops = DropCounter::new();
+        let (mut producer, mut consumer) = SpscQueue::with_capacity(8);
+        for _ in 0..5 {
+            producer.enqueue(drops.dropper()).unwrap();
+        }
+        drop(consumer.dequeue());
+        drop(producer);
+        assert_eq!(drops.drops(), 1);
+        drop(consumer);
+        assert_eq!(drops.drops(), 5);
+    }
+}
@@ -1,12 +1,24 @@
 use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering::{Relaxed, Release, Acquire}};
+use std::hint;
 use std::ptr;
-use super::HashMap;
-use std::{thread, thread::ThreadId};
-use std::time::Duration;
-use std::cell::UnsafeCell;
-use rand::{Rng, SmallRng, NewRng};
+use super::utils::{current_thread_index, random_below, PeekGuard};
+use std::cmp;
 use memory::HPBRManager;
+use stats::Counters;
+use backoff::{Backoff, NoBackoff};
+#[cfg(feature = "stats")]
+use stats::Stats;
 use std::mem;
+use std::mem::ManuallyDrop;
+
+/// The number of threads which can take part in elimination at once. Threads with a higher
+/// index use the stack without elimination.
+const ELIMINATION_SLOTS: usize = 128;
+/// The largest collision range, where every thread taking part in elimination could be paired.
+const MAX_COLLISION_RANGE: usize = ELIMINATION_SLOTS / 2;
+/// The bounds on the number of spins a thread waits in the elimination layer for a partner.
+const MIN_ELIMINATION_SPINS: usize = 16;
+const MAX_ELIMINATION_SPINS: usize = 4096;
 
 /// A lock-free stack with optional elimination backoff.
 ///
@@ -14,7 +26,8 @@ use std::mem;
 /// with an optional elimination backoff layer as described by [Colvin and Groves](http://ieeexplore.ieee.org/document/4343950/).
 /// If the elimination layer is turned on, then when the stack is heavily contended, operations will
 /// attempt to match each other to exchange values without touching the stack at all, in a attempt to
-/// increase scalability.
+/// increase scalability. Operations which fail to eliminate, or every failed operation if the layer
+/// is off, wait according to a Backoff strategy before retrying, which by default is to retry at once.
 /// 
 /// The stack can be used in a multithreaded context by wrapping it in an Arc.
 /// # Usage
@@ -33,12 +46,32 @@ pub struct Stack<T: Send> {
     head: AtomicPtr<Node<T>>,
     elimination: EliminationLayer<T>,
     manager: HPBRManager<Node<T>>,
-    elimination_on: bool
+    elimination_on: bool,
+    backoff: Box<dyn Backoff>,
+    stats: Counters
 }
 
 
+/// The reason a call to `Stack::try_push` failed. The data that could not be pushed is handed back.
+#[derive(Debug, PartialEq)]
+pub enum TryPushError<T> {
+    /// Another thread changed the top of the stack during the attempt.
+    Contended(T)
+}
+
+/// The reason a call to `Stack::try_pop` failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TryPopError {
+    /// The stack was empty.
+    Empty,
+    /// Another thread changed the top of the stack during the attempt.
+    Contended
+}
+
+/// The data is read out of a popped node rather than taken, so that a PeekGuard still pointing at it sees
+/// it unchanged. A node therefore never drops its data, which is dropped by whoever removes it.
 struct Node<T: Send> {
-    data: Option<T>,
+    data: ManuallyDrop<Option<T>>,
     next: AtomicPtr<Node<T>>
 }
 
@@ -49,23 +82,45 @@ impl<T: Send> Stack<T> {
     /// let stack: Stack<u8> = Stack::new(true);
     /// ```
     pub fn new(elimination_on: bool) -> Stack<T> {
-        Stack {
-            head: AtomicPtr::default(),
-            elimination: EliminationLayer::new(5),
-            manager: HPBRManager::new(200, 1),
-            elimination_on
-        }
+        Stack::new_with_collision_size(elimination_on, 5)
     }
 
+    /// Create a new stack, with or without elimination layer, starting the elimination layer
+    /// with the given collision range. The range then adapts to the contention on the stack.
+    /// # Examples
+    /// ```
+    /// let stack: Stack<u8> = Stack::new_with_collision_size(true, 8);
+    /// ```
     pub fn new_with_collision_size(elimination_on: bool, collision_size: usize) -> Self {
-        Self {
+        Stack::build(elimination_on, collision_size, Box::new(NoBackoff))
+    }
+
+    /// Create a new stack, with or without elimination layer, which waits between failed attempts
+    /// according to the given strategy.
+    /// # Examples
+    /// ```
+    /// let stack: Stack<u8> = Stack::new_with_backoff(false, ExponentialSpin::new(10));
+    /// ```
+    pub fn new_with_backoff<B: Backoff + 'static>(elimination_on: bool, backoff: B) -> Self {
+        Stack::build(elimination_on, 5, Box::new(backoff))
+    }
+
+    fn build(elimination_on: bool, collision_size: usize, backoff: Box<dyn Backoff>) -> Self {
+        Stack {
             head: AtomicPtr::default(),
             elimination: EliminationLayer::new(collision_size),
             manager: HPBRManager::new(200, 1),
-            elimination_on
+            elimination_on,
+            backoff,
+            stats: Counters::new()
         }
     }
 
+    fn backoff(&self, failures: u32) {
+        self.stats.backoff();
+        self.backoff.backoff(failures);
+    }
+
     /// Push a piece of data onto the stack. This operation blocks until success,
     /// which is guaranteed by the lock-free data structure.
     /// # Examples
@@ -76,8 +131,9 @@ impl<T: Send> Stack<T> {
     pub fn push(&self, val: T) {
         let mut node_ptr = Box::into_raw(Box::new(Node::new(val)));
         let mut thread_info_ptr: *mut ThreadInfo<T> = ptr::null_mut();
+        let mut failures = 0;
         loop {
-            node_ptr = match self.try_push(node_ptr) {
+            node_ptr = match self.attempt_push(node_ptr) {
                 Ok(_) => {
                     if !thread_info_ptr.is_null() {
                         // Make sure this doesn't need to be done with the memory manager
@@ -85,7 +141,10 @@ impl<T: Send> Stack<T> {
                     } 
                     return; 
                 }
-                Err(old_node) => old_node
+                Err(old_node) => {
+                    self.stats.cas_failure();
+                    old_node
+                }
             };
             if thread_info_ptr.is_null() {
                 thread_info_ptr = Box::into_raw(Box::new(ThreadInfo::new(Some(node_ptr), OpType::Push)));
@@ -93,15 +152,18 @@ impl<T: Send> Stack<T> {
             if self.elimination_on {
                 match self.elimination.try_eliminate(thread_info_ptr, OpType::Push) {
                     Ok(_) => {
+                        self.stats.elimination();
                         return
                     },
                     Err(_) => {}
                 }
             }
+            failures += 1;
+            self.backoff(failures);
         }
     }
 
-    fn try_push(&self, node_ptr: *mut Node<T>) -> Result<(), *mut Node<T>> {
+    fn attempt_push(&self, node_ptr: *mut Node<T>) -> Result<(), *mut Node<T>> {
         let old_head = self.head.load(Acquire);
         unsafe { (*node_ptr).next = AtomicPtr::new(old_head) };
 
@@ -113,6 +175,29 @@ impl<T: Send> Stack<T> {
         }
     }
 
+    /// Make a single attempt to push a piece of data onto the stack, without retrying or
+    /// using the elimination layer. If another thread changed the stack during the attempt,
+    /// the data is handed back inside `TryPushError::Contended`.
+    /// # Examples
+    /// ```
+    /// let stack: Stack<String> = Stack::new(true);
+    /// match stack.try_push("hello".to_owned()) {
+    ///     Ok(()) => {},
+    ///     Err(TryPushError::Contended(val)) => stack.push(val) // Or do some other work first
+    /// }
+    /// ```
+    pub fn try_push(&self, val: T) -> Result<(), TryPushError<T>> {
+        let node_ptr = Node::new_as_pointer(val);
+        match self.attempt_push(node_ptr) {
+            Ok(()) => Ok(()),
+            Err(node_ptr) => {
+                self.stats.cas_failure();
+                let node = unsafe { Box::from_raw(node_ptr) };
+                Err(TryPushError::Contended(ManuallyDrop::into_inner(node.data).unwrap()))
+            }
+        }
+    }
+
     /// Pop a piece of data from the top of the stack, or return None if the stack
     /// is empty. Blocks until success.
     /// # Examples
@@ -123,26 +208,31 @@ impl<T: Send> Stack<T> {
     /// ```
     pub fn pop(&self) -> Option<T> {
         let mut thread_info_ptr: *mut ThreadInfo<T> = ptr::null_mut();
+        let mut failures = 0;
         loop {
-            if let Ok(val) = self.try_pop() {
+            if let Ok(val) = self.attempt_pop() {
                 if !thread_info_ptr.is_null() {
                     unsafe { Box::from_raw(thread_info_ptr) };
                 }
                 return val
             }
+            self.stats.cas_failure();
             if thread_info_ptr.is_null() {
                 thread_info_ptr = Box::into_raw(Box::new(ThreadInfo::new(None, OpType::Pop)));
             }
             if self.elimination_on {
                 if let Ok(val) = self.elimination.try_eliminate(thread_info_ptr, OpType::Pop) {
+                    self.stats.elimination();
                     unsafe { Box::from_raw(thread_info_ptr) };
                     return val
                 }
             }
+            failures += 1;
+            self.backoff(failures);
         }
     }
 
-    fn try_pop(&self) -> Result<Option<T>, ()> {
+    fn attempt_pop(&self) -> Result<Option<T>, ()> {
         let old_head = self.head.load(Acquire);
         if old_head.is_null() {
             return Ok(None)
@@ -153,31 +243,166 @@ impl<T: Send> Stack<T> {
                 return Err(())
             }
             let new_head = (*old_head).next.load(Acquire);
-            match self.head.compare_exchange_weak(old_head, new_head, Release, Relaxed) {
+            match self.head.compare_exchange(old_head, new_head, Release, Relaxed) {
                 Err(_) => Err(()),
-                Ok(old_head) => {
-                    let old_head_val = ptr::replace(old_head, Node::default());
-                    let data = old_head_val.data;
-                    self.manager.retire(old_head, 0);
-                    Ok(data)
+                Ok(old_head) => Ok(Node::take_data(old_head, &self.manager))
+            }
+        }
+    }
+
+    /// Make a single attempt to pop a piece of data from the top of the stack, without retrying
+    /// or using the elimination layer. Fails with `TryPopError::Empty` if the stack is empty, and
+    /// `TryPopError::Contended` if another thread changed the stack during the attempt.
+    /// # Examples
+    /// ```
+    /// let stack: Stack<String> = Stack::new(true);
+    /// assert_eq!(stack.try_pop(), Err(TryPopError::Empty));
+    /// stack.push("hello".to_owned());
+    /// assert_eq!(stack.try_pop(), Ok("hello".to_owned()));
+    /// ```
+    pub fn try_pop(&self) -> Result<T, TryPopError> {
+        match self.attempt_pop() {
+            Ok(Some(val)) => Ok(val),
+            Ok(None) => Err(TryPopError::Empty),
+            Err(()) => {
+                self.stats.cas_failure();
+                Err(TryPopError::Contended)
+            }
+        }
+    }
+
+    /// Look at the data on top of the stack without removing it, or return None if the stack is empty.
+    /// The node holding the data is protected by a hazard pointer inside the PeekGuard, so it is not
+    /// freed while the guard is alive, even if the data is popped in the meantime.
+    /// # Examples
+    /// ```
+    /// let stack: Stack<u8> = Stack::new(true);
+    /// stack.push(8);
+    /// let is_eight = stack.peek().map_or(false, |guard| *guard.data() == 8);
+    /// if is_eight {
+    ///     stack.pop();
+    /// }
+    /// ```
+    pub fn peek(&self) -> Option<PeekGuard<'_, T>> where T: Sync {
+        loop {
+            let head = self.head.load(Acquire);
+            if head.is_null() {
+                return None
+            }
+            let handle = self.manager.protect_dynamic(head);
+            // Nodes are only retired once popped, so if the head is unchanged once it is protected,
+            // the node stays allocated until the guard is dropped
+            if ptr::eq(head, self.head.load(Acquire)) {
+                if let Some(ref data) = *unsafe { &*head }.data {
+                    return Some(PeekGuard::new(data, handle))
+                }
+            }
+        }
+    }
+
+    /// Push every item of an iterator onto the stack with a single successful CAS. The items are linked
+    /// into a chain beforehand, so they appear on the stack all at once, with the last item on top.
+    /// Like `push`, this blocks until success, but it does not use the elimination layer.
+    /// # Examples
+    /// ```
+    /// let stack: Stack<u8> = Stack::new(true);
+    /// stack.push_all(vec![1, 2, 3]);
+    /// assert_eq!(stack.pop(), Some(3));
+    /// ```
+    pub fn push_all<I: IntoIterator<Item = T>>(&self, iter: I) {
+        let mut top: *mut Node<T> = ptr::null_mut();
+        let mut bottom: *mut Node<T> = ptr::null_mut();
+        for val in iter {
+            let node_ptr = Node::new_as_pointer(val);
+            if bottom.is_null() {
+                bottom = node_ptr;
+            }
+            unsafe { (*node_ptr).next.store(top, Relaxed) };
+            top = node_ptr;
+        }
+        if top.is_null() {
+            return
+        }
+
+        let mut old_head = self.head.load(Acquire);
+        loop {
+            unsafe { (*bottom).next.store(old_head, Relaxed) };
+            match self.head.compare_exchange(old_head, top, Release, Acquire) {
+                Ok(_) => return,
+                Err(current) => {
+                    self.stats.cas_failure();
+                    old_head = current;
                 }
             }
         }
     }
+
+    /// Take every item off the stack at once, by swapping the head for an empty stack. The detached
+    /// items are returned as an iterator, starting from the top of the stack. Items left unconsumed
+    /// when the iterator is dropped are dropped with it.
+    /// # Examples
+    /// ```
+    /// let stack: Stack<u8> = Stack::new(true);
+    /// stack.push(1);
+    /// stack.push(2);
+    /// assert_eq!(stack.pop_all().collect::<Vec<u8>>(), vec![2, 1]);
+    /// assert_eq!(stack.pop(), None);
+    /// ```
+    pub fn pop_all(&self) -> PopAll<'_, T> {
+        PopAll {
+            current: self.head.swap(ptr::null_mut(), Acquire),
+            manager: &self.manager
+        }
+    }
+
+    /// Get a snapshot of the contention and reclamation counters of the stack, including
+    /// those of its elimination layer.
+    /// # Examples
+    /// ```
+    /// let stack: Stack<u8> = Stack::new(true);
+    /// stack.push(8);
+    /// stack.pop();
+    /// assert_eq!(stack.stats().retired, 1);
+    /// ```
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> Stats {
+        self.stats.snapshot() + self.manager.stats() + self.elimination.manager.stats()
+    }
+}
+
+/// An iterator over the items taken off a stack by `Stack::pop_all`.
+///
+/// Other threads may still be reading or peeking at the detached nodes when they were taken, so each
+/// node is retired to the stack's HPBRManager once its item has been moved out, rather than freed.
+pub struct PopAll<'a, T: Send + 'a> {
+    current: *mut Node<T>,
+    manager: &'a HPBRManager<Node<T>>
+}
+
+impl<'a, T: Send> Iterator for PopAll<'a, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        if self.current.is_null() {
+            return None
+        }
+        let node_ptr = self.current;
+        unsafe {
+            self.current = (*node_ptr).next.load(Relaxed);
+            Node::take_data(node_ptr, self.manager)
+        }
+    }
 }
 
-fn get_id() -> usize {
-    unsafe { mem::transmute::<ThreadId, u64>(thread::current().id()) as usize } 
+impl<'a, T: Send> Drop for PopAll<'a, T> {
+    fn drop(&mut self) {
+        for _ in self {}
+    }
 }
 
 impl<T: Send> Default for Stack<T> {
     fn default() -> Self {
-        Self {
-            head: AtomicPtr::default(),
-            elimination: EliminationLayer::new(5),
-            manager: HPBRManager::new(200, 1),
-            elimination_on: false
-        }
+        Stack::new(false)
     }
 }
 
@@ -189,7 +414,8 @@ impl<T: Send> Drop for Stack<T> {
         while !ptr::eq(current, ptr::null()) {
             unsafe {
                 let next = (*current).next.load(Relaxed);
-                Box::from_raw(current);
+                let node = Box::from_raw(current);
+                drop(ManuallyDrop::into_inner(node.data));
                 current = next;
             }
         }
@@ -198,37 +424,42 @@ impl<T: Send> Drop for Stack<T> {
 
 impl<T: Send> Node<T> {
     fn new_as_pointer(val: T) -> *mut Self {
-        Box::into_raw(Box::new(Node {
-            data: Some(val),
-            next: AtomicPtr::default()
-        }))
+        Box::into_raw(Box::new(Node::new(val)))
     }
 
     fn new(val: T) -> Self {
         Node {
-            data: Some(val),
+            data: ManuallyDrop::new(Some(val)),
             next: AtomicPtr::default()
         }
     }
-}
 
-impl<T: Send> Default for Node<T> {
-    fn default() -> Self {
-        Node {
-            data: None,
-            next: AtomicPtr::default()
-        }
+    /// Read the data out of a node which has been taken off the stack, and retire the node, as other
+    /// threads may still be reading or peeking at it.
+    unsafe fn take_data(node_ptr: *mut Self, manager: &HPBRManager<Self>) -> Option<T> {
+        let data = ptr::read(&*(*node_ptr).data);
+        manager.retire(node_ptr, 0);
+        data
     }
-} 
+}
 
+/// The elimination layer pairs up pushes and pops which failed on the stack.
+///
+/// A thread publishes its operation in its slot, picks a random position in the active range of the
+/// collision array, and swaps its index in. If it found a complementary operation there, the two are
+/// exchanged directly. Otherwise it spins briefly, in case another thread picks it. The active range and
+/// the spin count adapt to the outcomes: waiting with nobody arriving shrinks the range and lengthens the
+/// wait, so threads are more likely to meet, while finding the position taken by a mismatched or racing
+/// thread grows the range and shortens the wait, spreading out a crowd. The adaptive values are updated
+/// without synchronisation, as they are only hints.
 struct EliminationLayer<T: Send> {
-    location: HashMap<usize, AtomicPtr<ThreadInfo<T>>>,
+    location: Vec<AtomicPtr<ThreadInfo<T>>>,
     collision: Vec<AtomicUsize>,
-    rng: UnsafeCell<SmallRng>,
+    range: AtomicUsize,
+    spins: AtomicUsize,
     manager: HPBRManager<ThreadInfo<T>>
 }
 
-unsafe impl<T: Send> Sync for EliminationLayer<T> {}
 
 struct ThreadInfo<T: Send> {
     id: usize,
@@ -248,34 +479,30 @@ enum OpType {
 // Segfault is on the nodes, not the thread info. How to manage this?
 impl<T: Send> EliminationLayer<T> {
     fn new(collision_size: usize) -> Self {
-        let mut collision = Vec::with_capacity(collision_size);
-        for _ in 0..collision_size {
+        let mut collision = Vec::with_capacity(MAX_COLLISION_RANGE);
+        for _ in 0..MAX_COLLISION_RANGE {
             collision.push(AtomicUsize::new(usize::max_value()))
         }
+        let mut location = Vec::with_capacity(ELIMINATION_SLOTS);
+        for _ in 0..ELIMINATION_SLOTS {
+            location.push(AtomicPtr::default())
+        }
         Self {
-            location: HashMap::new(),
+            location,
             collision,
-            rng: UnsafeCell::new(SmallRng::new()),
+            range: AtomicUsize::new(cmp::max(1, cmp::min(collision_size, MAX_COLLISION_RANGE))),
+            spins: AtomicUsize::new(MIN_ELIMINATION_SPINS),
             manager: HPBRManager::new(100, 2)
         }
     }
 
     fn try_eliminate(&self, me_info_ptr: *mut ThreadInfo<T>, op: OpType) -> Result<Option<T>, ()> {
-        let me_id = get_id();
-
-        match self.location.get(&me_id) {
-            None => {
-                match self.location.insert(me_id, AtomicPtr::new(me_info_ptr)) {
-                    Ok(()) => {},
-                    Err(_) => {
-                        return Err(())
-                    }
-                }
-            },
-            Some(data_guard) => {
-                data_guard.data().store(me_info_ptr, Release);
-            }
-        }
+        let me_id = unsafe { (*me_info_ptr).id };
+        let me_atomic = match self.location.get(me_id) {
+            Some(atomic) => atomic,
+            None => return Err(())
+        };
+        me_atomic.store(me_info_ptr, Release);
 
         let position = self.get_position();
 
@@ -284,43 +511,73 @@ impl<T: Send> EliminationLayer<T> {
             them_id = current;
         }
 
-        if them_id != usize::max_value() {
-            match self.location.get(&them_id) {
-                None => {},
-                Some(data_guard) => {
-                    let them_info_ptr = data_guard.data().load(Acquire);
-                    self.manager.protect(them_info_ptr, 0);
-
-                    if is_complimentary(them_id, them_info_ptr, data_guard.data(), op) {
-                        let me_atomic = self.location.get(&me_id).unwrap().data();
-                        match me_atomic.compare_exchange(me_info_ptr, ptr::null_mut(), Release, Relaxed) {
-                            Ok(_) => {
-                                return self.try_collision(me_info_ptr, them_info_ptr, data_guard.data(), me_atomic)
-                            },
-                            Err(current) => {
-                                let ret_val = self.finish_collision(current, op);
-                                self.manager.unprotect(0);
-                                return ret_val
-                            }
-                        }                        
+        // Finding our own index means we were the last thread here, which is the same as finding nobody
+        if them_id != usize::max_value() && them_id != me_id {
+            let them_atomic = &self.location[them_id];
+            let them_info_ptr = them_atomic.load(Acquire);
+            self.manager.protect(them_info_ptr, 0);
+
+            if is_complimentary(them_id, them_info_ptr, them_atomic, op) {
+                match me_atomic.compare_exchange(me_info_ptr, ptr::null_mut(), Release, Relaxed) {
+                    Ok(_) => {
+                        let ret_val = self.try_collision(me_info_ptr, them_info_ptr, them_atomic, me_atomic);
+                        if ret_val.is_err() {
+                            self.adapt_to_crowding();
+                        }
+                        return ret_val
+                    },
+                    Err(current) => {
+                        let ret_val = self.finish_collision(current, me_atomic, op);
+                        self.manager.unprotect(0);
+                        return ret_val
                     }
-                    self.manager.unprotect(0); 
                 }
             }
+            self.manager.unprotect(0);
+            if !them_info_ptr.is_null() {
+                self.adapt_to_crowding();
+            }
+        }
+
+        // Wait for a partner to pick us, leaving as soon as one has
+        let spins = self.spins.load(Relaxed);
+        for _ in 0..spins {
+            if !ptr::eq(me_atomic.load(Relaxed), me_info_ptr) {
+                break;
+            }
+            hint::spin_loop();
         }
-        thread::sleep(Duration::new(0, 100));
-        let me_atomic = self.location.get(&me_id).unwrap().data();
         match me_atomic.compare_exchange(me_info_ptr, ptr::null_mut(), Release, Relaxed) {
             Ok(_) => {
+                self.adapt_to_timeout();
                 return Err(())
             },
-            Err(current) => return self.finish_collision(current, op)
+            Err(current) => return self.finish_collision(current, me_atomic, op)
         }
     }
 
     fn get_position(&self) -> usize {
-        let rand = unsafe { &mut *self.rng.get() };
-        rand.gen_range(0, self.collision.len())
+        random_below(self.range.load(Relaxed))
+    }
+
+    /// Nobody arrived while we waited, so there are too few threads for the range: bring them closer together.
+    fn adapt_to_timeout(&self) {
+        let range = self.range.load(Relaxed);
+        if range > 1 {
+            self.range.store(range - 1, Relaxed);
+        }
+        let spins = self.spins.load(Relaxed);
+        self.spins.store(cmp::min(spins * 2, MAX_ELIMINATION_SPINS), Relaxed);
+    }
+
+    /// We met a thread we could not pair with, so there are too many threads for the range: spread them out.
+    fn adapt_to_crowding(&self) {
+        let range = self.range.load(Relaxed);
+        if range < MAX_COLLISION_RANGE {
+            self.range.store(range + 1, Relaxed);
+        }
+        let spins = self.spins.load(Relaxed);
+        self.spins.store(cmp::max(spins / 2, MIN_ELIMINATION_SPINS), Relaxed);
     }
 
     fn try_collision(&self, me_ptr: *mut ThreadInfo<T>, them_ptr: *mut ThreadInfo<T>, 
@@ -346,12 +603,11 @@ impl<T: Send> EliminationLayer<T> {
                 match them_atomic.compare_exchange(them_ptr, ptr::null_mut(), Release, Relaxed) {
                     Ok(_) => {
                         let mut owned_info = unsafe { ptr::read(them_ptr) };
-                        let mut node_ptr = mem::replace(&mut owned_info.node, None).unwrap();
-                        let node = unsafe { ptr::replace(node_ptr, Node::default()) };
-                        unsafe { Box::from_raw(node_ptr) };
+                        let node_ptr = mem::replace(&mut owned_info.node, None).unwrap();
+                        let node = unsafe { Box::from_raw(node_ptr) };
                         self.manager.retire(them_ptr, 0);
                         me_atomic.store(ptr::null_mut(), Release);
-                        return Ok(node.data)
+                        return Ok(ManuallyDrop::into_inner(node.data))
                     },
                     Err(_) => {
                         self.manager.unprotect(0);
@@ -362,17 +618,18 @@ impl<T: Send> EliminationLayer<T> {
         }
     }
 
-    fn finish_collision(&self, new_info_ptr: *mut ThreadInfo<T>, me_op: OpType) -> Result<Option<T>, ()> {
+    fn finish_collision(&self, new_info_ptr: *mut ThreadInfo<T>, me_atomic: &AtomicPtr<ThreadInfo<T>>, me_op: OpType)
+            -> Result<Option<T>, ()>
+    {
         match me_op {
             OpType::Push => { return Ok(None) },
             OpType::Pop => {
                 let mut owned_info = unsafe { ptr::read(new_info_ptr) };
-                let mut node_ptr = mem::replace(&mut owned_info.node, None).unwrap();
-                let node = unsafe { ptr::replace(node_ptr, Node::default()) };
-                unsafe { Box::from_raw(node_ptr) };
-                self.location.get(&get_id()).unwrap().data().store(ptr::null_mut(), Release);
+                let node_ptr = mem::replace(&mut owned_info.node, None).unwrap();
+                let node = unsafe { Box::from_raw(node_ptr) };
+                me_atomic.store(ptr::null_mut(), Release);
                 self.manager.retire(new_info_ptr, 0);
-                return Ok(node.data)
+                return Ok(ManuallyDrop::into_inner(node.data))
             }
         }
     } 
@@ -400,8 +657,8 @@ fn is_complimentary<T: Send>(them_id: usize, them_ptr: *mut ThreadInfo<T>,
 
 impl<T: Send> Drop for EliminationLayer<T> {
     fn drop(&mut self) {
-        for guard in self.location.iter() {
-            let ptr = guard.data().load(Relaxed);
+        for atomic in self.location.iter() {
+            let ptr = atomic.load(Relaxed);
             if !ptr.is_null() {
                 unsafe { Box::from_raw(ptr) };
             }
@@ -412,7 +669,7 @@ impl<T: Send> Drop for EliminationLayer<T> {
 impl<T: Send> ThreadInfo<T> {
     fn new(node: Option<*mut Node<T>>, op: OpType) -> Self {
         Self {
-            id: get_id(),
+            id: current_thread_index(),
             op,
             node
         }
@@ -426,11 +683,11 @@ mod tests {
 
     use rand::{thread_rng, Rng};
 
-    use super::Stack;
-    use super::get_id;
+    use super::{Stack, TryPushError, TryPopError, PopAll, Node, ThreadInfo, OpType, MIN_ELIMINATION_SPINS};
     use super::super::super::testing::linearizability_tester::{LinearizabilityTester, LinearizabilityResult, ThreadLog};
+    use backoff::Backoff;
 
-    use std::sync::atomic::Ordering;
+    use std::sync::atomic::{AtomicUsize, Ordering};
     use std::{thread, thread::ThreadId};
     use std::sync::Arc;
     use std::cell::RefCell;
@@ -459,9 +716,9 @@ mod tests {
         stack.push(1);
         //println!("{:?}", stack);
         unsafe {
-            let val = (*stack.head.load(Ordering::Relaxed)).data;
+            let val = *(*stack.head.load(Ordering::Relaxed)).data;
             assert_eq!(val, Some(1));
-            let next_val = (*(*stack.head.load(Ordering::Relaxed)).next.load(Ordering::Relaxed)).data;
+            let next_val = *(*(*stack.head.load(Ordering::Relaxed)).next.load(Ordering::Relaxed)).data;
             assert_eq!(next_val, Some(3));
         }
     }
@@ -485,13 +742,185 @@ mod tests {
     }
 
     #[test]
-     
-    fn test_thread_id() {
-        let threadid = thread::current().id();
-        let num_id = get_id();
+    fn test_try_push_pop() {
+        let stack: Stack<u8> = Stack::new(true);
+        assert_eq!(stack.try_pop(), Err(TryPopError::Empty));
+        assert_eq!(stack.try_push(1), Ok(()));
+        assert_eq!(stack.try_push(2), Ok(()));
+        assert_eq!(stack.try_pop(), Ok(2));
+        assert_eq!(stack.try_pop(), Ok(1));
+        assert_eq!(stack.try_pop(), Err(TryPopError::Empty));
+
+        let stack: Arc<Stack<u32>> = Arc::new(Stack::new(false));
+        let mut waitvec: Vec<thread::JoinHandle<usize>> = Vec::new();
+        for _ in 0..8 {
+            let stack_copy = stack.clone();
+            waitvec.push(thread::spawn(move || {
+                let mut pushed = 0;
+                for i in 0..1000 {
+                    let mut val = i;
+                    loop {
+                        match stack_copy.try_push(val) {
+                            Ok(()) => break,
+                            Err(TryPushError::Contended(returned)) => val = returned
+                        }
+                    }
+                    pushed += 1;
+                }
+                pushed
+            }));
+        }
+        let pushed: usize = waitvec.into_iter().map(|handle| handle.join().unwrap()).sum();
+        let mut popped = 0;
+        while stack.try_pop() != Err(TryPopError::Empty) {
+            popped += 1;
+        }
+        assert_eq!(pushed, popped);
+    }
 
-        assert_eq!(unsafe { mem::transmute::<usize, ThreadId>(num_id) }, threadid);
-        assert_eq!(get_id(), unsafe { mem::transmute::<ThreadId, usize>(threadid) });
+    #[test]
+    fn test_peek() {
+        let stack: Stack<u8> = Stack::new(true);
+        assert!(stack.peek().is_none());
+        stack.push(1);
+        stack.push(2);
+        assert_eq!(*stack.peek().unwrap().data(), 2);
+        assert_eq!(stack.pop(), Some(2));
+        assert_eq!(stack.peek().unwrap().cloned(), 1);
+
+        // A guard stays valid after its element is popped, even by the same thread
+        let guard = stack.peek().unwrap();
+        assert_eq!(stack.pop(), Some(1));
+        assert_eq!(*guard.data(), 1);
+        drop(guard);
+
+        // Peekers and poppers race over the same elements
+        let stack: Arc<Stack<[usize; 4]>> = Arc::new(Stack::new(false));
+        let mut waitvec: Vec<thread::JoinHandle<()>> = Vec::new();
+        for _ in 0..4 {
+            let stack_copy = stack.clone();
+            waitvec.push(thread::spawn(move || {
+                for i in 0..5000 {
+                    stack_copy.push([i; 4]);
+                    stack_copy.pop();
+                }
+            }));
+            let stack_copy = stack.clone();
+            waitvec.push(thread::spawn(move || {
+                for _ in 0..5000 {
+                    if let Some(guard) = stack_copy.peek() {
+                        let data = guard.data();
+                        assert!(data[0] < 5000 && data.iter().all(|&x| x == data[0]));
+                    }
+                }
+            }));
+        }
+        for handle in waitvec {
+            handle.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_push_all_pop_all() {
+        let stack: Stack<Foo> = Stack::new(true);
+        stack.push_all(Vec::new());
+        assert_eq!(stack.pop(), None);
+
+        stack.push(Foo {data: 0});
+        stack.push_all((1..5).map(|data| Foo {data}));
+        assert_eq!(stack.pop(), Some(Foo {data: 4}));
+
+        let mut all = stack.pop_all();
+        assert_eq!(stack.pop(), None);
+        assert_eq!(all.next(), Some(Foo {data: 3}));
+        assert_eq!(all.next(), Some(Foo {data: 2}));
+        // The rest of the items are dropped with the iterator
+        drop(all);
+        assert_eq!(stack.pop_all().next(), None);
+
+        let stack: Arc<Stack<usize>> = Arc::new(Stack::new(true));
+        let mut waitvec: Vec<thread::JoinHandle<usize>> = Vec::new();
+        for _ in 0..8 {
+            let stack_copy = stack.clone();
+            waitvec.push(thread::spawn(move || {
+                for _ in 0..100 {
+                    stack_copy.push_all(0..10);
+                }
+                0
+            }));
+            let stack_copy = stack.clone();
+            waitvec.push(thread::spawn(move || {
+                let mut popped = 0;
+                for _ in 0..100 {
+                    popped += stack_copy.pop_all().count();
+                    if stack_copy.pop().is_some() {
+                        popped += 1;
+                    }
+                }
+                popped
+            }));
+        }
+        let popped: usize = waitvec.into_iter().map(|handle| handle.join().unwrap()).sum();
+        assert_eq!(popped + stack.pop_all().count(), 8000);
+    }
+
+    #[test]
+    fn test_with_backoff() {
+        #[derive(Debug)]
+        struct CountingBackoff(Arc<AtomicUsize>);
+
+        impl Backoff for CountingBackoff {
+            fn backoff(&self, failures: u32) {
+                assert!(failures >= 1);
+                self.0.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let stack: Arc<Stack<usize>> = Arc::new(Stack::new_with_backoff(false, CountingBackoff(calls.clone())));
+        let mut waitvec: Vec<thread::JoinHandle<()>> = Vec::new();
+        for _ in 0..8 {
+            let stack_copy = stack.clone();
+            let calls_copy = calls.clone();
+            waitvec.push(thread::spawn(move || {
+                // Keep contending until some attempt has failed and backed off, which can take a
+                // while on a single core
+                let mut i = 0;
+                while i < 10000 || (calls_copy.load(Ordering::Relaxed) == 0 && i < 10000000) {
+                    stack_copy.push(i);
+                    assert!(stack_copy.pop().is_some());
+                    i += 1;
+                }
+            }));
+        }
+        for handle in waitvec {
+            handle.join().unwrap();
+        }
+        assert_eq!(stack.pop(), None);
+        assert!(calls.load(Ordering::Relaxed) > 0);
+    }
+
+    #[test]
+    fn test_adaptive_elimination() {
+        let stack: Stack<u8> = Stack::new_with_collision_size(true, 8);
+        let node_ptr = Node::new_as_pointer(1);
+        let info_ptr = Box::into_raw(Box::new(ThreadInfo::new(Some(node_ptr), OpType::Push)));
+
+        // Nobody else is eliminating, so every attempt waits in vain and the layer contracts
+        for _ in 0..3 {
+            assert!(stack.elimination.try_eliminate(info_ptr, OpType::Push).is_err());
+        }
+        assert_eq!(stack.elimination.range.load(Ordering::Relaxed), 5);
+        assert_eq!(stack.elimination.spins.load(Ordering::Relaxed), MIN_ELIMINATION_SPINS * 8);
+
+        stack.elimination.adapt_to_crowding();
+        assert_eq!(stack.elimination.range.load(Ordering::Relaxed), 6);
+        assert_eq!(stack.elimination.spins.load(Ordering::Relaxed), MIN_ELIMINATION_SPINS * 4);
+
+        unsafe {
+            drop(Box::from_raw(info_ptr));
+            drop(Box::from_raw(node_ptr));
+        }
     }
 
     #[test]
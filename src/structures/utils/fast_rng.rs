@@ -0,0 +1,75 @@
+use std::cell::Cell;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::Relaxed;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The increment of the SplitMix64 generator, used to space out the seeds of successive threads.
+const GOLDEN_GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+
+static SEEDS: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    static STATE: Cell<u64> = Cell::new(seed());
+}
+
+/// Seed a new thread's generator. Every thread takes the next value of a shared counter, mixed with
+/// the time so that runs differ, and scrambles it so that consecutive threads get unrelated streams.
+fn seed() -> u64 {
+    let time = SystemTime::now().duration_since(UNIX_EPOCH).map(|duration| duration.as_nanos() as u64).unwrap_or(0);
+    let mut z = SEEDS.fetch_add(GOLDEN_GAMMA, Relaxed).wrapping_add(GOLDEN_GAMMA) ^ time;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^= z >> 31;
+    // Xorshift gets stuck at zero
+    if z == 0 { GOLDEN_GAMMA } else { z }
+}
+
+/// Get a random number from the current thread's generator.
+///
+/// Each thread has its own xorshift64* generator, so this never touches memory shared with other
+/// threads. It is fast and well enough distributed to pick slots and backoff times, but is not
+/// suitable for anything needing unpredictability.
+pub(crate) fn random_usize() -> usize {
+    STATE.with(|state| {
+        let mut x = state.get();
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        state.set(x);
+        (x.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 32) as usize
+    })
+}
+
+/// Get a random number in `0..bound` from the current thread's generator. `bound` must not be zero.
+pub(crate) fn random_below(bound: usize) -> usize {
+    random_usize() % bound
+}
+
+mod tests {
+    #![allow(unused_imports)]
+    use super::{random_usize, random_below};
+    use std::collections::HashSet;
+    use std::thread;
+
+    #[test]
+    fn test_random_below() {
+        let mut seen = [false; 8];
+        for _ in 0..1000 {
+            let value = random_below(8);
+            assert!(value < 8);
+            seen[value] = true;
+        }
+        assert!(seen.iter().all(|&seen| seen));
+    }
+
+    #[test]
+    fn test_threads_get_different_streams() {
+        let firsts: HashSet<Vec<usize>> = (0..8)
+            .map(|_| thread::spawn(|| (0..4).map(|_| random_usize()).collect()))
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|handle: thread::JoinHandle<Vec<usize>>| handle.join().unwrap())
+            .collect();
+        assert_eq!(firsts.len(), 8);
+    }
+}
@@ -0,0 +1,186 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize};
+use std::sync::atomic::Ordering::{Acquire, Release, Relaxed};
+use std::hint;
+use std::cell::UnsafeCell;
+use std::thread;
+use super::current_thread_index;
+
+/// The number of threads which can publish requests at once. Threads with a higher index apply
+/// their own requests under the combiner lock.
+const COMBINING_SLOTS: usize = 128;
+
+const EMPTY: usize = 0;
+const PENDING: usize = 1;
+const DONE: usize = 2;
+const PANICKED: usize = 3;
+
+/// The core of a flat-combining structure, as described in [Flat Combining and the
+/// Synchronization-Parallelism Tradeoff](https://dl.acm.org/citation.cfm?id=1810540).
+///
+/// A sequential structure is protected by a single lock. Instead of contending for the lock,
+/// a thread publishes its request in its own record, indexed by its thread index, and then either
+/// waits for the response or takes the lock and becomes the combiner, applying every pending request
+/// in one pass. The sequential structure stays in the combiner's cache, and the lock is taken once
+/// per batch rather than once per operation.
+///
+/// This is blocking: if the combiner is descheduled, every waiting thread waits with it.
+pub(crate) struct FlatCombiner<S, Req, Resp> {
+    lock: AtomicBool,
+    state: UnsafeCell<S>,
+    records: Vec<Record<Req, Resp>>,
+    high_water: AtomicUsize
+}
+
+unsafe impl<S: Send, Req: Send, Resp: Send> Sync for FlatCombiner<S, Req, Resp> {}
+unsafe impl<S: Send, Req: Send, Resp: Send> Send for FlatCombiner<S, Req, Resp> {}
+
+struct Record<Req, Resp> {
+    status: AtomicUsize,
+    request: UnsafeCell<Option<Req>>,
+    response: UnsafeCell<Option<Resp>>
+}
+
+impl<S, Req, Resp> FlatCombiner<S, Req, Resp> {
+    pub fn new(state: S) -> Self {
+        let mut records = Vec::with_capacity(COMBINING_SLOTS);
+        for _ in 0..COMBINING_SLOTS {
+            records.push(Record {
+                status: AtomicUsize::new(EMPTY),
+                request: UnsafeCell::new(None),
+                response: UnsafeCell::new(None)
+            });
+        }
+        FlatCombiner {
+            lock: AtomicBool::new(false),
+            state: UnsafeCell::new(state),
+            records,
+            high_water: AtomicUsize::new(0)
+        }
+    }
+
+    /// Have `apply` run on the sequential structure with the given request, by whichever thread is
+    /// combining, and return its response.
+    pub fn execute<F>(&self, request: Req, apply: F) -> Resp
+    where F: Fn(&mut S, Req) -> Resp
+    {
+        let index = current_thread_index();
+        let record = match self.records.get(index) {
+            Some(record) => record,
+            None => {
+                let _guard = self.lock();
+                return apply(unsafe { &mut *self.state.get() }, request)
+            }
+        };
+
+        if self.high_water.load(Relaxed) < index + 1 {
+            self.high_water.fetch_max(index + 1, Relaxed);
+        }
+        // Only this thread writes the request, and only while no combiner reads the record
+        unsafe { *record.request.get() = Some(request) };
+        record.status.store(PENDING, Release);
+
+        loop {
+            match record.status.load(Acquire) {
+                DONE => {
+                    let response = unsafe { (*record.response.get()).take().unwrap() };
+                    record.status.store(EMPTY, Relaxed);
+                    return response
+                },
+                PANICKED => {
+                    record.status.store(EMPTY, Relaxed);
+                    panic!("the request panicked while another thread was combining")
+                },
+                _ => {}
+            }
+            if let Some(_guard) = self.try_lock() {
+                self.combine(&apply);
+            } else {
+                hint::spin_loop();
+            }
+        }
+    }
+
+    /// Apply every pending request. Must be called with the lock held.
+    fn combine<F>(&self, apply: &F)
+    where F: Fn(&mut S, Req) -> Resp
+    {
+        let state = unsafe { &mut *self.state.get() };
+        let high_water = self.high_water.load(Relaxed);
+        for record in &self.records[..high_water] {
+            if record.status.load(Acquire) == PENDING {
+                let _guard = PanicGuard { status: &record.status };
+                unsafe {
+                    let request = (*record.request.get()).take().unwrap();
+                    *record.response.get() = Some(apply(state, request));
+                }
+                record.status.store(DONE, Release);
+            }
+        }
+    }
+
+    fn try_lock(&self) -> Option<LockGuard<'_>> {
+        if !self.lock.load(Relaxed) && self.lock.compare_exchange(false, true, Acquire, Relaxed).is_ok() {
+            Some(LockGuard { lock: &self.lock })
+        } else {
+            None
+        }
+    }
+
+    fn lock(&self) -> LockGuard<'_> {
+        loop {
+            if let Some(guard) = self.try_lock() {
+                return guard
+            }
+            hint::spin_loop();
+        }
+    }
+}
+
+/// Holds the combiner lock, and releases it when dropped, so a panic in `apply` does not leave
+/// every other thread waiting for the lock forever.
+struct LockGuard<'a> {
+    lock: &'a AtomicBool
+}
+
+/// Marks a record whose request is being applied as panicked if `apply` unwinds, so that its thread
+/// stops waiting for a response and no combiner reads the record again.
+struct PanicGuard<'a> {
+    status: &'a AtomicUsize
+}
+
+impl<'a> Drop for PanicGuard<'a> {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            self.status.store(PANICKED, Release);
+        }
+    }
+}
+
+impl<'a> Drop for LockGuard<'a> {
+    fn drop(&mut self) {
+        self.lock.store(false, Release);
+    }
+}
+
+mod tests {
+    #![allow(unused_imports)]
+    use super::FlatCombiner;
+    use std::panic::{self, AssertUnwindSafe};
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn test_panic_releases_lock() {
+        let combiner: Arc<FlatCombiner<usize, usize, usize>> = Arc::new(FlatCombiner::new(0));
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            combiner.execute(0, |_, _| panic!("apply failed"))
+        }));
+        assert!(result.is_err());
+
+        // Another thread can still take the lock and combine
+        let combiner_copy = combiner.clone();
+        let handle = thread::spawn(move || combiner_copy.execute(2, |total, val| { *total += val; *total }));
+        assert_eq!(handle.join().unwrap(), 2);
+        assert_eq!(combiner.execute(3, |total, val| { *total += val; *total }), 5);
+    }
+}
@@ -1 +1,13 @@
-pub mod atomic_markable;
\ No newline at end of file
+pub use self::tagged_ptr::TaggedAtomicPtr;
+pub use self::versioned_ptr::{AtomicVersionedPtr, VersionedPtr};
+pub use self::thread_index::current_thread_index;
+pub use self::peek_guard::PeekGuard;
+pub(crate) use self::flat_combiner::FlatCombiner;
+pub(crate) use self::fast_rng::{random_usize, random_below};
+
+mod tagged_ptr;
+mod versioned_ptr;
+mod thread_index;
+mod peek_guard;
+mod flat_combiner;
+mod fast_rng;
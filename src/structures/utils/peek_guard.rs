@@ -0,0 +1,61 @@
+use memory::HPHandle;
+use std::fmt::Debug;
+use std::fmt;
+
+/// A guard over the element at the front of a structure, returned by `peek`.
+///
+/// The guard holds a hazard pointer on the node containing the element, so the node is not freed while
+/// the guard is alive, even if the element is removed from the structure in the meantime.
+pub struct PeekGuard<'a, T: Send + 'a> {
+    data: &'a T,
+    _handle: Box<dyn NodeHandle + 'a>
+}
+
+/// A hazard pointer protecting the node which holds a peeked element, whatever the type of the node.
+pub(crate) trait NodeHandle {}
+
+impl<'a, N: Send> NodeHandle for HPHandle<'a, N> {}
+
+impl<'a, T: Send + 'a> PeekGuard<'a, T> {
+    /// Create a guard over data inside the node protected by the given handle.
+    pub(crate) fn new<N: Send + 'a>(data: &'a T, handle: HPHandle<'a, N>) -> PeekGuard<'a, T> {
+        PeekGuard {
+            data,
+            _handle: Box::new(handle)
+        }
+    }
+
+    /// Access the data inside the guard.
+    /// # Example
+    /// ```
+    /// let guard = stack.peek().unwrap();
+    /// println!("{}", guard.data()); // Prints the element on top of the stack
+    /// ```
+    pub fn data(&self) -> &T {
+        self.data
+    }
+}
+
+impl<'a, T: Send + Clone + 'a> PeekGuard<'a, T> {
+    /// Consume the guard to obtain a clone of the element.
+    /// # Example
+    /// ```
+    /// let data = stack.peek().unwrap().cloned();
+    /// println!("{}", &data);
+    /// ```
+    pub fn cloned(self) -> T {
+        self.data.clone()
+    }
+}
+
+impl<'a, T: Debug + Send + 'a> Debug for PeekGuard<'a, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PeekGuard({:?})", self.data)
+    }
+}
+
+impl<'a, T: Send + PartialEq + 'a> PartialEq for PeekGuard<'a, T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.data == other.data
+    }
+}
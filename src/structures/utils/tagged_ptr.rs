@@ -0,0 +1,214 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::marker::PhantomData;
+use std::fmt;
+use std::mem;
+use std::ptr;
+
+/// An atomic pointer which stores a tag in the low bits of the address.
+///
+/// A pointer to `T` is always a multiple of the alignment of `T`, so its lowest `log2(align_of::<T>())`
+/// bits are always zero. This type uses all of those bits to store a tag alongside the pointer, so
+/// the pointer and tag can be read and modified together in a single atomic operation. Lock-free
+/// structures commonly use this to mark nodes as logically deleted, or to flag the kind of node a
+/// pointer leads to.
+///
+/// Using a `T` with an alignment of 1 leaves no bits for the tag, and is rejected at compile time.
+/// Tags passed to any method must fit inside `TAG_MASK`.
+///
+/// Most methods take and return tagged raw pointers, as `AtomicPtr` would. The associated functions
+/// `tag`, `untagged`, `has_tag`, `with_tag` and `without_tag` can be used to inspect and build them.
+/// Unlike the structures in this crate, the pointer does not own the data it points to, and dropping it
+/// does not free anything.
+/// # Examples
+/// ```
+/// const DELETED: usize = 0x1;
+/// let ptr: TaggedAtomicPtr<u64> = TaggedAtomicPtr::new(Box::into_raw(Box::new(8)));
+/// let current = ptr.load(Ordering::Acquire);
+/// assert!(ptr.compare_and_set_tag(current, DELETED, Ordering::AcqRel, Ordering::Acquire).is_ok());
+/// assert!(TaggedAtomicPtr::has_tag(ptr.load(Ordering::Acquire), DELETED));
+/// ```
+pub struct TaggedAtomicPtr<T> {
+    data: AtomicUsize,
+    _marker: PhantomData<*mut T>
+}
+
+unsafe impl<T> Send for TaggedAtomicPtr<T> {}
+unsafe impl<T> Sync for TaggedAtomicPtr<T> {}
+
+impl<T> TaggedAtomicPtr<T> {
+    /// The bits of the address that are available for the tag.
+    pub const TAG_MASK: usize = mem::align_of::<T>() - 1;
+
+    /// The number of bits available for the tag.
+    pub const TAG_BITS: u32 = mem::align_of::<T>().trailing_zeros();
+
+    const ALIGNMENT_CHECK: () = assert!(mem::align_of::<T>() > 1, "TaggedAtomicPtr needs a type aligned to at least 2 bytes");
+
+    /// Create a new TaggedAtomicPtr holding the given tagged pointer.
+    /// # Examples
+    /// ```
+    /// let ptr: TaggedAtomicPtr<u64> = TaggedAtomicPtr::new(ptr::null_mut());
+    /// ```
+    // A generic constant is only evaluated where it is used, so the check must be named here, and a
+    // `const _` item outside the impl cannot refer to T
+    #[allow(clippy::let_unit_value)]
+    pub fn new(ptr: *mut T) -> Self {
+        let _ = Self::ALIGNMENT_CHECK;
+        TaggedAtomicPtr {
+            data: AtomicUsize::new(ptr as usize),
+            _marker: PhantomData
+        }
+    }
+
+    /// Load the tagged pointer.
+    pub fn load(&self, order: Ordering) -> *mut T {
+        self.data.load(order) as *mut T
+    }
+
+    /// Load the tagged pointer, or None if both the pointer and the tag are empty.
+    pub fn load_non_null(&self, order: Ordering) -> Option<*mut T> {
+        match self.data.load(order) {
+            0 => None,
+            data => Some(data as *mut T)
+        }
+    }
+
+    /// Load the tag on its own.
+    pub fn load_tag(&self, order: Ordering) -> usize {
+        self.data.load(order) & Self::TAG_MASK
+    }
+
+    /// Store a new tagged pointer.
+    pub fn store(&self, ptr: *mut T, order: Ordering) {
+        self.data.store(ptr as usize, order);
+    }
+
+    /// Store a new tagged pointer, returning the previous one.
+    pub fn swap(&self, ptr: *mut T, order: Ordering) -> *mut T {
+        self.data.swap(ptr as usize, order) as *mut T
+    }
+
+    /// Store `new` if the current value is `current`, comparing both pointer and tag.
+    /// Returns the previous value, inside Ok on success and Err on failure.
+    pub fn compare_exchange(&self, current: *mut T, new: *mut T, success: Ordering, failure: Ordering) -> Result<*mut T, *mut T> {
+        match self.data.compare_exchange(current as usize, new as usize, success, failure) {
+            Ok(data) => Ok(data as *mut T),
+            Err(data) => Err(data as *mut T)
+        }
+    }
+
+    /// Add the given tag bits to the current value if it is `current`.
+    /// Returns the previous value, inside Ok on success and Err on failure.
+    pub fn compare_and_set_tag(&self, current: *mut T, tag: usize, success: Ordering, failure: Ordering) -> Result<*mut T, *mut T> {
+        self.compare_exchange(current, Self::with_tag(current, tag), success, failure)
+    }
+
+    /// Add the given tag bits, leaving the pointer and other tag bits untouched.
+    /// Returns the previous value.
+    pub fn set_tag(&self, tag: usize, order: Ordering) -> *mut T {
+        debug_assert!(tag & !Self::TAG_MASK == 0, "tag does not fit in the alignment bits");
+        self.data.fetch_or(tag, order) as *mut T
+    }
+
+    /// Remove the given tag bits, leaving the pointer and other tag bits untouched.
+    /// Returns the previous value.
+    pub fn clear_tag(&self, tag: usize, order: Ordering) -> *mut T {
+        debug_assert!(tag & !Self::TAG_MASK == 0, "tag does not fit in the alignment bits");
+        self.data.fetch_and(!tag, order) as *mut T
+    }
+
+    /// Repeatedly apply `f` to the current value and try to store the result, until the store
+    /// succeeds or `f` returns None. Returns the previous value, inside Ok if a new value was stored
+    /// and Err otherwise.
+    pub fn fetch_update<F>(&self, success: Ordering, failure: Ordering, mut f: F) -> Result<*mut T, *mut T>
+    where F: FnMut(*mut T) -> Option<*mut T>
+    {
+        let mut current = self.load(failure);
+        while let Some(new) = f(current) {
+            match self.compare_exchange(current, new, success, failure) {
+                Ok(previous) => return Ok(previous),
+                Err(actual) => current = actual
+            }
+        }
+        Err(current)
+    }
+
+    /// Get the tag of a tagged pointer.
+    pub fn tag(ptr: *mut T) -> usize {
+        ptr as usize & Self::TAG_MASK
+    }
+
+    /// Get the pointer of a tagged pointer, with the whole tag removed.
+    pub fn untagged(ptr: *mut T) -> *mut T {
+        (ptr as usize & !Self::TAG_MASK) as *mut T
+    }
+
+    /// Check whether any of the given tag bits are set on a tagged pointer.
+    pub fn has_tag(ptr: *mut T, tag: usize) -> bool {
+        ptr as usize & tag != 0
+    }
+
+    /// Add the given tag bits to a pointer.
+    pub fn with_tag(ptr: *mut T, tag: usize) -> *mut T {
+        debug_assert!(tag & !Self::TAG_MASK == 0, "tag does not fit in the alignment bits");
+        (ptr as usize | tag) as *mut T
+    }
+
+    /// Remove the given tag bits from a pointer.
+    pub fn without_tag(ptr: *mut T, tag: usize) -> *mut T {
+        (ptr as usize & !tag) as *mut T
+    }
+}
+
+impl<T> Default for TaggedAtomicPtr<T> {
+    fn default() -> Self {
+        TaggedAtomicPtr::new(ptr::null_mut())
+    }
+}
+
+impl<T> fmt::Debug for TaggedAtomicPtr<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ptr = self.load(Ordering::Relaxed);
+        write!(f, "TaggedAtomicPtr({:p}, tag: {:b})", Self::untagged(ptr), Self::tag(ptr))
+    }
+}
+
+mod tests {
+    #![allow(unused_imports)]
+    use super::TaggedAtomicPtr;
+    use std::sync::atomic::Ordering::{Acquire, AcqRel, Relaxed};
+    use std::ptr;
+
+    #[test]
+    fn test_tag_bits() {
+        assert_eq!(TaggedAtomicPtr::<u16>::TAG_MASK, 0x1);
+        assert_eq!(TaggedAtomicPtr::<u32>::TAG_BITS, 2);
+        assert_eq!(TaggedAtomicPtr::<u64>::TAG_MASK, 0x7);
+    }
+
+    #[test]
+    fn test_tags() {
+        let raw = Box::into_raw(Box::new(8u64));
+        let ptr: TaggedAtomicPtr<u64> = TaggedAtomicPtr::new(raw);
+        assert_eq!(ptr.load_tag(Acquire), 0);
+
+        assert!(ptr.compare_and_set_tag(raw, 0x1, AcqRel, Acquire).is_ok());
+        assert!(ptr.compare_and_set_tag(raw, 0x1, AcqRel, Acquire).is_err());
+        ptr.set_tag(0x4, AcqRel);
+        let tagged = ptr.load(Acquire);
+        assert_eq!(TaggedAtomicPtr::tag(tagged), 0x5);
+        assert!(TaggedAtomicPtr::has_tag(tagged, 0x4));
+        assert!(!TaggedAtomicPtr::has_tag(tagged, 0x2));
+        assert_eq!(TaggedAtomicPtr::untagged(tagged), raw);
+        assert_eq!(TaggedAtomicPtr::without_tag(tagged, 0x4), TaggedAtomicPtr::with_tag(raw, 0x1));
+
+        ptr.clear_tag(0x1, AcqRel);
+        assert_eq!(ptr.load_tag(Acquire), 0x4);
+        assert_eq!(ptr.fetch_update(AcqRel, Acquire, |current| if TaggedAtomicPtr::has_tag(current, 0x4) { None } else { Some(current) }),
+                   Err(TaggedAtomicPtr::with_tag(raw, 0x4)));
+
+        unsafe { Box::from_raw(TaggedAtomicPtr::untagged(ptr.load(Relaxed))) };
+        let empty: TaggedAtomicPtr<u64> = TaggedAtomicPtr::default();
+        assert_eq!(empty.load_non_null(Acquire), None);
+    }
+}
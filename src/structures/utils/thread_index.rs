@@ -0,0 +1,89 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::sync::Mutex;
+
+/// The registry of thread indices. Released indices are reused smallest first, so that
+/// the live indices stay as dense as possible.
+struct Registry {
+    free: BinaryHeap<Reverse<usize>>,
+    next: usize
+}
+
+static REGISTRY: Mutex<Registry> = Mutex::new(Registry { free: BinaryHeap::new(), next: 0 });
+
+/// Owns the index of a thread, returning it to the registry when the thread exits.
+struct ThreadIndex {
+    index: usize
+}
+
+impl ThreadIndex {
+    fn acquire() -> Self {
+        let mut registry = REGISTRY.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        let index = match registry.free.pop() {
+            Some(Reverse(index)) => index,
+            None => {
+                registry.next += 1;
+                registry.next - 1
+            }
+        };
+        ThreadIndex { index }
+    }
+}
+
+impl Drop for ThreadIndex {
+    fn drop(&mut self) {
+        let mut registry = REGISTRY.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        registry.free.push(Reverse(self.index));
+    }
+}
+
+thread_local! {
+    static INDEX: ThreadIndex = ThreadIndex::acquire();
+}
+
+/// Get the index of the current thread.
+///
+/// Indices are small integers, unique among the threads alive at any one time, so they can be used
+/// to index flat per-thread arrays. A thread is given the lowest free index the first time it calls
+/// this function, which takes a lock once, and every later call is a thread-local read. The index is
+/// released when the thread exits and may then be given to a new thread, so anything stored against
+/// an index must be cleared by its thread before it exits.
+pub fn current_thread_index() -> usize {
+    INDEX.with(|index| index.index)
+}
+
+mod tests {
+    #![allow(unused_imports)]
+    use super::current_thread_index;
+    use std::sync::{Arc, Barrier};
+    use std::collections::HashSet;
+    use std::thread;
+
+    #[test]
+    fn test_indices_unique_and_stable() {
+        let barrier = Arc::new(Barrier::new(8));
+        let mut waitvec: Vec<thread::JoinHandle<usize>> = Vec::new();
+        for _ in 0..8 {
+            let barrier_copy = barrier.clone();
+            waitvec.push(thread::spawn(move || {
+                let index = current_thread_index();
+                // Keep every thread alive until all have their index
+                barrier_copy.wait();
+                assert_eq!(current_thread_index(), index);
+                index
+            }));
+        }
+        let indices: HashSet<usize> = waitvec.into_iter().map(|handle| handle.join().unwrap()).collect();
+        assert_eq!(indices.len(), 8);
+    }
+
+    #[test]
+    fn test_indices_reused() {
+        let mut seen = HashSet::new();
+        // Only one of these threads is alive at a time, so they cannot all need a fresh index
+        for _ in 0..100 {
+            seen.insert(thread::spawn(current_thread_index).join().unwrap());
+        }
+        assert!(seen.len() < 100);
+    }
+}
@@ -0,0 +1,327 @@
+use std::marker::PhantomData;
+use std::fmt;
+use std::ptr;
+
+/// A pointer paired with a version counter, as loaded from an AtomicVersionedPtr.
+pub struct VersionedPtr<T> {
+    ptr: *mut T,
+    version: usize
+}
+
+impl<T> VersionedPtr<T> {
+    /// The pointer part.
+    pub fn ptr(&self) -> *mut T {
+        self.ptr
+    }
+
+    /// The version part. This is incremented by every successful compare and exchange.
+    pub fn version(&self) -> usize {
+        self.version
+    }
+}
+
+impl<T> Clone for VersionedPtr<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for VersionedPtr<T> {}
+
+impl<T> PartialEq for VersionedPtr<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.ptr == other.ptr && self.version == other.version
+    }
+}
+
+impl<T> fmt::Debug for VersionedPtr<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "VersionedPtr({:p}, version: {})", self.ptr, self.version)
+    }
+}
+
+/// An atomic pointer with a version counter, protecting against the [ABA problem](https://en.wikipedia.org/wiki/ABA_problem).
+///
+/// Every successful `compare_exchange` increments the version, so a compare and exchange made with
+/// a stale value fails even if the same address has been stored again in the meantime. This makes it
+/// possible to build structures that reuse their nodes without the HPBRManager. The version only
+/// protects the pointer itself: any memory read through it must stay allocated, for example by
+/// recycling nodes rather than freeing them.
+///
+/// On x86_64, the pointer and a full 64-bit version are updated together with `cmpxchg16b`,
+/// which is available on every x86_64 processor apart from the very first generation, and is
+/// detected at runtime. Without it, and on other platforms, the version is packed into the unused
+/// upper bits of a 64-bit word, leaving 16 bits of version on 64-bit platforms, and 32 bits on
+/// 32-bit platforms. All operations are sequentially consistent.
+/// # Examples
+/// ```
+/// let ptr: AtomicVersionedPtr<u8> = AtomicVersionedPtr::new(ptr::null_mut());
+/// let current = ptr.load();
+/// let new = Box::into_raw(Box::new(8));
+/// assert!(ptr.compare_exchange(current, new).is_ok());
+/// assert!(ptr.compare_exchange(current, new).is_err()); // The version has changed
+/// ```
+pub struct AtomicVersionedPtr<T> {
+    inner: imp::AtomicPair,
+    _marker: PhantomData<*mut T>
+}
+
+unsafe impl<T> Send for AtomicVersionedPtr<T> {}
+unsafe impl<T> Sync for AtomicVersionedPtr<T> {}
+
+impl<T> AtomicVersionedPtr<T> {
+    /// Create a new AtomicVersionedPtr holding the given pointer at version 0.
+    pub fn new(ptr: *mut T) -> Self {
+        AtomicVersionedPtr {
+            inner: imp::AtomicPair::new(ptr as usize, 0),
+            _marker: PhantomData
+        }
+    }
+
+    /// Load the current pointer and version together.
+    pub fn load(&self) -> VersionedPtr<T> {
+        let (ptr, version) = self.inner.load();
+        VersionedPtr {
+            ptr: ptr as *mut T,
+            version
+        }
+    }
+
+    /// Store `new` with the next version if the pointer and version are still `current`.
+    /// Returns the new value on success, and the actual value on failure.
+    pub fn compare_exchange(&self, current: VersionedPtr<T>, new: *mut T) -> Result<VersionedPtr<T>, VersionedPtr<T>> {
+        let new_version = imp::next_version(current.version);
+        match self.inner.compare_exchange((current.ptr as usize, current.version), (new as usize, new_version)) {
+            Ok(()) => Ok(VersionedPtr { ptr: new, version: new_version }),
+            Err((ptr, version)) => Err(VersionedPtr { ptr: ptr as *mut T, version })
+        }
+    }
+}
+
+impl<T> Default for AtomicVersionedPtr<T> {
+    fn default() -> Self {
+        AtomicVersionedPtr::new(ptr::null_mut())
+    }
+}
+
+impl<T> fmt::Debug for AtomicVersionedPtr<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "AtomicVersionedPtr({:?})", self.load())
+    }
+}
+
+/// The representation with the version packed into the upper bits of a 64-bit word.
+mod packed {
+    #[cfg(target_pointer_width = "64")]
+    const PTR_BITS: u32 = 48;
+    #[cfg(not(target_pointer_width = "64"))]
+    const PTR_BITS: u32 = 32;
+
+    const PTR_MASK: u64 = (1 << PTR_BITS) - 1;
+    const VERSION_MASK: u64 = (1 << (64 - PTR_BITS)) - 1;
+
+    pub fn next_version(version: usize) -> usize {
+        ((version as u64 + 1) & VERSION_MASK) as usize
+    }
+
+    pub fn pack(pair: (usize, usize)) -> u64 {
+        debug_assert!(pair.0 as u64 & !PTR_MASK == 0, "pointer does not fit in the packed representation");
+        (pair.0 as u64) | ((pair.1 as u64 & VERSION_MASK) << PTR_BITS)
+    }
+
+    pub fn unpack(packed: u64) -> (usize, usize) {
+        ((packed & PTR_MASK) as usize, (packed >> PTR_BITS) as usize)
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod imp {
+    use std::arch::asm;
+    use std::cell::UnsafeCell;
+    use std::sync::atomic::AtomicU64;
+    use std::sync::atomic::Ordering::SeqCst;
+    use super::packed;
+
+    /// A pointer and version stored side by side in 16 aligned bytes, updated with `cmpxchg16b`. Without
+    /// it, the pair is packed into the first 8 bytes instead.
+    #[repr(C, align(16))]
+    pub struct AtomicPair {
+        pair: UnsafeCell<[u64; 2]>
+    }
+
+    /// Whether the processor has `cmpxchg16b`. The standard library caches the answer after the first call.
+    fn has_cmpxchg16b() -> bool {
+        is_x86_feature_detected!("cmpxchg16b")
+    }
+
+    pub fn next_version(version: usize) -> usize {
+        if has_cmpxchg16b() {
+            version.wrapping_add(1)
+        } else {
+            packed::next_version(version)
+        }
+    }
+
+    impl AtomicPair {
+        pub fn new(ptr: usize, version: usize) -> Self {
+            let pair = if has_cmpxchg16b() {
+                [ptr as u64, version as u64]
+            } else {
+                [packed::pack((ptr, version)), 0]
+            };
+            AtomicPair {
+                pair: UnsafeCell::new(pair)
+            }
+        }
+
+        /// The first 8 bytes, holding the whole pair when it is packed.
+        fn packed(&self) -> &AtomicU64 {
+            // The pair is aligned to 16 bytes, and is only ever accessed atomically
+            unsafe { &*(self.pair.get() as *const AtomicU64) }
+        }
+
+        pub fn load(&self) -> (usize, usize) {
+            if !has_cmpxchg16b() {
+                return packed::unpack(self.packed().load(SeqCst))
+            }
+            // A compare and exchange of zero with zero never changes the value, but always reads it atomically
+            match self.compare_exchange((0, 0), (0, 0)) {
+                Ok(()) => (0, 0),
+                Err(pair) => pair
+            }
+        }
+
+        pub fn compare_exchange(&self, current: (usize, usize), new: (usize, usize)) -> Result<(), (usize, usize)> {
+            if !has_cmpxchg16b() {
+                return match self.packed().compare_exchange(packed::pack(current), packed::pack(new), SeqCst, SeqCst) {
+                    Ok(_) => Ok(()),
+                    Err(actual) => Err(packed::unpack(actual))
+                }
+            }
+            let dst = self.pair.get();
+            let previous_ptr: u64;
+            let previous_version: u64;
+            let success: u64;
+            unsafe {
+                // rbx is reserved by the compiler, so the low word of the new value is swapped into it
+                // for the duration of the instruction
+                asm!(
+                    "xchg {rbx_tmp}, rbx",
+                    "lock cmpxchg16b xmmword ptr [{dst}]",
+                    "sete cl",
+                    "mov rbx, {rbx_tmp}",
+                    dst = in(reg) dst,
+                    rbx_tmp = inout(reg) new.0 as u64 => _,
+                    inout("rcx") new.1 as u64 => success,
+                    inout("rax") current.0 as u64 => previous_ptr,
+                    inout("rdx") current.1 as u64 => previous_version,
+                    options(nostack)
+                );
+            }
+            if success & 0xff != 0 {
+                Ok(())
+            } else {
+                Err((previous_ptr as usize, previous_version as usize))
+            }
+        }
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+mod imp {
+    use std::sync::atomic::AtomicU64;
+    use std::sync::atomic::Ordering::SeqCst;
+    use super::packed::{pack, unpack};
+
+    pub use super::packed::next_version;
+
+    /// A pointer and version packed into one word: the pointer in the low bits, the version above it.
+    pub struct AtomicPair {
+        pair: AtomicU64
+    }
+
+    impl AtomicPair {
+        pub fn new(ptr: usize, version: usize) -> Self {
+            AtomicPair {
+                pair: AtomicU64::new(pack((ptr, version)))
+            }
+        }
+
+        pub fn load(&self) -> (usize, usize) {
+            unpack(self.pair.load(SeqCst))
+        }
+
+        pub fn compare_exchange(&self, current: (usize, usize), new: (usize, usize)) -> Result<(), (usize, usize)> {
+            match self.pair.compare_exchange(pack(current), pack(new), SeqCst, SeqCst) {
+                Ok(_) => Ok(()),
+                Err(actual) => Err(unpack(actual))
+            }
+        }
+    }
+}
+
+mod tests {
+    #![allow(unused_imports)]
+    use super::{AtomicVersionedPtr, packed};
+    use std::sync::Arc;
+    use std::thread;
+    use std::ptr;
+
+    #[test]
+    fn test_version_increments() {
+        let first = Box::into_raw(Box::new(1u64));
+        let second = Box::into_raw(Box::new(2u64));
+        let atomic: AtomicVersionedPtr<u64> = AtomicVersionedPtr::new(first);
+
+        let start = atomic.load();
+        assert_eq!(start.ptr(), first);
+        assert_eq!(start.version(), 0);
+
+        let swapped = atomic.compare_exchange(start, second).unwrap();
+        assert_eq!(swapped.version(), 1);
+        let back = atomic.compare_exchange(swapped, first).unwrap();
+        // Same pointer as at the start, but the stale value is still rejected
+        assert_eq!(back.ptr(), start.ptr());
+        assert_eq!(atomic.compare_exchange(start, second), Err(back));
+        assert_eq!(atomic.load(), back);
+
+        unsafe {
+            drop(Box::from_raw(first));
+            drop(Box::from_raw(second));
+        }
+    }
+
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn test_packed_versions_wrap() {
+        let ptr = 0x1234_5678usize;
+        let mut version = 0;
+        for _ in 0..(1 << 16) {
+            version = packed::next_version(version);
+            assert_eq!(packed::unpack(packed::pack((ptr, version))), (ptr, version));
+        }
+        // 16 bits of version wrap back around to 0
+        assert_eq!(version, 0);
+    }
+
+    #[test]
+    fn test_concurrent_increments() {
+        let atomic: Arc<AtomicVersionedPtr<u64>> = Arc::new(AtomicVersionedPtr::new(ptr::null_mut()));
+        let mut waitvec: Vec<thread::JoinHandle<()>> = Vec::new();
+        for _ in 0..8 {
+            let atomic_copy = atomic.clone();
+            waitvec.push(thread::spawn(move || {
+                for _ in 0..1000 {
+                    let mut current = atomic_copy.load();
+                    while let Err(actual) = atomic_copy.compare_exchange(current, ptr::null_mut()) {
+                        current = actual;
+                    }
+                }
+            }));
+        }
+        for handle in waitvec {
+            handle.join().unwrap();
+        }
+        assert_eq!(atomic.load().version(), 8000);
+    }
+}
@@ -0,0 +1,553 @@
+use memory::HPBRManager;
+use stats::Counters;
+#[cfg(feature = "stats")]
+use stats::Stats;
+use std::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize};
+use std::sync::atomic::Ordering::SeqCst;
+use std::hint;
+use std::ptr;
+use super::utils::current_thread_index;
+
+/// The dequeuer of a node which no dequeue has claimed.
+const NO_THREAD: usize = usize::MAX;
+
+/// A wait-free Kogan-Petrank queue.
+///
+/// This queue is an implementation of that described in [Wait-free queues with multiple enqueuers and
+/// dequeuers](https://dl.acm.org/citation.cfm?id=1941585). It is a linked list like the Michael-Scott
+/// queue, but every operation first publishes a descriptor in a per-thread state array, tagged with a
+/// phase number taken from a shared counter. Before finishing its own operation, a thread helps every
+/// pending operation with a phase no greater than its own, so later operations cannot overtake a
+/// pending one indefinitely, at the cost of being slower than the lock-free Queue when there is no
+/// contention. The helping scheme of the paper is wait-free, but here descriptors are protected with
+/// hazard pointers, and protecting one retries for as long as it keeps being replaced. That retry is
+/// not bounded, so this implementation only guarantees lock-freedom.
+///
+/// Both the nodes and the descriptors are reclaimed with the HPBRManager. A node's element belongs to
+/// whichever dequeue removed the node in front of it, so a node is only retired once that dequeue has
+/// taken the element and the dequeue which removed the node itself has finished with it.
+///
+/// Each operation claims one of the queue's slots in the state array for its duration. The number of
+/// slots is given to `new`, and should be the number of threads using the queue: with no more threads
+/// than slots, an operation never waits for a slot, while with more, an operation waits until one is
+/// released.
+///
+/// The queue can be used in a multithreaded context by wrapping it in an Arc.
+/// # Usage
+/// ```
+/// let queue: Arc<WaitFreeQueue<u8>> = Arc::new(WaitFreeQueue::new(8));
+/// for _ in 0..8 {
+///     let queue_clone = queue.clone();
+///     thread::spawn(move || {
+///         queue_clone.enqueue(8);
+///         queue_clone.dequeue();
+///     });
+/// }
+/// ```
+pub struct WaitFreeQueue<T: Send> {
+    head: AtomicPtr<Node<T>>,
+    tail: AtomicPtr<Node<T>>,
+    state: Vec<AtomicPtr<OpDesc<T>>>,
+    claimed: Vec<AtomicBool>,
+    phase: AtomicUsize,
+    high_water: AtomicUsize,
+    node_manager: HPBRManager<Node<T>>,
+    desc_manager: HPBRManager<OpDesc<T>>,
+    stats: Counters
+}
+
+unsafe impl<T: Send> Sync for WaitFreeQueue<T> {}
+unsafe impl<T: Send> Send for WaitFreeQueue<T> {}
+
+struct Node<T: Send> {
+    value: Option<T>,
+    next: AtomicPtr<Node<T>>,
+    enq_tid: usize,
+    deq_tid: AtomicUsize,
+    // Counts the element being taken and the node being unlinked: the node is retired when both are done
+    releases: AtomicUsize
+}
+
+/// The description of a thread's latest operation. Descriptors are never modified, only replaced.
+struct OpDesc<T: Send> {
+    phase: usize,
+    pending: bool,
+    enqueue: bool,
+    node: *mut Node<T>
+}
+
+unsafe impl<T: Send> Send for OpDesc<T> {}
+
+impl<T: Send> Node<T> {
+    fn new_as_pointer(value: Option<T>, enq_tid: usize, releases: usize) -> *mut Self {
+        Box::into_raw(Box::new(Node {
+            value,
+            next: AtomicPtr::new(ptr::null_mut()),
+            enq_tid,
+            deq_tid: AtomicUsize::new(NO_THREAD),
+            releases: AtomicUsize::new(releases)
+        }))
+    }
+}
+
+impl<T: Send> OpDesc<T> {
+    fn new_as_pointer(phase: usize, pending: bool, enqueue: bool, node: *mut Node<T>) -> *mut Self {
+        Box::into_raw(Box::new(OpDesc { phase, pending, enqueue, node }))
+    }
+}
+
+impl<T: Send> WaitFreeQueue<T> {
+    /// Create a new, empty queue with a slot for each of `max_threads` threads, which must be at least 1.
+    /// # Examples
+    /// ```
+    /// let queue: WaitFreeQueue<u8> = WaitFreeQueue::new(8);
+    /// ```
+    pub fn new(max_threads: usize) -> Self {
+        if max_threads == 0 {
+            panic!("max_threads must be at least 1!")
+        }
+        // The dummy node has no element to take
+        let dummy = Node::new_as_pointer(None, NO_THREAD, 1);
+        WaitFreeQueue {
+            head: AtomicPtr::new(dummy),
+            tail: AtomicPtr::new(dummy),
+            state: (0..max_threads).map(|_| AtomicPtr::new(ptr::null_mut())).collect(),
+            claimed: (0..max_threads).map(|_| AtomicBool::new(false)).collect(),
+            phase: AtomicUsize::new(0),
+            high_water: AtomicUsize::new(0),
+            node_manager: HPBRManager::new(100, 2),
+            desc_manager: HPBRManager::new(100, 2),
+            stats: Counters::new()
+        }
+    }
+
+    /// Add a new element to the back of the queue.
+    /// # Examples
+    /// ```
+    /// let queue: WaitFreeQueue<String> = WaitFreeQueue::new(8);
+    /// queue.enqueue("hello".to_owned());
+    /// ```
+    pub fn enqueue(&self, val: T) {
+        let tid = self.claim();
+        let phase = self.phase.fetch_add(1, SeqCst);
+        let node = Node::new_as_pointer(Some(val), tid, 0);
+        self.publish(tid, OpDesc::new_as_pointer(phase, true, true, node));
+        self.help(phase);
+        self.help_finish_enq();
+        self.unclaim(tid);
+    }
+
+    /// Take an element from the front of the queue, or return None if the queue is empty.
+    /// # Examples
+    /// ```
+    /// let queue: WaitFreeQueue<String> = WaitFreeQueue::new(8);
+    /// queue.enqueue("hello".to_owned());
+    /// assert_eq!(queue.dequeue(), Some("hello".to_owned()));
+    /// ```
+    pub fn dequeue(&self) -> Option<T> {
+        let tid = self.claim();
+        let phase = self.phase.fetch_add(1, SeqCst);
+        self.publish(tid, OpDesc::new_as_pointer(phase, true, false, ptr::null_mut()));
+        self.help(phase);
+        self.help_finish_deq();
+
+        let first = unsafe { (*self.load_desc(tid, 0)).node };
+        self.desc_manager.unprotect(0);
+        self.unclaim(tid);
+        if first.is_null() {
+            return None
+        }
+        unsafe {
+            // Neither node can be retired until this dequeue releases it
+            let next = (*first).next.load(SeqCst);
+            let val = (*next).value.take();
+            // Make sure the head has passed our node before it can be retired
+            let _ = self.head.compare_exchange(first, next, SeqCst, SeqCst);
+            self.release(next);
+            self.release(first);
+            val
+        }
+    }
+
+    /// Claim a slot for an operation, making sure helpers will look at it. A thread starts looking from its
+    /// thread index, so with no more threads than slots, its first choice is usually free. If every slot is
+    /// claimed, wait for one to be released.
+    fn claim(&self) -> usize {
+        let slots = self.claimed.len();
+        let start = current_thread_index() % slots;
+        loop {
+            for i in 0..slots {
+                let tid = (start + i) % slots;
+                if self.claimed[tid].compare_exchange(false, true, SeqCst, SeqCst).is_ok() {
+                    if self.high_water.load(SeqCst) < tid + 1 {
+                        self.high_water.fetch_max(tid + 1, SeqCst);
+                    }
+                    return tid
+                }
+            }
+            hint::spin_loop();
+        }
+    }
+
+    /// Release the slot of a finished operation. The descriptor left in it is no longer pending, and the
+    /// next operation to claim the slot replaces it, just as a thread's next operation would.
+    fn unclaim(&self, tid: usize) {
+        self.claimed[tid].store(false, SeqCst);
+    }
+
+    /// Replace the descriptor of our own thread. Whoever removes a descriptor from the state array retires it.
+    fn publish(&self, tid: usize, desc: *mut OpDesc<T>) {
+        let old = self.state[tid].swap(desc, SeqCst);
+        if !old.is_null() {
+            self.desc_manager.retire(old, 1);
+        }
+    }
+
+    /// Load and protect the descriptor of a thread.
+    fn load_desc(&self, tid: usize, hazard_num: usize) -> *mut OpDesc<T> {
+        loop {
+            let desc = self.state[tid].load(SeqCst);
+            self.desc_manager.protect(desc, hazard_num);
+            if ptr::eq(desc, self.state[tid].load(SeqCst)) {
+                return desc
+            }
+        }
+    }
+
+    /// Replace a thread's descriptor, which must be protected, retiring it on success.
+    fn replace_desc(&self, tid: usize, current: *mut OpDesc<T>, new: *mut OpDesc<T>) -> bool {
+        match self.state[tid].compare_exchange(current, new, SeqCst, SeqCst) {
+            Ok(_) => {
+                self.desc_manager.retire(current, 1);
+                true
+            },
+            Err(_) => {
+                self.stats.cas_failure();
+                unsafe { drop(Box::from_raw(new)) };
+                false
+            }
+        }
+    }
+
+    fn is_still_pending(&self, tid: usize, phase: usize) -> bool {
+        let desc = self.load_desc(tid, 0);
+        let pending = unsafe { (*desc).pending && (*desc).phase <= phase };
+        self.desc_manager.unprotect(0);
+        pending
+    }
+
+    fn help(&self, phase: usize) {
+        for tid in 0..self.high_water.load(SeqCst) {
+            let desc = self.load_desc(tid, 0);
+            if desc.is_null() {
+                continue;
+            }
+            let (pending, enqueue, desc_phase) = unsafe { ((*desc).pending, (*desc).enqueue, (*desc).phase) };
+            self.desc_manager.unprotect(0);
+            if pending && desc_phase <= phase {
+                if enqueue {
+                    self.help_enq(tid, phase);
+                } else {
+                    self.help_deq(tid, phase);
+                }
+            }
+        }
+    }
+
+    fn help_enq(&self, tid: usize, phase: usize) {
+        while self.is_still_pending(tid, phase) {
+            let last = self.tail.load(SeqCst);
+            self.node_manager.protect(last, 0);
+            if !ptr::eq(last, self.tail.load(SeqCst)) {
+                continue;
+            }
+            let next = unsafe { (*last).next.load(SeqCst) };
+            if next.is_null() {
+                let desc = self.load_desc(tid, 1);
+                let (pending, node) = unsafe { ((*desc).pending && (*desc).phase <= phase, (*desc).node) };
+                self.desc_manager.unprotect(1);
+                if pending {
+                    // The node is only compared, never read: if the operation has finished, the CAS fails
+                    match unsafe { (*last).next.compare_exchange(ptr::null_mut(), node, SeqCst, SeqCst) } {
+                        Ok(_) => {
+                            self.help_finish_enq();
+                            return
+                        },
+                        Err(_) => self.stats.cas_failure()
+                    }
+                }
+            } else {
+                self.help_finish_enq();
+            }
+        }
+    }
+
+    fn help_finish_enq(&self) {
+        let last = self.tail.load(SeqCst);
+        self.node_manager.protect(last, 0);
+        if !ptr::eq(last, self.tail.load(SeqCst)) {
+            return
+        }
+        let next = unsafe { (*last).next.load(SeqCst) };
+        if next.is_null() {
+            return
+        }
+        // The node after the tail cannot have been dequeued while the tail is unchanged
+        self.node_manager.protect(next, 1);
+        if !ptr::eq(last, self.tail.load(SeqCst)) {
+            return
+        }
+        let tid = unsafe { (*next).enq_tid };
+        let desc = self.load_desc(tid, 1);
+        unsafe {
+            if ptr::eq(last, self.tail.load(SeqCst)) && ptr::eq((*desc).node, next) {
+                let new_desc = OpDesc::new_as_pointer((*desc).phase, false, true, next);
+                self.replace_desc(tid, desc, new_desc);
+                let _ = self.tail.compare_exchange(last, next, SeqCst, SeqCst);
+            }
+        }
+        self.desc_manager.unprotect(1);
+    }
+
+    fn help_deq(&self, tid: usize, phase: usize) {
+        while self.is_still_pending(tid, phase) {
+            let first = self.head.load(SeqCst);
+            self.node_manager.protect(first, 0);
+            if !ptr::eq(first, self.head.load(SeqCst)) {
+                continue;
+            }
+            let last = self.tail.load(SeqCst);
+            let next = unsafe { (*first).next.load(SeqCst) };
+            if !ptr::eq(first, self.head.load(SeqCst)) {
+                continue;
+            }
+            if ptr::eq(first, last) {
+                if next.is_null() {
+                    // The queue is empty: complete the dequeue with no node
+                    let desc = self.load_desc(tid, 1);
+                    unsafe {
+                        if ptr::eq(last, self.tail.load(SeqCst)) && (*desc).pending && (*desc).phase <= phase {
+                            let new_desc = OpDesc::new_as_pointer((*desc).phase, false, false, ptr::null_mut());
+                            self.replace_desc(tid, desc, new_desc);
+                        }
+                    }
+                    self.desc_manager.unprotect(1);
+                } else {
+                    // The tail is lagging behind
+                    self.help_finish_enq();
+                }
+            } else {
+                let desc = self.load_desc(tid, 1);
+                let (pending, node, desc_phase) = unsafe { ((*desc).pending && (*desc).phase <= phase, (*desc).node, (*desc).phase) };
+                if !pending {
+                    self.desc_manager.unprotect(1);
+                    break;
+                }
+                if ptr::eq(first, self.head.load(SeqCst)) && !ptr::eq(node, first) {
+                    // Record which node the dequeue is trying to remove
+                    let new_desc = OpDesc::new_as_pointer(desc_phase, true, false, first);
+                    if !self.replace_desc(tid, desc, new_desc) {
+                        self.desc_manager.unprotect(1);
+                        continue;
+                    }
+                }
+                self.desc_manager.unprotect(1);
+                unsafe {
+                    let _ = (*first).deq_tid.compare_exchange(NO_THREAD, tid, SeqCst, SeqCst);
+                }
+                self.help_finish_deq();
+            }
+        }
+    }
+
+    fn help_finish_deq(&self) {
+        let first = self.head.load(SeqCst);
+        self.node_manager.protect(first, 0);
+        if !ptr::eq(first, self.head.load(SeqCst)) {
+            return
+        }
+        let next = unsafe { (*first).next.load(SeqCst) };
+        let tid = unsafe { (*first).deq_tid.load(SeqCst) };
+        if tid == NO_THREAD {
+            return
+        }
+        let desc = self.load_desc(tid, 1);
+        unsafe {
+            if ptr::eq(first, self.head.load(SeqCst)) && !next.is_null() {
+                let new_desc = OpDesc::new_as_pointer((*desc).phase, false, false, (*desc).node);
+                self.replace_desc(tid, desc, new_desc);
+                let _ = self.head.compare_exchange(first, next, SeqCst, SeqCst);
+            }
+        }
+        self.desc_manager.unprotect(1);
+    }
+
+    /// Release one of the two claims on a node, retiring it once both are released.
+    fn release(&self, node: *mut Node<T>) {
+        if unsafe { (*node).releases.fetch_add(1, SeqCst) } == 1 {
+            self.node_manager.retire(node, 0);
+        }
+    }
+
+    /// Get a snapshot of the contention and reclamation counters of the queue.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> Stats {
+        self.stats.snapshot() + self.node_manager.stats()
+    }
+}
+
+impl<T: Send> Drop for WaitFreeQueue<T> {
+    fn drop(&mut self) {
+        let mut current = self.head.load(SeqCst);
+        while !current.is_null() {
+            unsafe {
+                let next = (*current).next.load(SeqCst);
+                drop(Box::from_raw(current));
+                current = next;
+            }
+        }
+        for slot in &self.state {
+            let desc = slot.load(SeqCst);
+            if !desc.is_null() {
+                unsafe { drop(Box::from_raw(desc)) };
+            }
+        }
+    }
+}
+
+mod tests {
+    #![allow(unused_imports)]
+    extern crate im;
+    use self::im::Vector;
+
+    use rand::{thread_rng, Rng};
+
+    use super::WaitFreeQueue;
+    use super::super::super::testing::{DropCounter, Dropper};
+    use super::super::super::testing::linearizability_tester::{LinearizabilityTester, LinearizabilityResult, ThreadLog};
+
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    #[test]
+    fn test_single_threaded() {
+        let queue: WaitFreeQueue<u8> = WaitFreeQueue::new(1);
+        assert_eq!(queue.dequeue(), None);
+        for i in 0..10 {
+            queue.enqueue(i);
+        }
+        for i in 0..10 {
+            assert_eq!(queue.dequeue(), Some(i));
+        }
+        assert_eq!(queue.dequeue(), None);
+    }
+
+    #[test]
+    fn test_multithreaded() {
+        let queue: Arc<WaitFreeQueue<usize>> = Arc::new(WaitFreeQueue::new(16));
+        let mut waitvec: Vec<thread::JoinHandle<usize>> = Vec::new();
+        for _ in 0..8 {
+            let queue_copy = queue.clone();
+            waitvec.push(thread::spawn(move || {
+                for i in 0..5000 {
+                    queue_copy.enqueue(i);
+                }
+                0
+            }));
+            let queue_copy = queue.clone();
+            waitvec.push(thread::spawn(move || {
+                let mut sum = 0;
+                for _ in 0..5000 {
+                    loop {
+                        if let Some(val) = queue_copy.dequeue() {
+                            sum += val;
+                            break;
+                        }
+                    }
+                }
+                sum
+            }));
+        }
+        let sum: usize = waitvec.into_iter().map(|handle| handle.join().unwrap()).sum();
+        assert_eq!(sum, 8 * (0..5000).sum::<usize>());
+        assert_eq!(queue.dequeue(), None);
+    }
+
+    #[test]
+    fn test_more_threads_than_slots() {
+        let queue: Arc<WaitFreeQueue<usize>> = Arc::new(WaitFreeQueue::new(2));
+        let mut waitvec: Vec<thread::JoinHandle<usize>> = Vec::new();
+        for _ in 0..8 {
+            let queue_copy = queue.clone();
+            waitvec.push(thread::spawn(move || {
+                let mut sum = 0;
+                for i in 0..1000 {
+                    queue_copy.enqueue(i);
+                    sum += queue_copy.dequeue().unwrap();
+                }
+                sum
+            }));
+        }
+        let sum: usize = waitvec.into_iter().map(|handle| handle.join().unwrap()).sum();
+        assert_eq!(sum, 8 * (0..1000).sum::<usize>());
+        assert_eq!(queue.dequeue(), None);
+    }
+
+    #[test]
+    fn test_values_dropped_once() {
+        let drops = DropCounter::new();
+        let queue: WaitFreeQueue<Dropper> = WaitFreeQueue::new(1);
+        for _ in 0..5 {
+            queue.enqueue(drops.dropper());
+        }
+        drop(queue.dequeue());
+        drop(queue.dequeue());
+        assert_eq!(drops.drops(), 2);
+        drop(queue);
+        assert_eq!(drops.drops(), 5);
+    }
+
+    #[test]
+    fn test_linearizable() {
+        let queue: WaitFreeQueue<usize> = WaitFreeQueue::new(8);
+        let sequential: Vector<usize> = Vector::new();
+        let mut linearizer: LinearizabilityTester<WaitFreeQueue<usize>, Vector<usize>, usize>
+                = LinearizabilityTester::new(8, 1000000, queue, sequential);
+
+        fn sequential_dequeue(queue: &Vector<usize>, _val: Option<usize>) -> (Vector<usize>, Option<usize>) {
+            match queue.pop_front() {
+                Some((arc, vec)) => {
+                    let res = *arc;
+                    (vec, Some(res))
+                },
+                None => (Vector::new(), None)
+            }
+        }
+
+        fn sequential_enqueue(queue: &Vector<usize>, val: Option<usize>) -> (Vector<usize>, Option<usize>) {
+            (queue.push_back(val.unwrap()), None)
+        }
+
+        fn worker(id: usize, log: &mut ThreadLog<WaitFreeQueue<usize>, Vector<usize>, usize>) {
+            for _ in 0..1000 {
+                let rand = thread_rng().gen_range(0, 101);
+                if rand < 30 {
+                    let val = thread_rng().gen_range(0, 122222);
+                    log.log_val(id, WaitFreeQueue::enqueue, val, format!("enqueue: {}", val), sequential_enqueue);
+                } else {
+                    log.log(id, WaitFreeQueue::dequeue, "dequeue".to_owned(), sequential_dequeue)
+                }
+            }
+        }
+
+        let result = linearizer.run(worker);
+
+        println!("{:?}", result);
+
+        match result {
+            LinearizabilityResult::Success => assert!(true),
+            _ => assert!(false)
+        }
+    }
+}
@@ -0,0 +1,42 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Counts how many of the Droppers it has made have been dropped, for checking that a structure
+/// drops every element it holds exactly once.
+/// # Example
+/// ```
+/// let drops = DropCounter::new();
+/// let queue: Queue<Dropper> = Queue::new();
+/// queue.enqueue(drops.dropper()).unwrap();
+/// drop(queue);
+/// assert_eq!(drops.drops(), 1);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct DropCounter(Arc<AtomicUsize>);
+
+/// A value whose drop is counted by the DropCounter which made it.
+#[derive(Debug)]
+pub struct Dropper(Arc<AtomicUsize>);
+
+impl DropCounter {
+    /// Create a new counter, with no drops counted.
+    pub fn new() -> Self {
+        DropCounter::default()
+    }
+
+    /// Make a new value whose drop is counted by this counter.
+    pub fn dropper(&self) -> Dropper {
+        Dropper(self.0.clone())
+    }
+
+    /// Get the number of values made by this counter which have been dropped so far.
+    pub fn drops(&self) -> usize {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for Dropper {
+    fn drop(&mut self) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+}
@@ -55,7 +55,9 @@
 //! ```
 
 pub use self::linearizability_tester::{LinearizabilityTester, LinearizabilityResult, ThreadLog};
+pub use self::drop_counter::{DropCounter, Dropper};
 
 pub mod linearizability_tester;
 mod time_stamped;
-mod automaton;
\ No newline at end of file
+mod automaton;
+mod drop_counter;
\ No newline at end of file